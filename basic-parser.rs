@@ -25,149 +25,9456 @@ now i have a tokens vector
 
  */
 
-use std::io;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::env;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
- #[derive(Debug)]
-enum Expression {
-    Val(i32), //ma opresc cand am valoare numar
-    Add(Box<Expression>, Box<Expression>), //o operatie se face intre 2 chestii 
-    Sub(Box<Expression>, Box<Expression>),
-    Mul(Box<Expression>, Box<Expression>),
-    Div(Box<Expression>, Box<Expression>),
+// [start, end) in bytes, in the original input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Span {
+    start: usize,
+    end: usize,
 }
 
-impl Expression {
-    fn eval(&self) -> i32 {
+impl Span {
+    fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    // the smallest span that covers both
+    fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Error {
+    InvalidCharacter(char, Span),
+    UnexpectedToken(String, &'static str, Span), //ce am gasit, ce asteptam
+    TrailingInput(String, Span),
+    UnmatchedParen(char, Span),
+    TooDeep(Span), // more than MAX_DEPTH levels, where it went over
+    DivisionByZero(Span),
+    Overflow(Span),
+    UnknownVariable(String, Span),
+    UnknownFunction(String, Span),
+    UnknownOperator(String, Span), // a custom operator the env doesn't have (any more)
+    WrongArity(String, Arity, usize, Span), //functia, cate vrea, cate a primit
+    RecursionLimit(String, Span),
+    InvalidArgument(String, Span), // sqrt(-1), gcd(1.5, 2)
+    TypeMismatch(&'static str, &'static str, Span), // what was expected, what it got: true + 1
+    MissingOperand(String, Span), // postfix "1 +": '+' has nothing to take off the stack
+    ExtraOperand(usize, Span),    // postfix "1 2": two values left at the end
+    TooComplex(usize, Span),      // needs a deeper stack than the VM has
+    NotDifferentiable(String, Span), // d/dx of x > 1, of gcd(x, 4), of x!
+    NotCompilable(String, Span),     // :asm of 2.5 * x, of gcd(x, 4), of x!
+    InvalidDocument(String, Span),   // a saved tree with a missing field, a newer version
+    Unsupported(String, &'static str, Span), // ce operatie, pt ce Scalar: '^' for complex
+    Unparsed(Span), // an Error node, the diagnostic is in parse_file's list
+}
+
+impl Error {
+    fn span(&self) -> Span {
         match self {
-            Expression::Val(number) => *number,
-            Expression::Add(left, right) => left.eval() + right.eval(),
-            Expression::Sub(left, right) => left.eval() - right.eval(),
-            Expression::Mul(left,right) => left.eval() * right.eval(),
-            Expression::Div(left, right) => left.eval() /right.eval(),
+            Error::InvalidCharacter(_, span)
+            | Error::UnexpectedToken(_, _, span)
+            | Error::TrailingInput(_, span)
+            | Error::UnmatchedParen(_, span)
+            | Error::TooDeep(span)
+            | Error::DivisionByZero(span)
+            | Error::Overflow(span)
+            | Error::UnknownVariable(_, span)
+            | Error::UnknownFunction(_, span)
+            | Error::UnknownOperator(_, span)
+            | Error::WrongArity(_, _, _, span)
+            | Error::RecursionLimit(_, span)
+            | Error::InvalidArgument(_, span)
+            | Error::TypeMismatch(_, _, span)
+            | Error::MissingOperand(_, span)
+            | Error::ExtraOperand(_, span)
+            | Error::TooComplex(_, span)
+            | Error::NotDifferentiable(_, span)
+            | Error::NotCompilable(_, span)
+            | Error::InvalidDocument(_, span)
+            | Error::Unsupported(_, _, span)
+            | Error::Unparsed(span) => *span,
         }
     }
+
+    // the offending line with carets under the span, like rustc does:
+    //   error: division by zero
+    //   --> 1:5
+    //     |
+    //   1 | 6 / (2 - 2)
+    //     |     ^^^^^^^
+    fn render(&self, source: &str) -> String {
+        let span = self.span();
+        let start = span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+        let line = &source[line_start..line_end];
+        let line_no = source[..line_start].matches('\n').count() + 1;
+
+        let column = source[line_start..start].chars().count();
+        let end = span.end.clamp(start, line_end);
+        let width = source[start..end].chars().count().max(1);
+
+        let gutter = " ".repeat(line_no.to_string().len());
+        format!(
+            "error: {}\n{}--> {}:{}\n{} |\n{} | {}\n{} | {}{}",
+            self,
+            gutter,
+            line_no,
+            column + 1,
+            gutter,
+            line_no,
+            line,
+            gutter,
+            " ".repeat(column),
+            "^".repeat(width)
+        )
+    }
 }
-#[derive(Debug)]
-struct Parser {
-    token_vector: Vec<String>,
-    index: usize,
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidCharacter(ch, _) => write!(f, "invalid character '{}'", ch),
+            Error::UnexpectedToken(found, expected, _) => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+            Error::TrailingInput(found, _) => write!(f, "unexpected {} after expression", found),
+            Error::UnmatchedParen(paren, _) => write!(f, "unmatched '{}'", paren),
+            Error::TooDeep(_) => write!(f, "nesting too deep, the limit is {} levels", MAX_DEPTH),
+            Error::DivisionByZero(_) => write!(f, "division by zero"),
+            Error::Overflow(_) => write!(f, "arithmetic overflow"),
+            Error::UnknownVariable(name, _) => write!(f, "unknown variable '{}'", name),
+            Error::UnknownFunction(name, _) => write!(f, "unknown function '{}'", name),
+            Error::UnknownOperator(symbol, _) => write!(f, "unknown operator '{}'", symbol),
+            Error::WrongArity(name, arity, found, _) => {
+                write!(f, "'{}' takes {} but got {}", name, arity, found)
+            }
+            Error::RecursionLimit(name, _) => {
+                write!(f, "recursion limit reached while calling '{}'", name)
+            }
+            Error::InvalidArgument(message, _) => write!(f, "{}", message),
+            Error::TypeMismatch(expected, found, _) => {
+                write!(f, "type error: expected {}, found {}", expected, found)
+            }
+            Error::MissingOperand(what, _) => write!(f, "not enough operands for {}", what),
+            Error::ExtraOperand(count, _) => {
+                write!(f, "{} values left over, missing an operator", count)
+            }
+            Error::TooComplex(depth, _) => write!(
+                f,
+                "expression needs a stack of {} values, the VM only has {}",
+                depth, VM_STACK_SIZE
+            ),
+            Error::NotDifferentiable(what, _) => write!(f, "can't differentiate {}", what),
+            Error::NotCompilable(what, _) => write!(f, "can't compile {} for the cpu", what),
+            Error::InvalidDocument(what, _) => write!(f, "can't read the tree: {}", what),
+            Error::Unsupported(what, scalar, _) => {
+                write!(f, "{} isn't defined for {}", what, scalar)
+            }
+            Error::Unparsed(_) => write!(f, "can't evaluate a part that didn't parse"),
+        }
+    }
 }
 
-impl Parser {
-    fn new(expression: &str) -> Parser {
-        //separ tokenurile
-        let mut toks = Vec::new();
-        let mut num = String::new();
-        
-        for ch in expression.chars()  {
-            if ch.is_digit(10) {
-                num.push(ch);
+// arbitrary precision integers and exact fractions, just enough for the calculator
+mod bignum {
+    use std::cmp::Ordering;
+    use std::convert::TryFrom;
+    use std::fmt;
+
+    // sign + magnitude, the magnitude in base 2^32 limbs, least significant first
+    // zero is an empty magnitude and is never negative, so derived equality works
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub(crate) struct BigInt {
+        negative: bool,
+        mag: Vec<u32>,
+    }
+
+    fn trim(mag: &mut Vec<u32>) {
+        while mag.last() == Some(&0) {
+            mag.pop();
+        }
+    }
+
+    fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+        a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+    }
+
+    fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+        let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+        let mut out = Vec::with_capacity(long.len() + 1);
+        let mut carry = 0u64;
+        for (i, &limb) in long.iter().enumerate() {
+            let sum = limb as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+            out.push(sum as u32);
+            carry = sum >> 32;
+        }
+        if carry > 0 {
+            out.push(carry as u32);
+        }
+        out
+    }
+
+    // a - b, with a >= b
+    fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+        let mut out = Vec::with_capacity(a.len());
+        let mut borrow = 0i64;
+        for (i, &limb) in a.iter().enumerate() {
+            let mut diff = limb as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+            borrow = 0;
+            if diff < 0 {
+                diff += 1 << 32;
+                borrow = 1;
+            }
+            out.push(diff as u32);
+        }
+        trim(&mut out);
+        out
+    }
+
+    fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+        if a.is_empty() || b.is_empty() {
+            return Vec::new();
+        }
+        // schoolbook, ca la scoala
+        let mut out = vec![0u32; a.len() + b.len()];
+        for (i, &x) in a.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &y) in b.iter().enumerate() {
+                let cur = out[i + j] as u64 + x as u64 * y as u64 + carry;
+                out[i + j] = cur as u32;
+                carry = cur >> 32;
+            }
+            out[i + b.len()] = carry as u32;
+        }
+        trim(&mut out);
+        out
+    }
+
+    // divides by a single limb in place, returns the remainder
+    fn div_small(mag: &mut Vec<u32>, divisor: u32) -> u32 {
+        let mut rem = 0u64;
+        for limb in mag.iter_mut().rev() {
+            let cur = (rem << 32) | *limb as u64;
+            *limb = (cur / divisor as u64) as u32;
+            rem = cur % divisor as u64;
+        }
+        trim(mag);
+        rem as u32
+    }
+
+    // shifted left by less than a limb, one limb longer (the top one may be 0)
+    fn shl_bits(mag: &[u32], shift: u32) -> Vec<u32> {
+        let mut out = Vec::with_capacity(mag.len() + 1);
+        let mut carry = 0u32;
+        for &limb in mag {
+            let wide = (limb as u64) << shift;
+            out.push(wide as u32 | carry);
+            carry = (wide >> 32) as u32;
+        }
+        out.push(carry);
+        out
+    }
+
+    // long division a limb at a time (Knuth's algorithm D, TAOCP 4.3.1): with b shifted so its
+    // top bit is set, the limb guessed from the top limbs is at most 2 too big; checking the
+    // next limb of b catches almost every miss, and the rare one left is fixed by adding b back
+    fn div_rem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+        if cmp_mag(a, b) == Ordering::Less {
+            return (Vec::new(), a.to_vec());
+        }
+        if b.len() == 1 {
+            let mut q = a.to_vec();
+            let r = div_small(&mut q, b[0]);
+            let r = if r == 0 { Vec::new() } else { vec![r] };
+            return (q, r);
+        }
+
+        let shift = b[b.len() - 1].leading_zeros();
+        let mut v = shl_bits(b, shift);
+        v.pop(); // b's top limb had room for the shift
+        let mut u = shl_bits(a, shift);
+        let n = v.len();
+        let (v1, v2) = (v[n - 1] as u64, v[n - 2] as u64);
+        let mut q = vec![0u32; a.len() - n + 1];
+        for j in (0..q.len()).rev() {
+            let top = (u[j + n] as u64) << 32 | u[j + n - 1] as u64;
+            let (mut guess, mut rest) = (top / v1, top % v1);
+            while guess > u32::MAX as u64 || guess * v2 > (rest << 32 | u[j + n - 2] as u64) {
+                guess -= 1;
+                rest += v1;
+                if rest > u32::MAX as u64 {
+                    break;
+                }
+            }
+
+            // u[j..=j + n] -= guess * v
+            let (mut carry, mut borrow) = (0u64, 0i64);
+            for i in 0..n {
+                let product = guess * v[i] as u64 + carry;
+                carry = product >> 32;
+                let diff = u[i + j] as i64 - borrow - (product as u32) as i64;
+                u[i + j] = diff as u32;
+                borrow = (diff < 0) as i64;
+            }
+            let diff = u[j + n] as i64 - borrow - carry as i64;
+            u[j + n] = diff as u32;
+            if diff < 0 {
+                // one too big after all: add v back
+                guess -= 1;
+                let mut carry = 0u64;
+                for i in 0..n {
+                    let sum = u[i + j] as u64 + v[i] as u64 + carry;
+                    u[i + j] = sum as u32;
+                    carry = sum >> 32;
+                }
+                u[j + n] = u[j + n].wrapping_add(carry as u32);
+            }
+            q[j] = guess as u32;
+        }
+
+        // the remainder is what's left in u[..n] (u[n] is 0 by now), shifted back
+        let mut r: Vec<u32> = (0..n)
+            .map(|i| ((u[i] as u64 | (u[i + 1] as u64) << 32) >> shift) as u32)
+            .collect();
+        trim(&mut q);
+        trim(&mut r);
+        (q, r)
+    }
+
+    impl BigInt {
+        pub(crate) fn zero() -> BigInt {
+            BigInt { negative: false, mag: Vec::new() }
+        }
+
+        fn from_parts(negative: bool, mut mag: Vec<u32>) -> BigInt {
+            trim(&mut mag);
+            let negative = negative && !mag.is_empty();
+            BigInt { negative, mag }
+        }
+
+        pub(crate) fn from_i128(n: i128) -> BigInt {
+            let mut abs = n.unsigned_abs();
+            let mut mag = Vec::new();
+            while abs > 0 {
+                mag.push(abs as u32);
+                abs >>= 32;
+            }
+            BigInt::from_parts(n < 0, mag)
+        }
+
+        pub(crate) fn to_i128(&self) -> Option<i128> {
+            if self.mag.len() > 4 {
+                return None;
+            }
+            let abs = self.mag.iter().rev().fold(0u128, |acc, &limb| (acc << 32) | limb as u128);
+            if self.negative {
+                0i128.checked_sub_unsigned(abs)
             } else {
-                if !num.is_empty() {
-                    toks.push(num.clone()); //atentie la drop si context
-                    num.clear();
+                i128::try_from(abs).ok()
+            }
+        }
+
+        // decimal digits only, the lexer already checked
+        pub(crate) fn parse(digits: &str) -> BigInt {
+            let mut mag: Vec<u32> = Vec::new();
+            for chunk in digits.as_bytes().chunks(9) {
+                let value: u32 = std::str::from_utf8(chunk).unwrap().parse().unwrap();
+                let scale = 10u32.pow(chunk.len() as u32);
+                // mag = mag * 10^len + value
+                let mut carry = value as u64;
+                for limb in mag.iter_mut() {
+                    let cur = *limb as u64 * scale as u64 + carry;
+                    *limb = cur as u32;
+                    carry = cur >> 32;
                 }
-                if ch.is_whitespace() {
-                    continue;
+                if carry > 0 {
+                    mag.push(carry as u32);
                 }
-                if "+-/*".contains(ch) {
-                    toks.push(ch.to_string());
-                } else {
-                    //return Err(format!("Invalid character: '{}'", ch));
-                    println!("ups");
-                    continue;
+            }
+            BigInt::from_parts(false, mag)
+        }
+
+        pub(crate) fn is_zero(&self) -> bool {
+            self.mag.is_empty()
+        }
+
+        pub(crate) fn is_negative(&self) -> bool {
+            self.negative
+        }
+
+        pub(crate) fn bits(&self) -> u64 {
+            match self.mag.last() {
+                Some(top) => (self.mag.len() as u64 - 1) * 32 + (32 - top.leading_zeros() as u64),
+                None => 0,
+            }
+        }
+
+        // the low 32 bits in two's complement, what an i32 would have wrapped to
+        pub(crate) fn wrapping_i32(&self) -> i32 {
+            let low = *self.mag.first().unwrap_or(&0);
+            if self.negative {
+                low.wrapping_neg() as i32
+            } else {
+                low as i32
+            }
+        }
+
+        pub(crate) fn to_f64(&self) -> f64 {
+            let base = 2f64.powi(32);
+            let abs = self.mag.iter().rev().fold(0.0, |acc, &limb| acc * base + limb as f64);
+            if self.negative {
+                -abs
+            } else {
+                abs
+            }
+        }
+
+        pub(crate) fn neg(&self) -> BigInt {
+            BigInt::from_parts(!self.negative, self.mag.clone())
+        }
+
+        pub(crate) fn abs(&self) -> BigInt {
+            BigInt::from_parts(false, self.mag.clone())
+        }
+
+        pub(crate) fn add(&self, other: &BigInt) -> BigInt {
+            if self.negative == other.negative {
+                return BigInt::from_parts(self.negative, add_mag(&self.mag, &other.mag));
+            }
+            // semne diferite: scad magnitudinea mica din cea mare, semnul e al celei mari
+            match cmp_mag(&self.mag, &other.mag) {
+                Ordering::Less => {
+                    BigInt::from_parts(other.negative, sub_mag(&other.mag, &self.mag))
                 }
+                _ => BigInt::from_parts(self.negative, sub_mag(&self.mag, &other.mag)),
             }
         }
 
-        if !num.is_empty() {
-            toks.push(num);
+        pub(crate) fn sub(&self, other: &BigInt) -> BigInt {
+            self.add(&other.neg())
+        }
+
+        pub(crate) fn mul(&self, other: &BigInt) -> BigInt {
+            BigInt::from_parts(self.negative != other.negative, mul_mag(&self.mag, &other.mag))
+        }
+
+        // truncated towards zero like the i32 operators; other must not be zero
+        pub(crate) fn div_rem(&self, other: &BigInt) -> (BigInt, BigInt) {
+            let (q, r) = div_rem_mag(&self.mag, &other.mag);
+            (
+                BigInt::from_parts(self.negative != other.negative, q),
+                BigInt::from_parts(self.negative, r),
+            )
+        }
+
+        pub(crate) fn pow(&self, mut exp: u32) -> BigInt {
+            let mut base = self.clone();
+            let mut result = BigInt::from_i128(1);
+            while exp > 0 {
+                if exp & 1 == 1 {
+                    result = result.mul(&base);
+                }
+                exp >>= 1;
+                if exp > 0 {
+                    base = base.mul(&base);
+                }
+            }
+            result
+        }
+
+        pub(crate) fn gcd(&self, other: &BigInt) -> BigInt {
+            let (mut a, mut b) = (self.abs(), other.abs());
+            while !b.is_zero() {
+                let (_, r) = a.div_rem(&b);
+                (a, b) = (b, r);
+            }
+            a
+        }
+
+        fn shr(&self, bits: u64) -> BigInt {
+            let limbs = (bits / 32) as usize;
+            let shift = (bits % 32) as u32;
+            if limbs >= self.mag.len() {
+                return BigInt::zero();
+            }
+            let mut mag: Vec<u32> = self.mag[limbs..].to_vec();
+            if shift > 0 {
+                for i in 0..mag.len() {
+                    let high = mag.get(i + 1).map_or(0, |next| next << (32 - shift));
+                    mag[i] = (mag[i] >> shift) | high;
+                }
+            }
+            BigInt::from_parts(self.negative, mag)
+        }
+    }
+
+    impl Ord for BigInt {
+        fn cmp(&self, other: &BigInt) -> Ordering {
+            match (self.negative, other.negative) {
+                (false, true) => Ordering::Greater,
+                (true, false) => Ordering::Less,
+                (false, false) => cmp_mag(&self.mag, &other.mag),
+                (true, true) => cmp_mag(&other.mag, &self.mag),
+            }
+        }
+    }
+
+    impl PartialOrd for BigInt {
+        fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl fmt::Display for BigInt {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            if self.is_zero() {
+                return write!(f, "0");
+            }
+            // cate 9 cifre odata, de la coada
+            let mut mag = self.mag.clone();
+            let mut chunks = Vec::new();
+            while !mag.is_empty() {
+                chunks.push(div_small(&mut mag, 1_000_000_000));
+            }
+            if self.negative {
+                write!(f, "-")?;
+            }
+            write!(f, "{}", chunks.pop().unwrap())?;
+            for chunk in chunks.iter().rev() {
+                write!(f, "{:09}", chunk)?;
+            }
+            Ok(())
+        }
+    }
+
+    // num/den with den > 0 and no common factor, so equal values are equal structs
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub(crate) struct Rational {
+        num: BigInt,
+        den: BigInt,
+    }
+
+    impl Rational {
+        // den must not be zero
+        pub(crate) fn new(num: BigInt, den: BigInt) -> Rational {
+            let (num, den) = if den.is_negative() { (num.neg(), den.neg()) } else { (num, den) };
+            let g = num.gcd(&den);
+            if g == BigInt::from_i128(1) || g.is_zero() {
+                return Rational { num, den };
+            }
+            Rational {
+                num: num.div_rem(&g).0,
+                den: den.div_rem(&g).0,
+            }
+        }
+
+        // a decimal literal exactly, -1.5e-3 is -3/2000; None if it isn't one or if the power of
+        // ten is past max_exp (it gets multiplied out in full, 1e999999999 would never finish)
+        pub(crate) fn parse_decimal(text: &str, max_exp: u64) -> Option<Rational> {
+            let (negative, text) = match text.strip_prefix('-') {
+                Some(text) => (true, text),
+                None => (false, text),
+            };
+            let (mantissa, exp) = match text.find(['e', 'E']) {
+                Some(at) => (&text[..at], text[at + 1..].parse::<i64>().ok()?),
+                None => (text, 0),
+            };
+            let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+            let digits = format!("{}{}", whole, fraction);
+            let exp = exp.checked_sub(fraction.len() as i64)?;
+            if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            if exp.unsigned_abs() > max_exp {
+                return None;
+            }
+            let digits = BigInt::parse(&digits);
+            let power = BigInt::from_i128(10).pow(exp.unsigned_abs() as u32);
+            let exact = if exp >= 0 {
+                Rational::from_int(digits.mul(&power))
+            } else {
+                Rational::new(digits, power)
+            };
+            Some(if negative { exact.neg() } else { exact })
+        }
+
+        // the other way, 3/2000 is 15e-4; None if den has factors other than 2 and 5
+        pub(crate) fn to_decimal(&self) -> Option<String> {
+            let (two, five) = (BigInt::from_i128(2), BigInt::from_i128(5));
+            let mut rest = self.den.clone();
+            let mut count = |factor: &BigInt| {
+                let mut times = 0;
+                loop {
+                    let (q, r) = rest.div_rem(factor);
+                    if !r.is_zero() {
+                        return times;
+                    }
+                    rest = q;
+                    times += 1;
+                }
+            };
+            let (twos, fives) = (count(&two), count(&five));
+            if rest != BigInt::from_i128(1) {
+                return None;
+            }
+            let exp = twos.max(fives);
+            let num = self.num.mul(&two.pow(exp - twos)).mul(&five.pow(exp - fives));
+            let digits = num.to_string();
+            let mantissa = digits.trim_end_matches('0');
+            let zeros = (digits.len() - mantissa.len()) as i64;
+            Some(format!("{}e{}", mantissa, zeros - exp as i64))
+        }
+
+        pub(crate) fn from_int(n: BigInt) -> Rational {
+            Rational { num: n, den: BigInt::from_i128(1) }
+        }
+
+        pub(crate) fn numer(&self) -> &BigInt {
+            &self.num
+        }
+
+        pub(crate) fn is_integer(&self) -> bool {
+            self.den == BigInt::from_i128(1)
+        }
+
+        pub(crate) fn add(&self, other: &Rational) -> Rational {
+            let num = self.num.mul(&other.den).add(&other.num.mul(&self.den));
+            Rational::new(num, self.den.mul(&other.den))
+        }
+
+        pub(crate) fn sub(&self, other: &Rational) -> Rational {
+            self.add(&other.neg())
+        }
+
+        pub(crate) fn mul(&self, other: &Rational) -> Rational {
+            Rational::new(self.num.mul(&other.num), self.den.mul(&other.den))
+        }
+
+        // other must not be zero
+        pub(crate) fn div(&self, other: &Rational) -> Rational {
+            Rational::new(self.num.mul(&other.den), self.den.mul(&other.num))
+        }
+
+        pub(crate) fn neg(&self) -> Rational {
+            Rational { num: self.num.neg(), den: self.den.clone() }
+        }
+
+        pub(crate) fn abs(&self) -> Rational {
+            Rational { num: self.num.abs(), den: self.den.clone() }
+        }
+
+        pub(crate) fn recip(&self) -> Rational {
+            Rational::new(self.den.clone(), self.num.clone())
+        }
+
+        pub(crate) fn pow(&self, exp: u32) -> Rational {
+            Rational { num: self.num.pow(exp), den: self.den.pow(exp) }
+        }
+
+        pub(crate) fn floor(&self) -> BigInt {
+            let (q, r) = self.num.div_rem(&self.den);
+            if r.is_negative() {
+                q.sub(&BigInt::from_i128(1))
+            } else {
+                q
+            }
+        }
+
+        pub(crate) fn ceil(&self) -> BigInt {
+            self.neg().floor().neg()
+        }
+
+        // towards zero
+        pub(crate) fn trunc(&self) -> BigInt {
+            self.num.div_rem(&self.den).0
+        }
+
+        // how big the bigger of the two halves is
+        pub(crate) fn bits(&self) -> u64 {
+            self.num.bits().max(self.den.bits())
+        }
+
+        pub(crate) fn to_f64(&self) -> f64 {
+            // both sides shifted down so huge ones don't turn into inf / inf
+            let shift = self.num.bits().max(self.den.bits()).saturating_sub(1000);
+            self.num.shr(shift).to_f64() / self.den.shr(shift).to_f64()
+        }
+
+        // the simplest fraction that is the same f64, so 0.1 becomes 1/10 and not
+        // 3602879701896397/36028797018963968 (which is what 0.1 really is in binary)
+        pub(crate) fn from_f64(x: f64) -> Rational {
+            let bits = x.abs().to_bits();
+            let exponent = ((bits >> 52) & 0x7ff) as i64;
+            let mantissa = if exponent == 0 {
+                (bits & ((1 << 52) - 1)) << 1
+            } else {
+                (bits & ((1 << 52) - 1)) | (1 << 52)
+            };
+            let shift = exponent - 1075; // x = mantissa * 2^shift
+            let (two, mantissa) = (BigInt::from_i128(2), BigInt::from_i128(mantissa as i128));
+            let (mut n, mut d) = if shift >= 0 {
+                (mantissa.mul(&two.pow(shift as u32)), BigInt::from_i128(1))
+            } else {
+                (mantissa, two.pow((-shift) as u32))
+            };
+
+            // continued fraction of n/d, stopping at the first convergent that rounds to x
+            let (mut h1, mut h2) = (BigInt::from_i128(1), BigInt::zero());
+            let (mut k1, mut k2) = (BigInt::zero(), BigInt::from_i128(1));
+            let mut best = Rational::from_int(BigInt::zero());
+            while !d.is_zero() {
+                let (a, r) = n.div_rem(&d);
+                (n, d) = (d, r);
+                let h = a.mul(&h1).add(&h2);
+                let k = a.mul(&k1).add(&k2);
+                (h2, h1) = (h1, h.clone());
+                (k2, k1) = (k1, k.clone());
+                best = Rational::new(h, k);
+                if best.to_f64() == x.abs() {
+                    break;
+                }
+            }
+            if x < 0.0 {
+                best.neg()
+            } else {
+                best
+            }
+        }
+    }
+
+    impl Ord for Rational {
+        fn cmp(&self, other: &Rational) -> Ordering {
+            self.num.mul(&other.den).cmp(&other.num.mul(&self.den))
+        }
+    }
+
+    impl PartialOrd for Rational {
+        fn partial_cmp(&self, other: &Rational) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl fmt::Display for Rational {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            if self.is_integer() {
+                write!(f, "{}", self.num)
+            } else {
+                write!(f, "{}/{}", self.num, self.den)
+            }
         }
-    
-        Parser {
-        token_vector: toks,
-        index: 0,
     }
 }
-fn current_token(&self) -> Option<&String> {
-    self.token_vector.get(self.index)
+
+use bignum::{BigInt, Rational};
+
+// ints stay exact as long as both sides are ints, as soon as a float shows up everything is float
+// an integer that fits in an i32 is always an Int and a Ratio is never a whole number, so the
+// derived PartialEq is the same as comparing values
+#[derive(Debug, Clone, PartialEq)]
+enum Number {
+    Int(i32),
+    Wide(i64), // only in OverflowMode::Promote, and only for values that don't fit in an i32
+    Big(BigInt), // the bigint and rational backends, for what doesn't fit in an i32
+    Ratio(Rational), // only the rational backend makes these
+    Float(f64),
+}
+
+// what int / int means; floats always do real division
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum DivisionMode {
+    #[default]
+    True, // 7 / 2 = 3.5, dar 6 / 2 = 3 ramane int
+    Truncate, // 7 / 2 = 3, -7 / 2 = -3 (ca in C)
+    Floor, // -7 / 2 = -4 (ca in Python)
 }
 
-// Advance to the next token
-fn advance(&mut self) {
-    if self.index < self.token_vector.len() {
-        self.index += 1;
+impl DivisionMode {
+    const ALL: [DivisionMode; 3] =
+        [DivisionMode::True, DivisionMode::Truncate, DivisionMode::Floor];
+
+    fn from_name(name: &str) -> Option<DivisionMode> {
+        DivisionMode::ALL.iter().copied().find(|mode| mode.name() == name)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            DivisionMode::True => "true",
+            DivisionMode::Truncate => "trunc",
+            DivisionMode::Floor => "floor",
+        }
     }
 }
 
-fn parse_exp(&mut self) -> Expression {
-    self.parse_term()
+// what happens when an integer result doesn't fit in an i32
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum OverflowMode {
+    #[default]
+    Checked, // eroare
+    Wrapping, // i32::MAX + 1 = i32::MIN, ca in release fara verificari
+    Saturating, // se opreste la i32::MAX / i32::MIN
+    Promote, // continua in i64 (Number::Wide), eroare doar daca nici acolo nu incape
 }
 
-fn parse_term(&mut self) -> Expression {
-    let mut expr = self.parse_factor();
+impl OverflowMode {
+    const ALL: [OverflowMode; 4] = [
+        OverflowMode::Checked,
+        OverflowMode::Wrapping,
+        OverflowMode::Saturating,
+        OverflowMode::Promote,
+    ];
 
-        while let Some(op) = self.current_token().cloned() {
-            if op == "+" || op == "-" {
-                self.advance();
-                let right = self.parse_factor();
-                expr = if op == "+" {
-                    Expression::Add(Box::new(expr), Box::new(right))
-                } else {
-                    Expression::Sub(Box::new(expr), Box::new(right))
-                };
-            } else {
-                break;
-            }
+    fn from_name(name: &str) -> Option<OverflowMode> {
+        OverflowMode::ALL.iter().copied().find(|mode| mode.name() == name)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            OverflowMode::Checked => "checked",
+            OverflowMode::Wrapping => "wrap",
+            OverflowMode::Saturating => "saturate",
+            OverflowMode::Promote => "promote",
         }
-        expr
+    }
 }
 
-fn parse_factor(&mut self) -> Expression {
-    let mut expr = self.parse_number();
+// which numbers the arithmetic works with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Backend {
+    #[default]
+    Machine, // i32 and f64, the OverflowMode decides what happens past i32
+    BigInt, // integers never overflow (the OverflowMode is ignored), 1 / 3 is still a float
+    Rational, // also exact fractions: 1 / 3 stays 1/3 and 0.1 is read as 1/10
+}
 
-    while let Some(op) = self.current_token().cloned() {
-        //trebuie sa clonez pt ca nu pot folosi referinat mutabila si imutabila at the same time
-        if op == "*" || op == "/" {
-            self.advance();
-            let right = self.parse_number();
-            expr = if op == "*" {
-                Expression::Mul(Box::new(expr), Box::new(right))
-            } else {
-                Expression::Div(Box::new(expr), Box::new(right))
-            };
-        } else {
-            break;
+impl Backend {
+    const ALL: [Backend; 3] = [Backend::Machine, Backend::BigInt, Backend::Rational];
+
+    fn from_name(name: &str) -> Option<Backend> {
+        Backend::ALL.iter().copied().find(|backend| backend.name() == name)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Backend::Machine => "machine",
+            Backend::BigInt => "bigint",
+            Backend::Rational => "rational",
         }
     }
-    expr
 }
 
-fn parse_number(&mut self) -> Expression {
-    if let Some(num) = self.current_token() {
-        if let Ok(value) = num.parse::<i32>() {
-            self.advance();
-            return Expression::Val(value);
+// anything bigger is an overflow even for BigInt; pow(9, 9999999) would just hang otherwise
+const MAX_BIG_BITS: u64 = 1 << 16;
+
+#[derive(Debug, Clone, Copy)]
+struct EvalOptions {
+    division: DivisionMode,
+    overflow: OverflowMode,
+    backend: Backend,
+    max_depth: usize, //cate apeluri de functii user pot fi unul in altul
+}
+
+impl Default for EvalOptions {
+    fn default() -> EvalOptions {
+        EvalOptions {
+            division: DivisionMode::default(),
+            overflow: OverflowMode::default(),
+            backend: Backend::default(),
+            max_depth: 256,
+        }
+    }
+}
+
+impl EvalOptions {
+    // --div=floor on the command line, :set div floor in the REPL
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "div" => {
+                self.division = DivisionMode::from_name(value).ok_or_else(|| {
+                    format!("unknown division mode {} (true, trunc, floor)", value)
+                })?;
+            }
+            "overflow" => {
+                self.overflow = OverflowMode::from_name(value).ok_or_else(|| {
+                    format!("unknown overflow mode {} (checked, wrap, saturate, promote)", value)
+                })?;
+            }
+            "backend" => {
+                self.backend = Backend::from_name(value).ok_or_else(|| {
+                    format!("unknown backend {} (machine, bigint, rational)", value)
+                })?;
+            }
+            _ => return Err(format!("unknown setting {} (div, overflow, backend)", name)),
         }
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "div = {}\noverflow = {}\nbackend = {}",
+            self.division.name(),
+            self.overflow.name(),
+            self.backend.name()
+        )
     }
-    panic!("Expected a number!");
 }
 
+// fn f(x, y) = x*x + y
+// the body is behind an Rc so a call can hold on to it while the env is borrowed mutably
+#[derive(Debug, Clone)]
+struct Function {
+    params: Vec<String>,
+    body: Rc<Node>,
 }
-fn main() {
 
-    println!("input: ");
+// the symbol table: the global scope plus one scope per active function call
+#[derive(Debug, Clone)]
+struct Environment {
+    scopes: Vec<HashMap<String, Value>>,
+    functions: HashMap<String, Function>,
+    operators: OperatorTable,
+    options: EvalOptions,
+    depth: usize, //cate niveluri de Node::eval sunt in curs, peste toate apelurile
+}
 
-    let mut input = String::new();
-    io::stdin().read_line(&mut input).expect("failure input");
-    let input = input.trim(); //shadowing
+// a call can't start deeper than this: max_depth counts the calls, but a call with a deep body
+// takes a lot more stack than one with a small body, and a debug build needs about 7 KB a level
+const MAX_EVAL_DEPTH: usize = 4 * MAX_DEPTH;
 
-    let mut parser = Parser::new(&input);
-    println!("{:#?}", parser);
-    //let expression = 
-    let expression = parser.parse_exp();
-    let result = expression.eval();
+impl Environment {
+    fn new(options: EvalOptions) -> Environment {
+        Environment {
+            scopes: vec![HashMap::new()], //scope-ul global
+            functions: HashMap::new(),
+            operators: OperatorTable::standard(),
+            options,
+            depth: 0,
+        }
+    }
 
-    println!("Result: {}", result);
+    // a function body sees its own parameters and the globals, not the locals of whoever called it
+    fn get(&self, name: &str) -> Option<Value> {
+        let local = self.scopes.last().and_then(|scope| scope.get(name));
+        local.or_else(|| self.scopes[0].get(name)).cloned()
+    }
 
-}
\ No newline at end of file
+    // let always binds in the innermost scope (shadowing, nu suprascrie ce e mai afara)
+    fn set(&mut self, name: &str, value: Value) {
+        let scope = self.scopes.last_mut().expect("the global scope is never popped");
+        match scope.get_mut(name) {
+            Some(slot) => *slot = value, //fara alocare cand variabila exista deja
+            None => {
+                scope.insert(name.to_string(), value);
+            }
+        }
+    }
+
+    fn define(&mut self, name: &str, function: Function) {
+        self.functions.insert(name.to_string(), function);
+    }
+
+    // user functions first (so they can shadow a built-in), then the built-ins
+    // user functions take any value, the built-ins only numbers
+    fn call(&mut self, name: &str, args: &[Value], span: Span) -> Result<Value, Error> {
+        if let Some(function) = self.functions.get(name).cloned() {
+            Arity::Exactly(function.params.len()).check(name, args.len(), span)?;
+            if self.scopes.len() > self.options.max_depth || self.depth > MAX_EVAL_DEPTH {
+                return Err(Error::RecursionLimit(name.to_string(), span));
+            }
+
+            let scope = function.params.iter().cloned().zip(args.iter().cloned()).collect();
+            self.scopes.push(scope);
+            let result = function.body.eval(self);
+            self.scopes.pop(); //si cand a dat eroare
+            return result;
+        }
+
+        match BUILTINS.iter().find(|builtin| builtin.name == name) {
+            Some(builtin) => {
+                builtin.arity.check(name, args.len(), span)?;
+                let numbers = numbers(args, span)?;
+                (builtin.func)(&numbers, &self.options, span).map(Value::Num)
+            }
+            None => Err(Error::UnknownFunction(name.to_string(), span)),
+        }
+    }
+
+    // custom operators are looked up when they run, like functions
+    fn apply_operator(
+        &self,
+        symbol: &str,
+        fixity: Fixity,
+        args: &[Value],
+        span: Span,
+    ) -> Result<Value, Error> {
+        match self.operators.find(symbol, fixity).map(|def| &def.kind) {
+            Some(OpKind::Custom(func)) => func(&numbers(args, span)?, &self.options)
+                .map(Value::Num)
+                .map_err(|message| Error::InvalidArgument(message, span)),
+            _ => Err(Error::UnknownOperator(symbol.to_string(), span)),
+        }
+    }
+}
+
+// built-ins and custom operators work on numbers only
+fn numbers(args: &[Value], span: Span) -> Result<Vec<Number>, Error> {
+    args.iter().map(|arg| arg.clone().number(span)).collect()
+}
+
+// the arithmetic returns None on overflow, the caller knows the span
+// integer results are first computed exactly (in i128 when the operands are small, in BigInt
+// when they are not) and only then squeezed into a Number according to the EvalOptions
+impl Number {
+    fn to_f64(&self) -> f64 {
+        match self {
+            Number::Int(n) => *n as f64,
+            Number::Wide(n) => *n as f64,
+            Number::Big(n) => n.to_f64(),
+            Number::Ratio(r) => r.to_f64(),
+            Number::Float(x) => *x,
+        }
+    }
+
+    fn as_int(&self) -> Option<i128> {
+        match self {
+            Number::Int(n) => Some(*n as i128),
+            Number::Wide(n) => Some(*n as i128),
+            Number::Big(n) => n.to_i128(),
+            Number::Ratio(_) | Number::Float(_) => None,
+        }
+    }
+
+    fn to_big(&self) -> Option<BigInt> {
+        match self {
+            Number::Big(n) => Some(n.clone()),
+            _ => self.as_int().map(BigInt::from_i128),
+        }
+    }
+
+    // every Number except a Float is an exact fraction
+    fn to_rational(&self) -> Option<Rational> {
+        match self {
+            Number::Ratio(r) => Some(r.clone()),
+            _ => self.to_big().map(Rational::from_int),
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        match self {
+            Number::Int(n) => *n == 0,
+            Number::Wide(n) => *n == 0,
+            Number::Big(n) => n.is_zero(),
+            Number::Ratio(_) => false,
+            Number::Float(x) => *x == 0.0,
+        }
+    }
+
+    fn float(x: f64) -> Option<Number> {
+        // inf nu e un rezultat, e overflow
+        x.is_finite().then_some(Number::Float(x))
+    }
+
+    // an exact integer result, as the backend and the overflow mode want it
+    fn fit(exact: i128, options: &EvalOptions) -> Option<Number> {
+        if let Ok(n) = i32::try_from(exact) {
+            return Some(Number::Int(n));
+        }
+        if options.backend != Backend::Machine {
+            return Some(Number::Big(BigInt::from_i128(exact)));
+        }
+        match options.overflow {
+            OverflowMode::Checked => None,
+            OverflowMode::Wrapping => Some(Number::Int(exact as i32)), //taie bitii de sus
+            OverflowMode::Saturating => {
+                Some(Number::Int(if exact < 0 { i32::MIN } else { i32::MAX }))
+            }
+            OverflowMode::Promote => i64::try_from(exact).ok().map(Number::Wide),
+        }
+    }
+
+    // the same for results that may not even fit in an i128
+    fn fit_big(exact: BigInt, options: &EvalOptions) -> Option<Number> {
+        if let Some(n) = exact.to_i128() {
+            return Number::fit(n, options);
+        }
+        match (options.backend, options.overflow) {
+            (Backend::BigInt | Backend::Rational, _) => {
+                (exact.bits() <= MAX_BIG_BITS).then_some(Number::Big(exact))
+            }
+            (Backend::Machine, OverflowMode::Wrapping) => Some(Number::Int(exact.wrapping_i32())),
+            (Backend::Machine, OverflowMode::Saturating) => {
+                Some(Number::Int(if exact.is_negative() { i32::MIN } else { i32::MAX }))
+            }
+            (Backend::Machine, _) => None,
+        }
+    }
+
+    fn from_rational(exact: Rational, options: &EvalOptions) -> Option<Number> {
+        if exact.is_integer() {
+            return Number::fit_big(exact.numer().clone(), options);
+        }
+        (exact.bits() <= MAX_BIG_BITS).then_some(Number::Ratio(exact))
+    }
+
+    // a Ratio the lexer made out of 1e400 or 1e-400, written back the same way: as n/d it would
+    // read back as a division, and an f64 can't hold it (fractions that came from elsewhere can)
+    fn as_decimal(&self) -> Option<String> {
+        match self {
+            Number::Ratio(exact) if !exact.to_f64().is_normal() => exact.to_decimal(),
+            _ => None,
+        }
+    }
+
+    // a literal from the source, as the current backend reads it: the lexer makes a Big out of
+    // anything that doesn't fit in an i32, and a Ratio only out of decimals an f64 can't hold
+    fn literal(&self, options: &EvalOptions) -> Option<Number> {
+        match self {
+            Number::Big(n) => Number::fit_big(n.clone(), options),
+            Number::Float(x) if options.backend == Backend::Rational => {
+                Number::from_rational(Rational::from_f64(*x), options)
+            }
+            Number::Ratio(exact) if options.backend == Backend::Rational => {
+                Number::from_rational(exact.clone(), options)
+            }
+            // 1e-400 is 0.0 for the others, like any float that small, and 1e400 an overflow
+            Number::Ratio(exact) => {
+                Some(exact.to_f64()).filter(|x| x.is_finite()).map(Number::Float)
+            }
+            _ => Some(self.clone()),
+        }
+    }
+
+    fn arith(
+        &self,
+        other: &Number,
+        options: &EvalOptions,
+        int_op: fn(i128, i128) -> Option<i128>,
+        big_op: fn(&BigInt, &BigInt) -> BigInt,
+        ratio_op: fn(&Rational, &Rational) -> Rational,
+        float_op: fn(f64, f64) -> f64,
+    ) -> Option<Number> {
+        // i128 first, fara alocari, the BigInt only if that wasn't enough
+        if let (Some(a), Some(b)) = (self.as_int(), other.as_int()) {
+            if let Some(exact) = int_op(a, b) {
+                return Number::fit(exact, options);
+            }
+        }
+        if let (Some(a), Some(b)) = (self.to_big(), other.to_big()) {
+            return Number::fit_big(big_op(&a, &b), options);
+        }
+        match (self.to_rational(), other.to_rational()) {
+            (Some(a), Some(b)) => Number::from_rational(ratio_op(&a, &b), options),
+            _ => Number::float(float_op(self.to_f64(), other.to_f64())),
+        }
+    }
+
+    fn add(&self, other: &Number, options: &EvalOptions) -> Option<Number> {
+        self.arith(other, options, i128::checked_add, BigInt::add, Rational::add, |a, b| a + b)
+    }
+
+    fn sub(&self, other: &Number, options: &EvalOptions) -> Option<Number> {
+        self.arith(other, options, i128::checked_sub, BigInt::sub, Rational::sub, |a, b| a - b)
+    }
+
+    fn mul(&self, other: &Number, options: &EvalOptions) -> Option<Number> {
+        self.arith(other, options, i128::checked_mul, BigInt::mul, Rational::mul, |a, b| a * b)
+    }
+
+    // other must not be zero
+    // only int / int looks at the DivisionMode, a fraction is always divided exactly
+    fn div(&self, other: &Number, options: &EvalOptions) -> Option<Number> {
+        if let (Number::Int(a), Number::Int(b)) = (self, other) {
+            let (a, b) = (*a as i128, *b as i128); // i128, deci si i32::MIN / -1 e exact aici
+            let q = a / b;
+            let exact = match options.division {
+                DivisionMode::True if a % b != 0 => {
+                    if options.backend != Backend::Rational {
+                        return Number::float(a as f64 / b as f64);
+                    }
+                    let exact = Rational::new(BigInt::from_i128(a), BigInt::from_i128(b));
+                    return Number::from_rational(exact, options);
+                }
+                DivisionMode::True | DivisionMode::Truncate => q,
+                // trunchierea rotunjeste spre 0, corectez cand semnele difera si ramane rest
+                DivisionMode::Floor if a % b != 0 && (a < 0) != (b < 0) => q - 1,
+                DivisionMode::Floor => q,
+            };
+            return Number::fit(exact, options);
+        }
+
+        // everything else: the exact quotient first, then rounded the way the mode wants
+        let exact = match (self.to_rational(), other.to_rational()) {
+            (Some(a), Some(b)) => a.div(&b),
+            _ => return Number::float(self.to_f64() / other.to_f64()),
+        };
+        let ints = self.to_big().is_some() && other.to_big().is_some();
+        match options.division {
+            _ if exact.is_integer() => Number::from_rational(exact, options),
+            DivisionMode::Truncate if ints => Number::fit_big(exact.trunc(), options),
+            DivisionMode::Floor if ints => Number::fit_big(exact.floor(), options),
+            _ if options.backend == Backend::Rational || !ints => {
+                Number::from_rational(exact, options)
+            }
+            _ => Number::float(exact.to_f64()),
+        }
+    }
+
+    // rounded down whatever the DivisionMode, like Python; other must not be zero
+    fn floor_div(&self, other: &Number, options: &EvalOptions) -> Option<Number> {
+        let floor = EvalOptions { division: DivisionMode::Floor, ..*options };
+        match self.div(other, &floor)? {
+            Number::Float(x) => Some(Number::Float(x.floor())), // 7.5 // 2 = 3.0
+            Number::Ratio(r) => Number::fit_big(r.floor(), options),
+            int => Some(int),
+        }
+    }
+
+    // what's left after floor_div, so it has the sign of other; other must not be zero
+    fn rem(&self, other: &Number, options: &EvalOptions) -> Option<Number> {
+        if matches!(self, Number::Float(_)) || matches!(other, Number::Float(_)) {
+            let (x, y) = (self.to_f64(), other.to_f64());
+            let r = x % y; // are semnul lui x, il mut pe al lui y
+            return Number::float(if r != 0.0 && (r < 0.0) != (y < 0.0) { r + y } else { r });
+        }
+        if let (Some(a), Some(b)) = (self.as_int(), other.as_int()) {
+            if let Some(r) = a.checked_rem(b) {
+                return Number::fit(if r != 0 && (r < 0) != (b < 0) { r + b } else { r }, options);
+            }
+        }
+        let (a, b) = (self.to_rational()?, other.to_rational()?);
+        let q = Rational::from_int(a.div(&b).floor());
+        Number::from_rational(a.sub(&b.mul(&q)), options)
+    }
+
+    fn neg(&self, options: &EvalOptions) -> Option<Number> {
+        match self {
+            Number::Int(n) => Number::fit(-(*n as i128), options),
+            Number::Wide(n) => Number::fit(-(*n as i128), options),
+            Number::Big(n) => Number::fit_big(n.neg(), options),
+            Number::Ratio(r) => Some(Number::Ratio(r.neg())),
+            Number::Float(x) => Some(Number::Float(-x)),
+        }
+    }
+
+    fn abs(&self, options: &EvalOptions) -> Option<Number> {
+        match self {
+            Number::Int(n) => Number::fit((*n as i128).abs(), options),
+            Number::Wide(n) => Number::fit((*n as i128).abs(), options),
+            Number::Big(n) => Number::fit_big(n.abs(), options),
+            Number::Ratio(r) => Some(Number::Ratio(r.abs())),
+            Number::Float(x) => Some(Number::Float(x.abs())),
+        }
+    }
+
+    // int ^ non-negative int stays an int, a fraction ^ int stays a fraction (and so does
+    // int ^ negative int in the rational backend), everything else goes through powf
+    fn pow(&self, other: &Number, options: &EvalOptions) -> Option<Number> {
+        let exp = match (self, other.as_int()) {
+            (Number::Float(_), _) | (_, None) => None,
+            (Number::Ratio(_), Some(exp)) => Some(exp),
+            (_, Some(exp)) if exp >= 0 || options.backend == Backend::Rational => Some(exp),
+            _ => None,
+        };
+        let exp = match exp {
+            Some(exp) => exp,
+            None => return Number::float(self.to_f64().powf(other.to_f64())),
+        };
+
+        // 0, 1 and -1 stay small however big exp is, only its parity matters (0 ^ -n is caught
+        // in builtin_pow)
+        if let Some(base @ -1..=1) = self.as_int() {
+            let exact = match base {
+                0 if exp != 0 => 0,
+                -1 if exp % 2 != 0 => -1,
+                _ => 1,
+            };
+            return Number::fit(exact, options);
+        }
+        if let (Some(base), Ok(small_exp)) = (self.as_int(), u32::try_from(exp)) {
+            if let Some(exact) = base.checked_pow(small_exp) {
+                return Number::fit(exact, options);
+            }
+        }
+        if let (Backend::Machine, Some(base)) = (options.backend, self.as_int()) {
+            // too big even for i128; wrapping and saturating still have an answer
+            return match options.overflow {
+                OverflowMode::Wrapping => {
+                    // mod 2^32 an even base is 0 from the 32nd power on, and an odd one comes
+                    // back to 1 every 2^30 powers
+                    let exp = if base % 2 == 0 { exp.min(32) } else { exp % (1 << 30) };
+                    Some(Number::Int((base as i32).wrapping_pow(exp as u32)))
+                }
+                OverflowMode::Saturating => {
+                    let odd = exp % 2 != 0;
+                    Number::fit(if base < 0 && odd { i128::MIN } else { i128::MAX }, options)
+                }
+                OverflowMode::Checked | OverflowMode::Promote => None,
+            };
+        }
+
+        let base = self.to_rational()?;
+        let base = if exp < 0 { base.recip() } else { base }; // 0 ^ -n e prins in builtin_pow
+        let exp = exp.unsigned_abs();
+        if base.bits().saturating_mul(exp.min(u64::MAX as u128) as u64) > MAX_BIG_BITS {
+            return None; // check before computing, not after
+        }
+        Number::from_rational(base.pow(exp as u32), options)
+    }
+
+    // exact as long as neither is a Float
+    fn compare(&self, other: &Number) -> Option<Ordering> {
+        if let (Some(a), Some(b)) = (self.as_int(), other.as_int()) {
+            return Some(a.cmp(&b));
+        }
+        match (self.to_rational(), other.to_rational()) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            _ => self.to_f64().partial_cmp(&other.to_f64()),
+        }
+    }
+
+    // a float that holds a whole number in i32 range turns back into an Int
+    fn from_whole(x: f64) -> Number {
+        if x >= i32::MIN as f64 && x <= i32::MAX as f64 {
+            Number::Int(x as i32)
+        } else {
+            Number::Float(x)
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Number::Int(n) => write!(f, "{}", n),
+            Number::Wide(n) => write!(f, "{}", n),
+            Number::Big(n) => write!(f, "{}", n),
+            Number::Ratio(r) => write!(f, "{}", r), // 1/3
+            // Debug keeps the ".0" so 3.0 doesn't look like the int 3
+            Number::Float(x) => write!(f, "{:?}", x),
+        }
+    }
+}
+
+// what an expression evaluates to; a boolean is never silently a number (or the other way)
+// the number is a Number, except under :as (Value<i32>, Value<Complex>)
+#[derive(Debug, Clone, PartialEq)]
+enum Value<T = Number> {
+    Num(T),
+    Bool(bool), // x > 3, a && b
+}
+
+impl<T> Value<T> {
+    // for the error messages: expected a number, found a boolean
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Num(_) => "a number",
+            Value::Bool(_) => "a boolean",
+        }
+    }
+
+    // span is where the value came from, the error points there
+    fn number(self, span: Span) -> Result<T, Error> {
+        match self {
+            Value::Num(number) => Ok(number),
+            other => Err(Error::TypeMismatch("a number", other.type_name(), span)),
+        }
+    }
+
+    fn boolean(&self, span: Span) -> Result<bool, Error> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            other => Err(Error::TypeMismatch("a boolean", other.type_name(), span)),
+        }
+    }
+}
+
+impl From<Number> for Value {
+    fn from(number: Number) -> Value {
+        Value::Num(number)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Value<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Num(number) => write!(f, "{}", number),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+//--------------BUILT-IN FUNCTIONS---------------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Arity {
+    Exactly(usize),
+    AtLeast(usize), // min si max merg cu oricate
+}
+
+impl Arity {
+    fn check(self, name: &str, found: usize, span: Span) -> Result<(), Error> {
+        let ok = match self {
+            Arity::Exactly(n) => found == n,
+            Arity::AtLeast(n) => found >= n,
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(Error::WrongArity(name.to_string(), self, found, span))
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (prefix, n) = match self {
+            Arity::Exactly(n) => ("", n),
+            Arity::AtLeast(n) => ("at least ", n),
+        };
+        let plural = if *n == 1 { "" } else { "s" };
+        write!(f, "{}{} argument{}", prefix, n, plural)
+    }
+}
+
+struct Builtin {
+    name: &'static str,
+    arity: Arity,
+    func: fn(&[Number], &EvalOptions, Span) -> Result<Number, Error>, //arity e deja verificat
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin { name: "abs", arity: Arity::Exactly(1), func: builtin_abs },
+    Builtin { name: "min", arity: Arity::AtLeast(1), func: builtin_min },
+    Builtin { name: "max", arity: Arity::AtLeast(1), func: builtin_max },
+    Builtin { name: "pow", arity: Arity::Exactly(2), func: builtin_pow },
+    Builtin { name: "sqrt", arity: Arity::Exactly(1), func: builtin_sqrt },
+    Builtin { name: "gcd", arity: Arity::Exactly(2), func: builtin_gcd },
+    Builtin { name: "floor", arity: Arity::Exactly(1), func: builtin_floor },
+    Builtin { name: "ceil", arity: Arity::Exactly(1), func: builtin_ceil },
+    Builtin { name: "ln", arity: Arity::Exactly(1), func: builtin_ln },
+    Builtin { name: "exp", arity: Arity::Exactly(1), func: builtin_exp },
+];
+
+fn builtin_abs(args: &[Number], options: &EvalOptions, span: Span) -> Result<Number, Error> {
+    args[0].abs(options).ok_or(Error::Overflow(span))
+}
+
+// returns the argument itself, so min(1, 2.5) is still the int 1
+fn builtin_min(args: &[Number], _options: &EvalOptions, _span: Span) -> Result<Number, Error> {
+    let smaller = |a: &Number, b: &Number| b.compare(a) == Some(Ordering::Less);
+    let first = args.iter().reduce(|a, b| if smaller(a, b) { b } else { a });
+    Ok(first.expect("arity is at least 1").clone())
+}
+
+fn builtin_max(args: &[Number], _options: &EvalOptions, _span: Span) -> Result<Number, Error> {
+    let bigger = |a: &Number, b: &Number| b.compare(a) == Some(Ordering::Greater);
+    let first = args.iter().reduce(|a, b| if bigger(a, b) { b } else { a });
+    Ok(first.expect("arity is at least 1").clone())
+}
+
+// the same as 2 ^ 10
+fn builtin_pow(args: &[Number], options: &EvalOptions, span: Span) -> Result<Number, Error> {
+    BinOp::Pow.apply(&args[0], &args[1], options, span, span)
+}
+
+fn builtin_sqrt(args: &[Number], _options: &EvalOptions, span: Span) -> Result<Number, Error> {
+    let x = args[0].to_f64();
+    if x < 0.0 {
+        return Err(Error::InvalidArgument("sqrt of a negative number".to_string(), span));
+    }
+    Ok(Number::Float(x.sqrt()))
+}
+
+fn builtin_gcd(args: &[Number], options: &EvalOptions, span: Span) -> Result<Number, Error> {
+    if let (Some(mut a), Some(mut b)) = (args[0].as_int(), args[1].as_int()) {
+        (a, b) = (a.abs(), b.abs());
+        //euclid
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        // gcd(i32::MIN, 0) = 2^31, care nu incape in i32
+        return Number::fit(a, options).ok_or(Error::Overflow(span));
+    }
+    match (args[0].to_big(), args[1].to_big()) {
+        (Some(a), Some(b)) => Number::fit_big(a.gcd(&b), options).ok_or(Error::Overflow(span)),
+        _ => Err(Error::InvalidArgument("gcd needs integers".to_string(), span)),
+    }
+}
+
+fn builtin_floor(args: &[Number], options: &EvalOptions, span: Span) -> Result<Number, Error> {
+    match &args[0] {
+        Number::Float(x) => Ok(Number::from_whole(x.floor())),
+        Number::Ratio(r) => Number::fit_big(r.floor(), options).ok_or(Error::Overflow(span)),
+        int => Ok(int.clone()),
+    }
+}
+
+fn builtin_ceil(args: &[Number], options: &EvalOptions, span: Span) -> Result<Number, Error> {
+    match &args[0] {
+        Number::Float(x) => Ok(Number::from_whole(x.ceil())),
+        Number::Ratio(r) => Number::fit_big(r.ceil(), options).ok_or(Error::Overflow(span)),
+        int => Ok(int.clone()),
+    }
+}
+
+// natural log; derivatives of a ^ x need it
+fn builtin_ln(args: &[Number], _options: &EvalOptions, span: Span) -> Result<Number, Error> {
+    let x = args[0].to_f64();
+    if x <= 0.0 {
+        return Err(Error::InvalidArgument("ln of a number that isn't positive".to_string(), span));
+    }
+    Ok(Number::Float(x.ln()))
+}
+
+fn builtin_exp(args: &[Number], _options: &EvalOptions, span: Span) -> Result<Number, Error> {
+    Number::float(args[0].to_f64().exp()).ok_or(Error::Overflow(span))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    FloorDiv, // 7 // 2 = 3, -7 // 2 = -4 whatever the DivisionMode
+    Mod, // -7 % 2 = 1, the remainder that goes with //
+    Pow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Assoc {
+    Left, // 1 - 2 - 3 = (1 - 2) - 3
+    Right, // 2 ^ 3 ^ 2 = 2 ^ (3 ^ 2)
+}
+
+impl Assoc {
+    fn name(self) -> &'static str {
+        match self {
+            Assoc::Left => "left",
+            Assoc::Right => "right",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Assoc> {
+        [Assoc::Left, Assoc::Right].iter().copied().find(|a| a.name() == name)
+    }
+}
+
+// loosest first: || && ! comparisons, then the arithmetic from BinOp::precedence
+// ! is looser than the comparisons so !x > 3 is !(x > 3), like Python's not
+const OR_PRECEDENCE: u8 = 1;
+const AND_PRECEDENCE: u8 = 2;
+const NOT_PRECEDENCE: u8 = 3;
+const COMPARE_PRECEDENCE: u8 = 4;
+// unary minus binds tighter than * and / but looser than ^, so -2 ^ 2 = -(2 ^ 2) = -4
+const NEG_PRECEDENCE: u8 = 7;
+
+impl BinOp {
+    const ALL: [BinOp; 7] = [
+        BinOp::Add,
+        BinOp::Sub,
+        BinOp::Mul,
+        BinOp::Div,
+        BinOp::FloorDiv,
+        BinOp::Mod,
+        BinOp::Pow,
+    ];
+
+    fn from_symbol(symbol: &str) -> Option<BinOp> {
+        match symbol {
+            "**" => Some(BinOp::Pow), // ca in Python
+            _ => BinOp::ALL.iter().copied().find(|op| op.symbol() == symbol),
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::FloorDiv => "//",
+            BinOp::Mod => "%",
+            BinOp::Pow => "^",
+        }
+    }
+
+    // the parser, Shunting Yard and the printer all go by these two, so a new level is
+    // just a new number here
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Add | BinOp::Sub => 5,
+            BinOp::Mul | BinOp::Div | BinOp::FloorDiv | BinOp::Mod => 6,
+            BinOp::Pow => 8,
+        }
+    }
+
+    fn associativity(self) -> Assoc {
+        match self {
+            BinOp::Pow => Assoc::Right,
+            _ => Assoc::Left,
+        }
+    }
+
+    // a division by zero points at the right operand for these
+    fn divides(self) -> bool {
+        matches!(self, BinOp::Div | BinOp::FloorDiv | BinOp::Mod)
+    }
+
+    // span is the whole operation, right_span the divisor (a division by zero points at it)
+    fn apply(
+        self,
+        left: &Number,
+        right: &Number,
+        options: &EvalOptions,
+        span: Span,
+        right_span: Span,
+    ) -> Result<Number, Error> {
+        let result = match self {
+            BinOp::Add => left.add(right, options),
+            BinOp::Sub => left.sub(right, options),
+            BinOp::Mul => left.mul(right, options),
+            _ if self.divides() && right.is_zero() => {
+                return Err(Error::DivisionByZero(right_span));
+            }
+            BinOp::Div => left.div(right, options), // i32::MIN / -1 tot overflow e
+            BinOp::FloorDiv => left.floor_div(right, options),
+            BinOp::Mod => left.rem(right, options),
+            BinOp::Pow => {
+                if left.to_f64() < 0.0 && right.to_f64().fract() != 0.0 {
+                    let message = "pow of a negative number to a fractional power";
+                    return Err(Error::InvalidArgument(message.to_string(), span));
+                }
+                if left.is_zero() && right.to_f64() < 0.0 {
+                    return Err(Error::DivisionByZero(span));
+                }
+                left.pow(right, options)
+            }
+        };
+        result.ok_or(Error::Overflow(span))
+    }
+}
+
+// all of them at COMPARE_PRECEDENCE, left associative, so 1 < 2 < 3 is (1 < 2) < 3, a type error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    const ALL: [CmpOp; 6] = [CmpOp::Eq, CmpOp::Ne, CmpOp::Lt, CmpOp::Le, CmpOp::Gt, CmpOp::Ge];
+
+    fn from_symbol(symbol: &str) -> Option<CmpOp> {
+        CmpOp::ALL.iter().copied().find(|op| op.symbol() == symbol)
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            CmpOp::Eq => "==",
+            CmpOp::Ne => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        }
+    }
+
+    // for the disassembly
+    fn mnemonic(self) -> &'static str {
+        match self {
+            CmpOp::Eq => "eq",
+            CmpOp::Ne => "ne",
+            CmpOp::Lt => "lt",
+            CmpOp::Le => "le",
+            CmpOp::Gt => "gt",
+            CmpOp::Ge => "ge",
+        }
+    }
+
+    // numbers compare with numbers (1 == 1.0), booleans only with == and !=
+    // NaN is not equal, smaller or bigger than anything
+    fn apply<T: Scalar>(
+        self,
+        left: &Value<T>,
+        right: &Value<T>,
+        span: Span,
+    ) -> Result<bool, Error> {
+        let equality = matches!(self, CmpOp::Eq | CmpOp::Ne);
+        let order = match (left, right) {
+            (Value::Num(a), Value::Num(b)) => a.order(b, self, span)?,
+            (Value::Bool(a), Value::Bool(b)) if equality => Some(a.cmp(b)),
+            _ => {
+                let (expected, found) = match left {
+                    Value::Num(_) => ("a number", right.type_name()), // 1 == true, 1 < true
+                    Value::Bool(_) if equality => ("a boolean", right.type_name()), // true == 1
+                    Value::Bool(_) => ("a number", left.type_name()), // true < false
+                };
+                return Err(Error::TypeMismatch(expected, found, span));
+            }
+        };
+        Ok(match self {
+            CmpOp::Eq => order == Some(Ordering::Equal),
+            CmpOp::Ne => order != Some(Ordering::Equal),
+            CmpOp::Lt => order == Some(Ordering::Less),
+            CmpOp::Le => matches!(order, Some(Ordering::Less | Ordering::Equal)),
+            CmpOp::Gt => order == Some(Ordering::Greater),
+            CmpOp::Ge => matches!(order, Some(Ordering::Greater | Ordering::Equal)),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+// the numbers are Number everywhere except in the Scalar parser, see SCALARS
+enum Expression<T = Number> {
+    Val(T), //ma opresc cand am valoare numar
+    Add(Box<Node<T>>, Box<Node<T>>), //o operatie se face intre 2 chestii 
+    Sub(Box<Node<T>>, Box<Node<T>>),
+    Mul(Box<Node<T>>, Box<Node<T>>),
+    Div(Box<Node<T>>, Box<Node<T>>),
+    FloorDiv(Box<Node<T>>, Box<Node<T>>),
+    Mod(Box<Node<T>>, Box<Node<T>>),
+    Pow(Box<Node<T>>, Box<Node<T>>),
+    Neg(Box<Node<T>>), //minus unar, -x
+    Var(String),
+    Call(String, Vec<Node<T>>), // f(1, x + 2)
+    Custom(CustomOp, Vec<Node<T>>), // an operator from the OperatorTable, 5!
+    Bool(bool), // true, false
+    Compare(CmpOp, Box<Node<T>>, Box<Node<T>>),
+    And(Box<Node<T>>, Box<Node<T>>), // the right side only runs if the left is true
+    Or(Box<Node<T>>, Box<Node<T>>),  // ... if the left is false
+    Not(Box<Node<T>>),
+    If(Box<Node<T>>, Box<Node<T>>, Box<Node<T>>), // if x > 0 then x else -x, only one branch runs
+    Error, // what the recovering parser skipped, see parse_file; never evaluates
+}
+
+impl<T> Expression<T> {
+    fn binary(op: BinOp, left: Node<T>, right: Node<T>) -> Expression<T> {
+        let (left, right) = (Box::new(left), Box::new(right));
+        match op {
+            BinOp::Add => Expression::Add(left, right),
+            BinOp::Sub => Expression::Sub(left, right),
+            BinOp::Mul => Expression::Mul(left, right),
+            BinOp::Div => Expression::Div(left, right),
+            BinOp::FloorDiv => Expression::FloorDiv(left, right),
+            BinOp::Mod => Expression::Mod(left, right),
+            BinOp::Pow => Expression::Pow(left, right),
+        }
+    }
+
+    fn as_binary(&self) -> Option<(BinOp, &Node<T>, &Node<T>)> {
+        match self {
+            Expression::Add(left, right) => Some((BinOp::Add, left, right)),
+            Expression::Sub(left, right) => Some((BinOp::Sub, left, right)),
+            Expression::Mul(left, right) => Some((BinOp::Mul, left, right)),
+            Expression::Div(left, right) => Some((BinOp::Div, left, right)),
+            Expression::FloorDiv(left, right) => Some((BinOp::FloorDiv, left, right)),
+            Expression::Mod(left, right) => Some((BinOp::Mod, left, right)),
+            Expression::Pow(left, right) => Some((BinOp::Pow, left, right)),
+            _ => None,
+        }
+    }
+
+    // the operands, left to right
+    fn children(&self) -> Vec<&Node<T>> {
+        match self {
+            Expression::Val(_) | Expression::Var(_) | Expression::Bool(_) | Expression::Error => {
+                Vec::new()
+            }
+            Expression::Add(left, right)
+            | Expression::Sub(left, right)
+            | Expression::Mul(left, right)
+            | Expression::Div(left, right)
+            | Expression::FloorDiv(left, right)
+            | Expression::Mod(left, right)
+            | Expression::Pow(left, right)
+            | Expression::Compare(_, left, right)
+            | Expression::And(left, right)
+            | Expression::Or(left, right) => vec![left, right],
+            Expression::Neg(operand) | Expression::Not(operand) => vec![operand],
+            Expression::Call(_, args) | Expression::Custom(_, args) => args.iter().collect(),
+            Expression::If(cond, then, otherwise) => vec![cond, then, otherwise],
+        }
+    }
+}
+
+impl Expression {
+    // the same operator over other operands, given in the order children() has them
+    fn with_children(&self, children: Vec<Node>) -> Expression {
+        let mut children = children.into_iter();
+        let mut next = || Box::new(children.next().expect("as many operands as children()"));
+        match self {
+            Expression::Val(_) | Expression::Var(_) | Expression::Bool(_) | Expression::Error => {
+                self.clone()
+            }
+            Expression::Add(..)
+            | Expression::Sub(..)
+            | Expression::Mul(..)
+            | Expression::Div(..)
+            | Expression::FloorDiv(..)
+            | Expression::Mod(..)
+            | Expression::Pow(..) => {
+                let (op, _, _) = self.as_binary().expect("a binary node");
+                let left = next();
+                Expression::binary(op, *left, *next())
+            }
+            Expression::Neg(_) => Expression::Neg(next()),
+            Expression::Call(name, args) => {
+                Expression::Call(name.clone(), args.iter().map(|_| *next()).collect())
+            }
+            Expression::Custom(op, args) => {
+                Expression::Custom(op.clone(), args.iter().map(|_| *next()).collect())
+            }
+            Expression::Compare(op, _, _) => Expression::Compare(*op, next(), next()),
+            Expression::And(..) => Expression::And(next(), next()),
+            Expression::Or(..) => Expression::Or(next(), next()),
+            Expression::Not(_) => Expression::Not(next()),
+            Expression::If(..) => Expression::If(next(), next(), next()),
+        }
+    }
+
+    fn children_mut(&mut self) -> Vec<&mut Node> {
+        match self {
+            Expression::Val(_) | Expression::Var(_) | Expression::Bool(_) | Expression::Error => {
+                Vec::new()
+            }
+            Expression::Add(left, right)
+            | Expression::Sub(left, right)
+            | Expression::Mul(left, right)
+            | Expression::Div(left, right)
+            | Expression::FloorDiv(left, right)
+            | Expression::Mod(left, right)
+            | Expression::Pow(left, right)
+            | Expression::Compare(_, left, right)
+            | Expression::And(left, right)
+            | Expression::Or(left, right) => vec![left, right],
+            Expression::Neg(operand) | Expression::Not(operand) => vec![operand],
+            Expression::Call(_, args) | Expression::Custom(_, args) => args.iter_mut().collect(),
+            Expression::If(cond, then, otherwise) => vec![cond, then, otherwise],
+        }
+    }
+
+    // the node itself as a postfix item, without its operands
+    fn rpn_item(&self) -> RpnItem {
+        match self {
+            Expression::Val(number) => RpnItem::Num(number.clone()),
+            Expression::Var(name) => RpnItem::Var(name.clone()),
+            Expression::Add(..)
+            | Expression::Sub(..)
+            | Expression::Mul(..)
+            | Expression::Div(..)
+            | Expression::FloorDiv(..)
+            | Expression::Mod(..)
+            | Expression::Pow(..) => {
+                let (op, _, _) = self.as_binary().expect("a binary node");
+                RpnItem::Binary(op)
+            }
+            Expression::Neg(_) => RpnItem::Neg,
+            Expression::Call(name, args) => RpnItem::Call(name.clone(), args.len()),
+            Expression::Custom(op, _) => RpnItem::Custom(op.clone()),
+            Expression::Bool(b) => RpnItem::Bool(*b),
+            Expression::Compare(op, _, _) => RpnItem::Compare(*op),
+            Expression::And(..) => RpnItem::And,
+            Expression::Or(..) => RpnItem::Or,
+            Expression::Not(_) => RpnItem::Not,
+            Expression::If(..) => RpnItem::If,
+            Expression::Error => RpnItem::Error,
+        }
+    }
+
+    // the same rules as for the postfix -> infix conversion
+    fn infix(&self) -> Printed {
+        let operands = self.children().iter().map(|child| child.expr.infix()).collect();
+        infix_text(&self.rpn_item(), operands)
+    }
+
+    // every operator with its own parens: (1 + (2 * x)), ((-2) ^ 2), (if c then 1 else 2)
+    fn parenthesized(&self) -> String {
+        let operands = self
+            .children()
+            .iter()
+            .map(|child| Printed::atom(child.expr.parenthesized()))
+            .collect();
+        let printed = infix_text(&self.rpn_item(), operands);
+        if printed.precedence == ATOM_PRECEDENCE {
+            printed.text
+        } else {
+            format!("({})", printed.text)
+        }
+    }
+
+    // (+ 1 (* 2 x)), see sexpr_text
+    fn sexpr(&self) -> String {
+        let operands = self.children().iter().map(|child| child.expr.sexpr()).collect();
+        sexpr_text(&self.rpn_item(), operands)
+    }
+
+    // the other way from rpn_item: the node for item, with as many operands as it takes
+    fn from_item(item: RpnItem, operands: Vec<Node>) -> Expression {
+        let item = match item {
+            RpnItem::Call(name, _) => return Expression::Call(name, operands),
+            RpnItem::Custom(op) => return Expression::Custom(op, operands),
+            item => item,
+        };
+        let mut operands = operands.into_iter().map(Box::new);
+        let mut next = || operands.next().expect("as many operands as the item takes");
+        match item {
+            RpnItem::Num(number) => Expression::Val(number),
+            RpnItem::Var(name) => Expression::Var(name),
+            RpnItem::Bool(b) => Expression::Bool(b),
+            RpnItem::Binary(op) => {
+                let left = next();
+                Expression::binary(op, *left, *next())
+            }
+            RpnItem::Neg => Expression::Neg(next()),
+            RpnItem::Compare(op) => Expression::Compare(op, next(), next()),
+            RpnItem::And => Expression::And(next(), next()),
+            RpnItem::Or => Expression::Or(next(), next()),
+            RpnItem::Not => Expression::Not(next()),
+            RpnItem::If => Expression::If(next(), next(), next()),
+            RpnItem::Error => Expression::Error,
+            RpnItem::Call(..) | RpnItem::Custom(_) => unreachable!("handled above"),
+        }
+    }
+}
+
+// canonical infix, with only the parens precedence and associativity need
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.infix().text)
+    }
+}
+
+// an Expression plus the piece of input it came from, so eval errors can point at it
+#[derive(Debug, Clone)]
+struct Node<T = Number> {
+    expr: Expression<T>,
+    span: Span,
+}
+
+impl<T> Node<T> {
+    fn new(expr: Expression<T>, span: Span) -> Node<T> {
+        Node { expr, span }
+    }
+
+    // how many levels the tree has, counted with a stack of our own: the parser checks it on
+    // trees it can't check by recursing, see parse_pratt
+    fn height(&self) -> usize {
+        let mut pending = vec![(self, 1)];
+        let mut height = 0;
+        while let Some((node, depth)) = pending.pop() {
+            height = height.max(depth);
+            pending.extend(node.expr.children().into_iter().map(|child| (child, depth + 1)));
+        }
+        height
+    }
+}
+
+impl<T: Scalar> Node<T> {
+    // the same parsed Node can be evaluated again and again with different bindings in env
+    // the arms that go deeper are in eval_number and eval_logic, so one level of a deep tree
+    // only costs the stack of the kind of node it is
+    // a Node<i32> (:as) gets the session's variables that are i32s too, and no functions
+    fn eval(&self, env: &mut Environment) -> Result<Value<T>, Error> {
+        match &self.expr {
+            Expression::Val(value) => {
+                value.literal(&env.options).map(Value::Num).ok_or(Error::Overflow(self.span))
+            }
+            Expression::Error => Err(Error::Unparsed(self.span)),
+            Expression::Var(name) => match env.get(name) {
+                Some(Value::Num(number)) => T::from_number(&number, self.span).map(Value::Num),
+                Some(Value::Bool(b)) => Ok(Value::Bool(b)),
+                None => Err(Error::UnknownVariable(name.clone(), self.span)),
+            },
+            Expression::Bool(b) => Ok(Value::Bool(*b)),
+            _ => {
+                env.depth += 1; //Environment::call se uita la el
+                let value = match &self.expr {
+                    Expression::Compare(..)
+                    | Expression::And(..)
+                    | Expression::Or(..)
+                    | Expression::Not(..)
+                    | Expression::If(..) => self.eval_logic(env),
+                    _ => self.eval_number(env),
+                };
+                env.depth -= 1;
+                value
+            }
+        }
+    }
+
+    fn eval_number(&self, env: &mut Environment) -> Result<Value<T>, Error> {
+        match &self.expr {
+            Expression::Add(left, right)
+            | Expression::Sub(left, right)
+            | Expression::Mul(left, right)
+            | Expression::Div(left, right)
+            | Expression::FloorDiv(left, right)
+            | Expression::Mod(left, right)
+            | Expression::Pow(left, right) => {
+                let (op, _, _) = self.expr.as_binary().expect("a binary node");
+                let l = left.eval(env)?.number(left.span)?;
+                let r = right.eval(env)?.number(right.span)?;
+                T::apply(op, &l, &r, &env.options, self.span, right.span).map(Value::Num)
+            }
+            Expression::Neg(operand) => {
+                let value = operand.eval(env)?.number(operand.span)?;
+                value.neg(&env.options).map(Value::Num).ok_or(Error::Overflow(self.span))
+            }
+            Expression::Call(name, args) => {
+                let values = args.iter().map(|arg| arg.eval(env)).collect::<Result<Vec<_>, _>>()?;
+                T::call(env, name, &values, self.span)
+            }
+            Expression::Custom(op, operands) => {
+                let values =
+                    operands.iter().map(|arg| arg.eval(env)).collect::<Result<Vec<_>, _>>()?;
+                T::apply_operator(env, op, &values, self.span)
+            }
+            _ => unreachable!("eval has the other nodes"),
+        }
+    }
+
+    fn eval_logic(&self, env: &mut Environment) -> Result<Value<T>, Error> {
+        match &self.expr {
+            Expression::Compare(op, left, right) => {
+                let (l, r) = (left.eval(env)?, right.eval(env)?);
+                op.apply(&l, &r, self.span).map(Value::Bool)
+            }
+            // && si || se opresc cand stiu deja rezultatul: false && 1 / 0 e false, nu eroare
+            Expression::And(left, right) => {
+                if !left.eval(env)?.boolean(left.span)? {
+                    return Ok(Value::Bool(false));
+                }
+                right.eval(env)?.boolean(right.span).map(Value::Bool)
+            }
+            Expression::Or(left, right) => {
+                if left.eval(env)?.boolean(left.span)? {
+                    return Ok(Value::Bool(true));
+                }
+                right.eval(env)?.boolean(right.span).map(Value::Bool)
+            }
+            Expression::Not(operand) => {
+                operand.eval(env)?.boolean(operand.span).map(|b| Value::Bool(!b))
+            }
+            Expression::If(cond, then, otherwise) => {
+                let branch = if cond.eval(env)?.boolean(cond.span)? { then } else { otherwise };
+                branch.eval(env)
+            }
+            _ => unreachable!("eval has the other nodes"),
+        }
+    }
+}
+
+impl Node {
+    // eval, with every step it takes on the way; the steps so far are there even on an error
+    fn trace(&self, env: &mut Environment) -> (Vec<Step>, Result<Value, Error>) {
+        let mut tracer = Tracer { env, tree: self.clone(), steps: Vec::new() };
+        let result = tracer.eval(&mut Vec::new());
+        (tracer.steps, result)
+    }
+
+    // where the first Error node is, if the recovering parser left one in
+    fn unparsed(&self) -> Option<Span> {
+        match &self.expr {
+            Expression::Error => Some(self.span),
+            expr => expr.children().into_iter().find_map(Node::unparsed),
+        }
+    }
+
+    // postfix (RPN) straight from the tree: children first, then the operator
+    fn postfix(&self, out: &mut Vec<(RpnItem, Span)>) {
+        for child in self.expr.children() {
+            child.postfix(out);
+        }
+        out.push((self.expr.rpn_item(), self.span));
+    }
+}
+
+// the spans don't count, so a reparsed tree equals the original: parse(print(e)) == e
+impl<T: PartialEq> PartialEq for Node<T> {
+    fn eq(&self, other: &Node<T>) -> bool {
+        self.expr == other.expr
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.expr)
+    }
+}
+
+#[derive(Debug)]
+enum Statement {
+    Let(String, Node), // let x = 3 * y
+    Fn(String, Vec<String>, Node), // fn f(x, y) = x*x + y
+    Expr(Node),
+}
+
+impl Statement {
+    // returns the value of the expression (for let, the value that got bound, fn has none)
+    fn run(&self, env: &mut Environment) -> Result<Option<Value>, Error> {
+        match self {
+            Statement::Let(name, value) => {
+                let value = value.eval(env)?;
+                env.set(name, value.clone());
+                Ok(Some(value))
+            }
+            Statement::Fn(name, params, body) => {
+                let function = Function {
+                    params: params.clone(),
+                    body: Rc::new(body.clone()),
+                };
+                env.define(name, function);
+                Ok(None)
+            }
+            Statement::Expr(expr) => expr.eval(env).map(Some),
+        }
+    }
+}
+
+// as it would be typed in
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Statement::Let(name, value) => write!(f, "let {} = {}", name, value),
+            Statement::Fn(name, params, body) => {
+                write!(f, "fn {}({}) = {}", name, params.join(", "), body)
+            }
+            Statement::Expr(expr) => write!(f, "{}", expr),
+        }
+    }
+}
+
+// the tokenizer lives on its own so it can be used (and checked) without the parser
+mod lexer {
+    use super::{BigInt, Error, Number, Rational, Span, MAX_BIG_BITS};
+    use std::fmt;
+
+    // tokens borrow from the input, nu mai fac String pt fiecare
+    #[derive(Debug, Clone, PartialEq)]
+    pub(crate) enum Token<'a> {
+        Number(Number),
+        Imaginary(&'a str), // 2i, 0.5i: only the Complex parser takes them
+        Ident(&'a str),
+        Op(&'a str),
+        LParen,
+        RParen,
+        Comma,
+        Invalid, // what tokenize_lossy left in place of a lexer error
+        Eof,
+    }
+
+    impl fmt::Display for Token<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Token::Number(n) => write!(f, "number {}", n),
+                Token::Imaginary(text) => write!(f, "imaginary number {}", text),
+                Token::Ident(name) => write!(f, "identifier '{}'", name),
+                Token::Op(op) => write!(f, "'{}'", op),
+                Token::LParen => write!(f, "'('"),
+                Token::RParen => write!(f, "')'"),
+                Token::Comma => write!(f, "','"),
+                Token::Invalid => write!(f, "invalid input"),
+                Token::Eof => write!(f, "end of input"),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub(crate) struct Lexeme<'a> {
+        pub(crate) token: Token<'a>,
+        pub(crate) span: Span,
+    }
+
+    #[derive(Debug)]
+    pub(crate) struct Lexer<'a, 'o> {
+        input: &'a str,
+        operators: &'o [&'o str], // the symbols from the OperatorTable, longest first
+        pos: usize,
+        done: bool,
+    }
+
+    impl<'a, 'o> Lexer<'a, 'o> {
+        pub(crate) fn new(input: &'a str, operators: &'o [&'o str]) -> Lexer<'a, 'o> {
+            Lexer {
+                input,
+                operators,
+                pos: 0,
+                done: false,
+            }
+        }
+
+        // consume chars while the predicate holds, return the slice we went over
+        fn take_while(&mut self, pred: impl Fn(char) -> bool) -> &'a str {
+            let start = self.pos;
+            let rest = &self.input[start..];
+            let len = rest.find(|ch: char| !pred(ch)).unwrap_or(rest.len());
+            self.pos += len;
+            &self.input[start..self.pos]
+        }
+
+        // number := digits ['.' digits] [('e' | 'E') ['+' | '-'] digits], or starting at '.'
+        // without '.' and exponent it's an Int (a Big if it doesn't fit), otherwise a Float, or
+        // the exact Ratio if an f64 can't hold it (1e400, 1e-400), for the rational backend
+        fn number(&mut self) -> Result<Number, Error> {
+            let start = self.pos;
+            let mut is_float = false;
+
+            self.take_while(|c| c.is_ascii_digit());
+            if self.input[self.pos..].starts_with('.') {
+                is_float = true;
+                self.pos += 1;
+                self.take_while(|c| c.is_ascii_digit());
+            }
+
+            // 2e3 e exponent, dar 2e sau 2ex nu (acolo e e un identificator)
+            let rest = &self.input[self.pos..];
+            if rest.starts_with(['e', 'E']) {
+                let sign = if rest[1..].starts_with(['+', '-']) { 1 } else { 0 };
+                if rest[1 + sign..].starts_with(|c: char| c.is_ascii_digit()) {
+                    is_float = true;
+                    self.pos += 1 + sign;
+                    self.take_while(|c| c.is_ascii_digit());
+                }
+            }
+
+            let text = &self.input[start..self.pos];
+            let span = Span::new(start, self.pos);
+            if is_float {
+                let mantissa = text.split(['e', 'E']).next().unwrap_or(text);
+                let nonzero = mantissa.contains(|c| ('1'..='9').contains(&c));
+                let x = text.parse::<f64>().map_err(|_| Error::Overflow(span))?;
+                if x.is_finite() && (x != 0.0 || !nonzero) {
+                    return Ok(Number::Float(x));
+                }
+                // 10^19660 still fits in MAX_BIG_BITS, 3/10 being a bit under log10(2)
+                match Rational::parse_decimal(text, MAX_BIG_BITS * 3 / 10) {
+                    Some(exact) => Ok(Number::Ratio(exact)),
+                    None if x == 0.0 => Ok(Number::Float(x)), // 1e-99999, 0 for every backend
+                    None => Err(Error::Overflow(span)),       // 1e99999
+                }
+            } else {
+                // only digits, so the only way parse can fail is a number too big for i32
+                // whether a Big is fine is up to the backend, eval decides
+                let big = || Number::Big(BigInt::parse(text));
+                Ok(text.parse::<i32>().map_or_else(|_| big(), Number::Int))
+            }
+        }
+
+        fn next_lexeme(&mut self) -> Result<Lexeme<'a>, Error> {
+            self.take_while(char::is_whitespace);
+            let start = self.pos;
+            let rest = &self.input[start..];
+
+            let ch = match rest.chars().next() {
+                Some(ch) => ch,
+                None => {
+                    self.done = true;
+                    return Ok(Lexeme {
+                        token: Token::Eof,
+                        span: Span::new(start, start),
+                    });
+                }
+            };
+
+            let starts_number = ch.is_ascii_digit()
+                || (ch == '.' && rest[1..].starts_with(|c: char| c.is_ascii_digit()));
+
+            let token = if starts_number {
+                let number = self.number()?;
+                // 2i e imaginar, dar 2in nu (like the exponent, 2e vs 2ex)
+                let is_name = |c: char| c.is_alphanumeric() || c == '_';
+                let after = self.input[self.pos..].strip_prefix('i');
+                if after.is_some_and(|after| !after.starts_with(is_name)) {
+                    self.pos += 1;
+                    Token::Imaginary(&self.input[start..self.pos])
+                } else {
+                    Token::Number(number)
+                }
+            } else if ch.is_alphabetic() || ch == '_' {
+                Token::Ident(self.take_while(|c| c.is_alphanumeric() || c == '_'))
+            } else if let Some(op) = self.operators.iter().find(|op| rest.starts_with(*op)) {
+                self.pos += op.len();
+                Token::Op(&self.input[start..self.pos])
+            } else {
+                self.pos += ch.len_utf8();
+                match ch {
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    ',' => Token::Comma,
+                    _ => return Err(Error::InvalidCharacter(ch, Span::new(start, self.pos))),
+                }
+            };
+
+            Ok(Lexeme {
+                token,
+                span: Span::new(start, self.pos),
+            })
+        }
+    }
+
+    // yields tokens up to and including Eof, then stops
+    impl<'a> Iterator for Lexer<'a, '_> {
+        type Item = Result<Lexeme<'a>, Error>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.done {
+                return None;
+            }
+            let lexeme = self.next_lexeme();
+            if lexeme.is_err() {
+                self.done = true;
+            }
+            Some(lexeme)
+        }
+    }
+
+    pub(crate) fn tokenize<'a>(
+        input: &'a str,
+        operators: &[&str],
+    ) -> Result<Vec<Lexeme<'a>>, Error> {
+        Lexer::new(input, operators).collect()
+    }
+
+    // doesn't stop at the first error: the bad char (or number) becomes an Invalid token and
+    // the error goes on the list, so the recovering parser still sees the rest of the line
+    // starts at byte start, so the spans are into the whole input (parse_file gives it a file)
+    pub(crate) fn tokenize_lossy<'a>(
+        input: &'a str,
+        start: usize,
+        operators: &[&str],
+    ) -> (Vec<Lexeme<'a>>, Vec<Error>) {
+        let mut lexer = Lexer::new(input, operators);
+        lexer.pos = start;
+        let (mut lexemes, mut errors) = (Vec::new(), Vec::new());
+        while !lexer.done {
+            match lexer.next_lexeme() {
+                Ok(lexeme) => lexemes.push(lexeme),
+                Err(e) => {
+                    lexemes.push(Lexeme {
+                        token: Token::Invalid,
+                        span: e.span(),
+                    });
+                    errors.push(e);
+                }
+            }
+        }
+        (lexemes, errors)
+    }
+}
+
+use lexer::{Lexeme, Token};
+
+// names that can't be variables, functions or parameters
+const KEYWORDS: &[&str] = &["let", "fn", "if", "then", "else", "true", "false"];
+
+// the parser and everything that walks a tree are recursive, so ((((...)))) ten thousand deep
+// would run out of stack; past this many levels it's a TooDeep error instead
+const MAX_DEPTH: usize = 256;
+
+// the same grammar for any Scalar, the literals are whatever T::parse_literal makes of them
+#[derive(Debug)]
+struct Parser<'a, T = Number> {
+    tokens: Vec<Lexeme<'a>>, //se termina mereu cu Eof
+    operators: &'a OperatorTable,
+    index: usize,
+    source: &'a str, // the literals are read from here, by their spans
+    scalar: PhantomData<T>,
+    recovering: bool, // parse_file: errors go to diagnostics and the part becomes an Error node
+    diagnostics: Vec<Error>,
+    depth: usize, // how many parse_pratt calls deep we are, see nested
+}
+
+impl<'a> Parser<'a> {
+    fn new(expression: &'a str, operators: &'a OperatorTable) -> Result<Parser<'a>, Error> {
+        Parser::over(expression, operators)
+    }
+
+    // for parse_file: the tokens come from tokenize_lossy, their spans are into source
+    fn recovering(
+        tokens: Vec<Lexeme<'a>>,
+        source: &'a str,
+        operators: &'a OperatorTable,
+    ) -> Parser<'a> {
+        Parser {
+            tokens,
+            operators,
+            index: 0,
+            source,
+            scalar: PhantomData,
+            recovering: true,
+            diagnostics: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    // statement := 'let' ident '=' exp
+    //            | 'fn' ident '(' [ident (',' ident)*] ')' '=' exp
+    //            | exp
+    fn parse_statement(&mut self) -> Result<Statement, Error> {
+        match self.current_token() {
+            Token::Ident("let") => {
+                self.advance();
+                let name = self.expect_ident("a variable name")?;
+                self.expect(Token::Op("="), "'='")?;
+                Ok(Statement::Let(name, self.parse()?))
+            }
+            Token::Ident("fn") => {
+                self.advance();
+                let name = self.expect_ident("a function name")?;
+                self.expect(Token::LParen, "'('")?;
+
+                let mut params = Vec::new();
+                if self.current_token() != Token::RParen {
+                    params.push(self.expect_ident("a parameter name")?);
+                    while self.current_token() == Token::Comma {
+                        self.advance();
+                        params.push(self.expect_ident("a parameter name")?);
+                    }
+                }
+                self.expect(Token::RParen, "')' or ','")?;
+                self.expect(Token::Op("="), "'='")?;
+                Ok(Statement::Fn(name, params, self.parse()?))
+            }
+            _ => Ok(Statement::Expr(self.parse()?)),
+        }
+    }
+}
+
+impl<'a, T: Scalar> Parser<'a, T> {
+    // Parser::<Complex>::over("(1 + 2i) * 3", &operators)
+    fn over(expression: &'a str, operators: &'a OperatorTable) -> Result<Parser<'a, T>, Error> {
+        Ok(Parser {
+            tokens: lexer::tokenize(expression, &operators.symbols())?,
+            operators,
+            index: 0,
+            source: expression,
+            scalar: PhantomData,
+            recovering: false,
+            diagnostics: Vec::new(),
+            depth: 0,
+        })
+    }
+
+    fn current_token(&self) -> Token<'a> {
+        self.tokens[self.index].token.clone()
+    }
+
+    fn current_span(&self) -> Span {
+        self.tokens[self.index].span
+    }
+
+    // Advance to the next token (Eof stays put)
+    fn advance(&mut self) {
+        if self.index + 1 < self.tokens.len() {
+            self.index += 1;
+        }
+    }
+
+    // Parse the whole input as one expression
+    // recovering, whatever is left after it is only reported, the tree stays as it was
+    fn parse(&mut self) -> Result<Node<T>, Error> {
+        let start = self.current_span();
+        let result = self.parse_exp();
+        let expr = self.recover(result, start, &[])?;
+        match self.expect_end() {
+            Err(e) if self.recovering => self.diagnostics.push(e),
+            result => result?,
+        }
+        Ok(expr)
+    }
+
+    // panic mode: the error is kept and the parser skips to the first of stops that isn't
+    // inside parentheses (or to the end of the statement), everything from start up to there
+    // is one Error node; not recovering, the result is passed on as it is
+    fn recover(
+        &mut self,
+        result: Result<Node<T>, Error>,
+        start: Span,
+        stops: &[Token<'a>],
+    ) -> Result<Node<T>, Error> {
+        match result {
+            Err(e) if self.recovering => {
+                self.diagnostics.push(e);
+                let skipped = self.synchronize(stops);
+                Ok(Node::new(Expression::Error, start.to(skipped)))
+            }
+            result => result,
+        }
+    }
+
+    // same for a '(' group or a call, and then the ')' that closes it is taken too
+    fn recover_group(
+        &mut self,
+        result: Result<Node<T>, Error>,
+        open: Span,
+    ) -> Result<Node<T>, Error> {
+        if result.is_ok() || !self.recovering {
+            return result;
+        }
+        let mut node = self.recover(result, open, &[Token::RParen])?;
+        if self.current_token() == Token::RParen {
+            node.span = node.span.to(self.current_span());
+            self.advance();
+        }
+        Ok(node)
+    }
+
+    // returns the span of the tokens it skipped, empty when it was already at a stop
+    fn synchronize(&mut self, stops: &[Token<'a>]) -> Span {
+        let start = self.current_span().start;
+        let mut end = start;
+        let mut depth = 0usize;
+        loop {
+            let token = self.current_token();
+            if token == Token::Eof || (depth == 0 && stops.contains(&token)) {
+                return Span::new(start, end);
+            }
+            match token {
+                Token::LParen => depth += 1,
+                Token::RParen => depth = depth.saturating_sub(1), // a stray ')' doesn't count
+                _ => {}
+            }
+            end = self.current_span().end;
+            self.advance();
+        }
+    }
+
+    // could the current token be the start of an operand
+    fn starts_operand(&self) -> bool {
+        match self.current_token() {
+            Token::Number(_) | Token::Imaginary(_) | Token::LParen | Token::Invalid => true,
+            Token::Ident(name) => !matches!(name, "let" | "fn" | "then" | "else"),
+            Token::Op(_) => self.current_op(Fixity::Prefix).is_some(),
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, token: Token, expected: &'static str) -> Result<(), Error> {
+        if self.current_token() != token {
+            let found = self.current_token().to_string();
+            return Err(Error::UnexpectedToken(found, expected, self.current_span()));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn expect_ident(&mut self, expected: &'static str) -> Result<String, Error> {
+        match self.current_token() {
+            Token::Ident(name) if !KEYWORDS.contains(&name) => {
+                self.advance();
+                Ok(name.to_string())
+            }
+            tok => Err(Error::UnexpectedToken(tok.to_string(), expected, self.current_span())),
+        }
+    }
+
+    // anything left over after the expression is an error
+    fn expect_end(&self) -> Result<(), Error> {
+        match self.current_token() {
+            Token::Eof => Ok(()),
+            Token::RParen => Err(Error::UnmatchedParen(')', self.current_span())),
+            tok => {
+                let end = self.tokens[self.tokens.len() - 1].span.end;
+                let rest = Span::new(self.current_span().start, end);
+                Err(Error::TrailingInput(tok.to_string(), rest))
+            }
+        }
+    }
+
+    fn parse_exp(&mut self) -> Result<Node<T>, Error> {
+        self.nested(|parser| parser.parse_pratt(0))
+    }
+
+    // every way down (a group, an argument, an operand) goes through here, so the depth is
+    // checked at one place
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Node<T>, Error>,
+    ) -> Result<Node<T>, Error> {
+        if self.depth == MAX_DEPTH {
+            return Err(Error::TooDeep(self.current_span()));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    // Pratt: a prefix operator or a primary, then every postfix and infix operator that binds
+    // at least as tightly as min; all of them come from the OperatorTable, none is hard coded
+    // after a left associative operator the right side has to bind tighter (1 - 2 - 3 is
+    // (1 - 2) - 3), after a right associative one the same is enough (2 ^ 3 ^ 2 is 2 ^ (3 ^ 2));
+    // a prefix operator takes what binds tighter than itself, so -2 ^ 2 is -(2 ^ 2) = -4
+    fn parse_pratt(&mut self, min: u8) -> Result<Node<T>, Error> {
+        let start = self.current_span();
+        let mut expr = match self.current_op(Fixity::Prefix) {
+            Some(op) => self.parse_prefix(op)?,
+            None => self.parse_primary()?,
+        };
+
+        // 1 + 1 + ... never goes deeper here, but every operator puts its tree one level deeper,
+        // so the height is kept as the tree grows and past MAX_DEPTH it's TooDeep at the operator
+        let mut height = Self::within_limit(expr.height(), start)?;
+        loop {
+            // 1 $ 2: the lexer already complained about $, so no new diagnostic, the operand
+            // after it goes into the Error node too (only tokenize_lossy makes Invalid)
+            if self.current_token() == Token::Invalid {
+                expr = self.skip_invalid(expr.span)?;
+                height = 1;
+                continue;
+            }
+            if let Some(op) = self.current_op(Fixity::Postfix).filter(|op| op.power >= min) {
+                expr = self.parse_postfix(op, expr, &mut height)?;
+                continue;
+            }
+            match self.current_op(Fixity::Infix).filter(|op| op.power >= min) {
+                Some(op) => expr = self.parse_infix(op, expr, &mut height)?,
+                None => return Ok(expr),
+            }
+        }
+    }
+
+    // what goes deeper is out of parse_pratt, so the frames that pile up on ----1 stay small
+
+    fn parse_prefix(&mut self, op: &OpDef) -> Result<Node<T>, Error> {
+        let start = self.current_span();
+        self.advance();
+        let tokens = &self.tokens[self.index..];
+        if let Some(value) = negative_literal(op, tokens, self.source, self.operators) {
+            let span = start.to(self.current_span());
+            self.advance();
+            return Ok(Node::new(Expression::Val(value), span));
+        }
+        //recursiv pt --x
+        let operand = self.nested(|parser| parser.parse_pratt(op.power + 1))?;
+        let span = start.to(operand.span);
+        Ok(op.node(vec![operand], span))
+    }
+
+    fn parse_postfix(
+        &mut self,
+        op: &OpDef,
+        operand: Node<T>,
+        height: &mut usize,
+    ) -> Result<Node<T>, Error> {
+        *height = Self::within_limit(*height + 1, self.current_span())?;
+        let span = operand.span.to(self.current_span());
+        self.advance();
+        Ok(op.node(vec![operand], span))
+    }
+
+    fn parse_infix(
+        &mut self,
+        op: &OpDef,
+        left: Node<T>,
+        height: &mut usize,
+    ) -> Result<Node<T>, Error> {
+        let at = self.current_span();
+        self.advance();
+        let next = match op.assoc {
+            Assoc::Left => op.power + 1,
+            Assoc::Right => op.power,
+        };
+        let right = self.nested(|parser| parser.parse_pratt(next))?;
+        *height = Self::within_limit((*height).max(right.height()) + 1, at)?;
+        let span = left.span.to(right.span);
+        Ok(op.node(vec![left, right], span))
+    }
+
+    // skips the Invalid tokens and the operand after them, the Error node covers it all
+    fn skip_invalid(&mut self, mut span: Span) -> Result<Node<T>, Error> {
+        while self.current_token() == Token::Invalid {
+            span = span.to(self.current_span());
+            self.advance();
+        }
+        if self.starts_operand() {
+            span = span.to(self.nested(|parser| parser.parse_pratt(u8::MAX))?.span);
+        }
+        Ok(Node::new(Expression::Error, span))
+    }
+
+    fn within_limit(height: usize, at: Span) -> Result<usize, Error> {
+        match height {
+            0..=MAX_DEPTH => Ok(height),
+            _ => Err(Error::TooDeep(at)),
+        }
+    }
+
+    fn current_op(&self, fixity: Fixity) -> Option<&'a OpDef> {
+        match self.current_token() {
+            Token::Op(symbol) => self.operators.find(symbol, fixity),
+            _ => None,
+        }
+    }
+
+    // consumes the ')' that matches the '(' at open and returns its span
+    // running out of input means the '(' was never closed, anything else is just unexpected
+    fn close_paren(&mut self, open: Span, expected: &'static str) -> Result<Span, Error> {
+        match self.current_token() {
+            Token::RParen => {
+                let span = self.current_span();
+                self.advance();
+                Ok(span)
+            }
+            Token::Eof => Err(Error::UnmatchedParen('(', open)),
+            tok => Err(Error::UnexpectedToken(tok.to_string(), expected, self.current_span())),
+        }
+    }
+
+    fn arguments(&mut self) -> Result<Vec<Node<T>>, Error> {
+        let mut args = Vec::new();
+        if self.current_token() != Token::RParen {
+            args.push(self.argument()?);
+            while self.current_token() == Token::Comma {
+                self.advance();
+                args.push(self.argument()?);
+            }
+        }
+        Ok(args)
+    }
+
+    // recovering, a bad argument is an Error node and the call goes on from the next ','
+    fn argument(&mut self) -> Result<Node<T>, Error> {
+        let start = self.current_span();
+        let mut arg = self.parse_exp();
+        // the 3 in f(1, 2 3) spoils the argument, not the whole call
+        if self.recovering && arg.is_ok() {
+            match self.current_token() {
+                Token::Comma | Token::RParen | Token::Eof => {}
+                tok => {
+                    let found = tok.to_string();
+                    arg = Err(Error::UnexpectedToken(found, "')' or ','", self.current_span()));
+                }
+            }
+        }
+        self.recover(arg, start, &[Token::Comma, Token::RParen])
+    }
+
+    // primary := number | 'true' | 'false' | ident | ident '(' [exp (',' exp)*] ')'
+    //          | '(' exp ')' | 'if' exp 'then' exp 'else' exp
+    fn parse_primary(&mut self) -> Result<Node<T>, Error> {
+        let span = self.current_span();
+        match self.current_token() {
+            tok @ (Token::Number(_) | Token::Imaginary(_)) => {
+                let text = &self.source[span.start..span.end];
+                let value = T::parse_literal(text).ok_or_else(|| {
+                    if T::too_big(text) {
+                        Error::Overflow(span)
+                    } else {
+                        Error::UnexpectedToken(tok.to_string(), T::LITERAL, span)
+                    }
+                })?;
+                self.advance();
+                Ok(Node::new(Expression::Val(value), span))
+            }
+            Token::Ident(name @ ("true" | "false")) => {
+                self.advance();
+                Ok(Node::new(Expression::Bool(name == "true"), span))
+            }
+            Token::Ident("if") => self.parse_if(span),
+            Token::Ident(name) if !KEYWORDS.contains(&name) => {
+                self.advance();
+                if self.current_token() != Token::LParen {
+                    return Ok(Node::new(Expression::Var(name.to_string()), span));
+                }
+                self.parse_call(name, span)
+            }
+            Token::LParen => self.parse_group(span),
+            // the lexer has already reported it
+            Token::Invalid => {
+                self.advance();
+                Ok(Node::new(Expression::Error, span))
+            }
+            tok => {
+                let expected = "a number, a variable, '(' or 'if'";
+                Err(Error::UnexpectedToken(tok.to_string(), expected, span))
+            }
+        }
+    }
+
+    // the arms of parse_primary that go deeper are functions of their own, so the frames that
+    // pile up on ((((...)))) stay small
+
+    // the else branch takes everything it can: in if c then 1 else 2 + 3 it is 2 + 3
+    fn parse_if(&mut self, span: Span) -> Result<Node<T>, Error> {
+        self.advance();
+        let cond = self.parse_exp()?;
+        self.expect(Token::Ident("then"), "'then'")?;
+        let then = self.parse_exp()?;
+        self.expect(Token::Ident("else"), "'else'")?;
+        let otherwise = self.parse_exp()?;
+        let span = span.to(otherwise.span);
+        let expr = Expression::If(Box::new(cond), Box::new(then), Box::new(otherwise));
+        Ok(Node::new(expr, span))
+    }
+
+    // the name is already consumed, we're at its '('
+    fn parse_call(&mut self, name: &str, span: Span) -> Result<Node<T>, Error> {
+        let open = self.current_span();
+        self.advance();
+
+        let call = self.arguments().and_then(|args| {
+            let span = span.to(self.close_paren(open, "')' or ','")?);
+            Ok(Node::new(Expression::Call(name.to_string(), args), span))
+        });
+        self.recover_group(call, span)
+    }
+
+    fn parse_group(&mut self, span: Span) -> Result<Node<T>, Error> {
+        self.advance();
+        //recursiv, deci merge oricat de adanc
+        let group = self.parse_exp().and_then(|mut expr| {
+            //span-ul include si parantezele
+            expr.span = span.to(self.close_paren(span, "')'")?);
+            Ok(expr)
+        });
+        self.recover_group(group, span)
+    }
+
+}
+
+//--------------SCALARS---------------
+/*
+The parser doesn't care what a number is: Parser<T> reads the same grammar for any Scalar and
+hands every literal to T::parse_literal, so with T = Complex the lexer's 2i works:
+
+    Parser::<Complex>::over("(1 + 2i) * (3 - 1i)", &operators)?.parse()?  // 5 + 5i
+
+Node<T>::eval works for any of them too (that's :as). Number is the Scalar the rest of the
+program uses, with the backends, the functions and the custom operators; i32, i64, f64 and
+Complex only get +, -, *, / and unary minus, the comparisons when they have an order, and the
+logic, which doesn't care about numbers. Anything else in the tree is an Unsupported error.
+ */
+
+trait Scalar: Clone + PartialEq + fmt::Debug + fmt::Display {
+    const NAME: &'static str; // for :as and the errors
+    const LITERAL: &'static str; // what parse_literal takes, as the parse errors say it
+
+    // a literal as written: 7, 2.5, 1e3, 2i
+    fn parse_literal(text: &str) -> Option<Self>;
+    // the literal with a minus in front, only for the ones where that fits and the digits alone
+    // don't: 2147483648 for the i32s
+    fn parse_negative(_text: &str) -> Option<Self> {
+        None
+    }
+    // the right kind of literal, only too big: 2147483648 for an i32, 1e999 for an f64
+    fn too_big(_text: &str) -> bool {
+        false
+    }
+    // a literal from the tree, as eval reads it (only Number's backends read them differently)
+    fn literal(&self, _options: &EvalOptions) -> Option<Self> {
+        Some(self.clone())
+    }
+    // a variable of the session, which holds Numbers; span is where the tree uses it
+    fn from_number(number: &Number, span: Span) -> Result<Self, Error>;
+
+    fn is_zero(&self) -> bool;
+    // None is an overflow
+    fn neg(&self, options: &EvalOptions) -> Option<Self>;
+    fn add(&self, other: &Self) -> Option<Self>;
+    fn sub(&self, other: &Self) -> Option<Self>;
+    fn mul(&self, other: &Self) -> Option<Self>;
+    // other isn't zero, apply checks that first
+    fn checked_div(&self, other: &Self) -> Option<Self>;
+
+    // a binary operator for eval, with the same spans as BinOp::apply (which Number uses)
+    fn apply(
+        op: BinOp,
+        left: &Self,
+        right: &Self,
+        _options: &EvalOptions,
+        span: Span,
+        right_span: Span,
+    ) -> Result<Self, Error> {
+        let result = match op {
+            BinOp::Add => left.add(right),
+            BinOp::Sub => left.sub(right),
+            BinOp::Mul => left.mul(right),
+            BinOp::Div if right.is_zero() => return Err(Error::DivisionByZero(right_span)),
+            BinOp::Div => left.checked_div(right),
+            _ => return Err(Error::Unsupported(format!("'{}'", op.symbol()), Self::NAME, span)),
+        };
+        result.ok_or(Error::Overflow(span))
+    }
+
+    // for the comparisons; None is unordered (NaN), no order at all is an Unsupported error
+    fn order(&self, _other: &Self, op: CmpOp, span: Span) -> Result<Option<Ordering>, Error> {
+        Err(Error::Unsupported(format!("'{}'", op.symbol()), Self::NAME, span))
+    }
+
+    // the functions and the custom operators are Number's
+    fn call(
+        _env: &mut Environment,
+        name: &str,
+        _args: &[Value<Self>],
+        span: Span,
+    ) -> Result<Value<Self>, Error> {
+        Err(Error::UnknownFunction(name.to_string(), span))
+    }
+
+    fn apply_operator(
+        _env: &mut Environment,
+        op: &CustomOp,
+        _args: &[Value<Self>],
+        span: Span,
+    ) -> Result<Value<Self>, Error> {
+        Err(Error::Unsupported(format!("'{}'", op.symbol), Self::NAME, span))
+    }
+}
+
+// -2147483648 is one literal when only the negative fits, so that it isn't an overflow before
+// the minus gets to it; tokens start at the digits, right after the minus. Only when nothing
+// after them binds tighter than the minus: -2147483648 ^ 2 stays a negation (and an overflow)
+fn negative_literal<T: Scalar>(
+    minus: &OpDef,
+    tokens: &[Lexeme],
+    source: &str,
+    operators: &OperatorTable,
+) -> Option<T> {
+    let (digits, next) = match tokens {
+        [digits, next, ..] if matches!(digits.token, Token::Number(_)) => (digits, &next.token),
+        _ => return None,
+    };
+    let binds = |fixity| match next {
+        Token::Op(symbol) => {
+            operators.find(symbol, fixity).is_some_and(|op| op.power > minus.power)
+        }
+        _ => false,
+    };
+    if !matches!(minus.kind, OpKind::Neg) || binds(Fixity::Postfix) || binds(Fixity::Infix) {
+        return None;
+    }
+    if *next == Token::Invalid {
+        return None; // parse_file puts whatever comes next in the same Error node
+    }
+    T::parse_negative(&source[digits.span.start..digits.span.end])
+}
+
+// eval's own; + - * / with the default settings when called outside of it
+impl Scalar for Number {
+    const NAME: &'static str = "number";
+    const LITERAL: &'static str = "a real number";
+
+    fn parse_literal(text: &str) -> Option<Number> {
+        match lexer::tokenize(text, &[]).ok()?.as_slice() {
+            [number, end] if end.token == Token::Eof => match &number.token {
+                Token::Number(number) => Some(number.clone()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn parse_negative(text: &str) -> Option<Number> {
+        match Number::parse_literal(text)? {
+            Number::Big(n) => i32::try_from(n.neg().to_i128()?).ok().map(Number::Int),
+            _ => None,
+        }
+    }
+
+    fn literal(&self, options: &EvalOptions) -> Option<Number> {
+        Number::literal(self, options)
+    }
+
+    fn from_number(number: &Number, _span: Span) -> Result<Number, Error> {
+        Ok(number.clone())
+    }
+
+    fn is_zero(&self) -> bool {
+        Number::is_zero(self)
+    }
+
+    fn neg(&self, options: &EvalOptions) -> Option<Number> {
+        Number::neg(self, options)
+    }
+
+    fn add(&self, other: &Number) -> Option<Number> {
+        Number::add(self, other, &EvalOptions::default())
+    }
+
+    fn sub(&self, other: &Number) -> Option<Number> {
+        Number::sub(self, other, &EvalOptions::default())
+    }
+
+    fn mul(&self, other: &Number) -> Option<Number> {
+        Number::mul(self, other, &EvalOptions::default())
+    }
+
+    fn checked_div(&self, other: &Number) -> Option<Number> {
+        Number::div(self, other, &EvalOptions::default())
+    }
+
+    fn apply(
+        op: BinOp,
+        left: &Number,
+        right: &Number,
+        options: &EvalOptions,
+        span: Span,
+        right_span: Span,
+    ) -> Result<Number, Error> {
+        op.apply(left, right, options, span, right_span)
+    }
+
+    fn order(&self, other: &Number, _op: CmpOp, _span: Span) -> Result<Option<Ordering>, Error> {
+        Ok(self.compare(other))
+    }
+
+    fn call(env: &mut Environment, name: &str, args: &[Value], span: Span) -> Result<Value, Error> {
+        env.call(name, args, span)
+    }
+
+    fn apply_operator(
+        env: &mut Environment,
+        op: &CustomOp,
+        args: &[Value],
+        span: Span,
+    ) -> Result<Value, Error> {
+        env.apply_operator(&op.symbol, op.fixity, args, span)
+    }
+}
+
+// checked, like OverflowMode::Checked; / truncates
+macro_rules! int_scalar {
+    ($int:ty, $name:expr) => {
+        impl Scalar for $int {
+            const NAME: &'static str = $name;
+            const LITERAL: &'static str = "an integer";
+
+            fn parse_literal(text: &str) -> Option<$int> {
+                text.parse().ok()
+            }
+
+            fn parse_negative(text: &str) -> Option<$int> {
+                match text.parse::<$int>() {
+                    Ok(_) => None,
+                    Err(_) => format!("-{}", text).parse().ok(),
+                }
+            }
+
+            fn too_big(text: &str) -> bool {
+                text.bytes().all(|b| b.is_ascii_digit())
+            }
+
+            // 2.5 isn't one, 5000000000 is too big for an i32
+            fn from_number(number: &Number, span: Span) -> Result<$int, Error> {
+                match number {
+                    Number::Int(_) | Number::Wide(_) | Number::Big(_) => {
+                        let n = number.as_int().and_then(|n| <$int>::try_from(n).ok());
+                        n.ok_or(Error::Overflow(span))
+                    }
+                    _ => {
+                        let found = format!("number {}", number);
+                        Err(Error::UnexpectedToken(found, Self::LITERAL, span))
+                    }
+                }
+            }
+
+            fn is_zero(&self) -> bool {
+                *self == 0
+            }
+
+            fn neg(&self, _options: &EvalOptions) -> Option<$int> {
+                self.checked_neg()
+            }
+
+            fn add(&self, other: &$int) -> Option<$int> {
+                self.checked_add(*other)
+            }
+
+            fn sub(&self, other: &$int) -> Option<$int> {
+                self.checked_sub(*other)
+            }
+
+            fn mul(&self, other: &$int) -> Option<$int> {
+                self.checked_mul(*other)
+            }
+
+            fn checked_div(&self, other: &$int) -> Option<$int> {
+                <$int>::checked_div(*self, *other) // MIN / -1
+            }
+
+            fn order(&self, other: &$int, _: CmpOp, _: Span) -> Result<Option<Ordering>, Error> {
+                Ok(self.partial_cmp(other))
+            }
+        }
+    };
+}
+
+int_scalar!(i32, "i32");
+int_scalar!(i64, "i64");
+
+// inf nu e un rezultat, e overflow (ca la Number::float)
+impl Scalar for f64 {
+    const NAME: &'static str = "f64";
+    const LITERAL: &'static str = "a real number";
+
+    fn parse_literal(text: &str) -> Option<f64> {
+        text.parse().ok().filter(|x: &f64| x.is_finite())
+    }
+
+    fn too_big(text: &str) -> bool {
+        text.parse::<f64>().is_ok()
+    }
+
+    fn from_number(number: &Number, span: Span) -> Result<f64, Error> {
+        Some(number.to_f64()).filter(|x| x.is_finite()).ok_or(Error::Overflow(span))
+    }
+
+    fn is_zero(&self) -> bool {
+        *self == 0.0
+    }
+
+    fn neg(&self, _options: &EvalOptions) -> Option<f64> {
+        Some(-self)
+    }
+
+    fn add(&self, other: &f64) -> Option<f64> {
+        Some(self + other).filter(|x| x.is_finite())
+    }
+
+    fn sub(&self, other: &f64) -> Option<f64> {
+        Some(self - other).filter(|x| x.is_finite())
+    }
+
+    fn mul(&self, other: &f64) -> Option<f64> {
+        Some(self * other).filter(|x| x.is_finite())
+    }
+
+    fn checked_div(&self, other: &f64) -> Option<f64> {
+        Some(self / other).filter(|x| x.is_finite())
+    }
+
+    fn order(&self, other: &f64, _op: CmpOp, _span: Span) -> Result<Option<Ordering>, Error> {
+        Ok(self.partial_cmp(other))
+    }
+}
+
+// a + bi, from the ex7 exercise
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Complex {
+    real: f64,
+    imaginary: f64,
+}
+
+impl Complex {
+    fn new(real: f64, imaginary: f64) -> Complex {
+        Complex { real, imaginary }
+    }
+
+    fn finite(self) -> Option<Complex> {
+        (self.real.is_finite() && self.imaginary.is_finite()).then_some(self)
+    }
+}
+
+impl Scalar for Complex {
+    const NAME: &'static str = "complex";
+    const LITERAL: &'static str = "a number or an imaginary number (2i)";
+
+    fn parse_literal(text: &str) -> Option<Complex> {
+        match text.strip_suffix('i') {
+            Some(imaginary) => f64::parse_literal(imaginary).map(|b| Complex::new(0.0, b)),
+            None => f64::parse_literal(text).map(|a| Complex::new(a, 0.0)),
+        }
+    }
+
+    fn too_big(text: &str) -> bool {
+        f64::too_big(text.strip_suffix('i').unwrap_or(text))
+    }
+
+    fn from_number(number: &Number, span: Span) -> Result<Complex, Error> {
+        f64::from_number(number, span).map(|a| Complex::new(a, 0.0))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.real == 0.0 && self.imaginary == 0.0
+    }
+
+    fn neg(&self, _options: &EvalOptions) -> Option<Complex> {
+        Some(Complex::new(-self.real, -self.imaginary))
+    }
+
+    fn add(&self, other: &Complex) -> Option<Complex> {
+        Complex::new(self.real + other.real, self.imaginary + other.imaginary).finite()
+    }
+
+    fn sub(&self, other: &Complex) -> Option<Complex> {
+        Complex::new(self.real - other.real, self.imaginary - other.imaginary).finite()
+    }
+
+    fn mul(&self, other: &Complex) -> Option<Complex> {
+        let (a, b, c, d) = (self.real, self.imaginary, other.real, other.imaginary);
+        Complex::new(a * c - b * d, a * d + b * c).finite()
+    }
+
+    // Smith's way, scaled by the bigger part of the divisor so c*c + d*d can't overflow; with
+    // d = 0 it's exactly a / c, the same as f64
+    fn checked_div(&self, other: &Complex) -> Option<Complex> {
+        let (a, b, c, d) = (self.real, self.imaginary, other.real, other.imaginary);
+        let quotient = if d.abs() <= c.abs() {
+            let (r, den) = (d / c, c + d * (d / c));
+            Complex::new((a + b * r) / den, (b - a * r) / den)
+        } else {
+            let (r, den) = (c / d, c * (c / d) + d);
+            Complex::new((a * r + b) / den, (b * r - a) / den)
+        };
+        quotient.finite()
+    }
+}
+
+// 5 + 5i, 5 - 6i, 2i, 3
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (a, b) = (self.real, self.imaginary);
+        if b == 0.0 {
+            write!(f, "{}", a)
+        } else if a == 0.0 {
+            write!(f, "{}i", b)
+        } else if b < 0.0 {
+            write!(f, "{} - {}i", a, -b)
+        } else {
+            write!(f, "{} + {}i", a, b)
+        }
+    }
+}
+
+// :as complex (1 + 2i) * (3 - 1i), with the session's variables
+fn calculate_as<T: Scalar>(text: &str, env: &mut Environment) -> Result<Value<T>, Error> {
+    let operators = env.operators.clone();
+    Parser::<T>::over(text, &operators)?.parse()?.eval(env)
+}
+
+//--------------OPERATOR TABLE---------------
+/*
+Every operator the parser knows is a row here: symbol, fixity, binding power (the same scale
+as BinOp::precedence, higher binds tighter) and associativity. The built-in rows come from
+BinOp, CmpOp and the logic operators; custom ones are registered at runtime with a closure
+that does the work:
+
+    env.operators.register_postfix("!", 10, factorial)?;   // 5! = 120
+
+A symbol can be prefix and infix, or prefix and postfix (in operand position only prefix
+makes sense), but not infix and postfix: after an operand there'd be no way to tell which.
+That's how ! is both the built-in not (!x) and the factorial main registers (x!).
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fixity {
+    Prefix,
+    Infix,
+    Postfix,
+}
+
+impl Fixity {
+    fn arity(self) -> usize {
+        match self {
+            Fixity::Infix => 2,
+            Fixity::Prefix | Fixity::Postfix => 1,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Fixity::Prefix => "prefix",
+            Fixity::Infix => "infix",
+            Fixity::Postfix => "postfix",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Fixity> {
+        [Fixity::Prefix, Fixity::Infix, Fixity::Postfix].iter().copied().find(|f| f.name() == name)
+    }
+}
+
+// what a custom operator does with its operands; an Err becomes an InvalidArgument error
+type OpFn = dyn Fn(&[Number], &EvalOptions) -> Result<Number, String>;
+
+#[derive(Clone)]
+enum OpKind {
+    Binary(BinOp),
+    Neg,
+    Plus, // +x e doar x, nu face nod
+    Compare(CmpOp),
+    And,
+    Or,
+    Not,
+    Custom(Rc<OpFn>),
+}
+
+#[derive(Clone)]
+struct OpDef {
+    symbol: String,
+    fixity: Fixity,
+    power: u8,
+    assoc: Assoc, // only infix operators look at it
+    kind: OpKind,
+}
+
+// what a node or a postfix item keeps of a custom operator: enough to find it again when it
+// runs, and to print it (with the binding power it was parsed with) without the table
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CustomOp {
+    symbol: String,
+    fixity: Fixity,
+    power: u8,
+    assoc: Assoc,
+}
+
+// closures have no Debug
+impl fmt::Debug for OpDef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (kind, fixity) = (self.kind_name(), self.fixity.name());
+        write!(f, "{} {} {} (power {})", kind, fixity, self.symbol, self.power)
+    }
+}
+
+impl OpDef {
+    fn new(symbol: &str, fixity: Fixity, power: u8, assoc: Assoc, kind: OpKind) -> OpDef {
+        OpDef {
+            symbol: symbol.to_string(),
+            fixity,
+            power,
+            assoc,
+            kind,
+        }
+    }
+
+    fn is_custom(&self) -> bool {
+        matches!(self.kind, OpKind::Custom(_))
+    }
+
+    fn custom_op(&self) -> CustomOp {
+        CustomOp {
+            symbol: self.symbol.clone(),
+            fixity: self.fixity,
+            power: self.power,
+            assoc: self.assoc,
+        }
+    }
+
+    fn kind_name(&self) -> &'static str {
+        if self.is_custom() {
+            "custom"
+        } else {
+            "built-in"
+        }
+    }
+
+    // whether it is still on top of an operand when an operator with binding power `power`
+    // comes after it, ex: in -2 ^ 2 the - is not, in -2 * 2 it is
+    fn binds_before(&self, power: u8) -> bool {
+        let left = self.fixity == Fixity::Prefix || self.assoc == Assoc::Left;
+        self.power > power || (self.power == power && left)
+    }
+
+    // the node for this operator applied to its operands (left to right)
+    fn node<T>(&self, mut operands: Vec<Node<T>>, span: Span) -> Node<T> {
+        let expr = match &self.kind {
+            OpKind::Plus => return Node::new(operands.pop().expect("one operand").expr, span),
+            OpKind::Neg => Expression::Neg(Box::new(operands.pop().expect("one operand"))),
+            OpKind::Binary(op) => {
+                let right = operands.pop().expect("two operands");
+                let left = operands.pop().expect("two operands");
+                Expression::binary(*op, left, right)
+            }
+            OpKind::Compare(_) | OpKind::And | OpKind::Or => {
+                let right = Box::new(operands.pop().expect("two operands"));
+                let left = Box::new(operands.pop().expect("two operands"));
+                match self.kind {
+                    OpKind::Compare(op) => Expression::Compare(op, left, right),
+                    OpKind::And => Expression::And(left, right),
+                    _ => Expression::Or(left, right),
+                }
+            }
+            OpKind::Not => Expression::Not(Box::new(operands.pop().expect("one operand"))),
+            OpKind::Custom(_) => Expression::Custom(self.custom_op(), operands),
+        };
+        Node::new(expr, span)
+    }
+
+    // +x has no node, so it never gets this far
+    fn rpn_item(&self) -> RpnItem {
+        match &self.kind {
+            OpKind::Binary(op) => RpnItem::Binary(*op),
+            OpKind::Neg => RpnItem::Neg,
+            OpKind::Compare(op) => RpnItem::Compare(*op),
+            OpKind::And => RpnItem::And,
+            OpKind::Or => RpnItem::Or,
+            OpKind::Not => RpnItem::Not,
+            OpKind::Custom(_) => RpnItem::Custom(self.custom_op()),
+            OpKind::Plus => unreachable!("unary plus is dropped by the parser"),
+        }
+    }
+
+    // how it is written in prefix/postfix text: built-in unary ones have a name (neg, not)
+    fn written_as_symbol(&self) -> bool {
+        !matches!(self.kind, OpKind::Neg | OpKind::Plus | OpKind::Not)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct OperatorTable {
+    defs: Vec<OpDef>,
+}
+
+impl OperatorTable {
+    // what the language comes with
+    fn standard() -> OperatorTable {
+        let mut defs: Vec<OpDef> = BinOp::ALL
+            .iter().copied()
+            .map(|op| {
+                let kind = OpKind::Binary(op);
+                OpDef::new(op.symbol(), Fixity::Infix, op.precedence(), op.associativity(), kind)
+            })
+            .collect();
+        let (pow, kind) = (BinOp::Pow.precedence(), OpKind::Binary(BinOp::Pow));
+        defs.push(OpDef::new("**", Fixity::Infix, pow, Assoc::Right, kind));
+        defs.push(OpDef::new("-", Fixity::Prefix, NEG_PRECEDENCE, Assoc::Right, OpKind::Neg));
+        defs.push(OpDef::new("+", Fixity::Prefix, NEG_PRECEDENCE, Assoc::Right, OpKind::Plus));
+        for op in CmpOp::ALL {
+            let kind = OpKind::Compare(op);
+            let power = COMPARE_PRECEDENCE;
+            defs.push(OpDef::new(op.symbol(), Fixity::Infix, power, Assoc::Left, kind));
+        }
+        defs.push(OpDef::new("&&", Fixity::Infix, AND_PRECEDENCE, Assoc::Left, OpKind::And));
+        defs.push(OpDef::new("||", Fixity::Infix, OR_PRECEDENCE, Assoc::Left, OpKind::Or));
+        defs.push(OpDef::new("!", Fixity::Prefix, NOT_PRECEDENCE, Assoc::Right, OpKind::Not));
+        OperatorTable { defs }
+    }
+
+    fn find(&self, symbol: &str, fixity: Fixity) -> Option<&OpDef> {
+        self.defs.iter().find(|def| def.symbol == symbol && def.fixity == fixity)
+    }
+
+    // main only registers the postfix !, the others are there for whoever embeds the parser
+    #[allow(dead_code)]
+    fn register_infix(
+        &mut self,
+        symbol: &str,
+        power: u8,
+        assoc: Assoc,
+        func: impl Fn(&[Number], &EvalOptions) -> Result<Number, String> + 'static,
+    ) -> Result<(), String> {
+        let kind = OpKind::Custom(Rc::new(func));
+        self.register(OpDef::new(symbol, Fixity::Infix, power, assoc, kind))
+    }
+
+    #[allow(dead_code)]
+    fn register_prefix(
+        &mut self,
+        symbol: &str,
+        power: u8,
+        func: impl Fn(&[Number], &EvalOptions) -> Result<Number, String> + 'static,
+    ) -> Result<(), String> {
+        let kind = OpKind::Custom(Rc::new(func));
+        self.register(OpDef::new(symbol, Fixity::Prefix, power, Assoc::Right, kind))
+    }
+
+    fn register_postfix(
+        &mut self,
+        symbol: &str,
+        power: u8,
+        func: impl Fn(&[Number], &EvalOptions) -> Result<Number, String> + 'static,
+    ) -> Result<(), String> {
+        let kind = OpKind::Custom(Rc::new(func));
+        self.register(OpDef::new(symbol, Fixity::Postfix, power, Assoc::Left, kind))
+    }
+
+    // registering a symbol again with the same fixity replaces the old custom operator
+    fn register(&mut self, def: OpDef) -> Result<(), String> {
+        // (, ), "," and = mean something else already; letters and digits would eat names
+        let allowed = |ch: char| ch.is_ascii_punctuation() && !"(),=._".contains(ch);
+        if def.symbol.is_empty() || !def.symbol.chars().all(allowed) {
+            return Err(format!("'{}' can't be an operator, use punctuation", def.symbol));
+        }
+        if !(1..ATOM_PRECEDENCE).contains(&def.power) {
+            return Err(format!("binding power {} is out of range", def.power));
+        }
+
+        let mut replaced = None;
+        for (i, old) in self.defs.iter().enumerate().filter(|(_, old)| old.symbol == def.symbol) {
+            if old.fixity == def.fixity && old.is_custom() {
+                replaced = Some(i);
+            } else if old.fixity == def.fixity || old.written_as_symbol() {
+                // the second case: in postfix text both would be the same word (and if one of
+                // them is infix and the other postfix, the parser couldn't tell either)
+                let (kind, fixity) = (old.kind_name(), old.fixity.name());
+                return Err(format!("'{}' is already a {} {} operator", def.symbol, kind, fixity));
+            }
+        }
+        match replaced {
+            Some(i) => self.defs[i] = def,
+            None => self.defs.push(def),
+        }
+        Ok(())
+    }
+
+    // what the lexer has to split off, longest first so "**" wins over "*"
+    fn symbols(&self) -> Vec<&str> {
+        let mut symbols: Vec<&str> = self.defs.iter().map(|def| def.symbol.as_str()).collect();
+        symbols.push("="); // let si fn
+        symbols.sort_by_key(|symbol| std::cmp::Reverse(symbol.len()));
+        symbols.dedup();
+        symbols
+    }
+}
+
+// the example custom operator main registers: 5! = 120
+fn factorial(args: &[Number], options: &EvalOptions) -> Result<Number, String> {
+    match args[0].as_int() {
+        Some(n @ 0..=1000) => (2..=n).try_fold(Number::Int(1), |product, k| {
+            product.mul(&Number::Int(k as i32), options).ok_or_else(|| format!("{}! overflows", n))
+        }),
+        _ => Err("factorial needs an integer between 0 and 1000".to_string()),
+    }
+}
+
+//--------------SHUNTING YARD & RPN---------------
+/*
+The parser above builds a tree; Shunting Yard goes straight from the infix tokens to a
+postfix queue with a stack of pending operators. An operator waits on the stack until
+something with lower precedence (or a ')') shows up, then it goes to the output.
+The postfix queue is then evaluated with a stack of values, no tree needed.
+
+In postfix/prefix text unary minus is written "neg", ! is "not" and a call is written
+name/arity, so "1 2 3 max/3" calls max with three arguments. A conditional is "if" after its
+three operands: "x 0 > x x neg if" is if x > 0 then x else -x.
+
+Shunting Yard treats if/then/else like brackets: "if" opens, "then" and "else" close what came
+before them, and the else branch stays open (below every operator) until a ')', a ',' or the
+end closes it. Evaluating RPN is eager, both branches of an if and both sides of && and ||
+are computed, so errors are kept on the stack as values until something actually uses them;
+if true then 1 else 1 / 0 is 1 here too, same as with the tree.
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+enum RpnItem {
+    Num(Number),
+    Var(String),
+    Binary(BinOp),
+    Neg,
+    Call(String, usize),
+    Custom(CustomOp),
+    Bool(bool),
+    Compare(CmpOp),
+    And,
+    Or,
+    Not,
+    If, // cond, then, else
+    Error, // an Expression::Error
+}
+
+impl RpnItem {
+    // how many values it takes off the stack
+    fn operands(&self) -> usize {
+        match self {
+            RpnItem::Num(_) | RpnItem::Var(_) | RpnItem::Bool(_) | RpnItem::Error => 0,
+            RpnItem::Neg | RpnItem::Not => 1,
+            RpnItem::Binary(_) | RpnItem::Compare(_) | RpnItem::And | RpnItem::Or => 2,
+            RpnItem::If => 3,
+            RpnItem::Call(_, arity) => *arity,
+            RpnItem::Custom(op) => op.fixity.arity(),
+        }
+    }
+}
+
+impl fmt::Display for RpnItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpnItem::Num(number) => match number.as_decimal() {
+                Some(text) => write!(f, "{}", text),
+                None => write!(f, "{}", number),
+            },
+            RpnItem::Var(name) => write!(f, "{}", name),
+            RpnItem::Binary(op) => write!(f, "{}", op.symbol()),
+            RpnItem::Neg => write!(f, "neg"),
+            RpnItem::Call(name, arity) => write!(f, "{}/{}", name, arity),
+            RpnItem::Custom(op) => write!(f, "{}", op.symbol),
+            RpnItem::Bool(b) => write!(f, "{}", b),
+            RpnItem::Compare(op) => write!(f, "{}", op.symbol()),
+            RpnItem::And => write!(f, "&&"),
+            RpnItem::Or => write!(f, "||"),
+            RpnItem::Not => write!(f, "not"),
+            RpnItem::If => write!(f, "if"),
+            RpnItem::Error => write!(f, "<error>"),
+        }
+    }
+}
+
+// what waits on the operator stack
+enum Pending<'a> {
+    Op(&'a OpDef, Span), // infix or prefix, postfix ones go straight to the output
+    Paren(Span),
+    Call(&'a str, Span, usize), // the '(' of a call, with the arguments counted so far
+    // the span of the 'if' in all three
+    If(Span),   // waiting for its 'then'
+    Then(Span), // waiting for its 'else'
+    Else(Span), // complete once the else branch is, whatever closes that
+}
+
+// moves the operator to the output; parens and unfinished ifs never get here
+fn emit(pending: Pending, out: &mut Vec<(RpnItem, Span)>, close: Span) {
+    match pending {
+        Pending::Op(op, span) => out.push((op.rpn_item(), span)),
+        Pending::Call(name, span, args) => {
+            out.push((RpnItem::Call(name.to_string(), args), span.to(close)));
+        }
+        Pending::Else(span) => out.push((RpnItem::If, span.to(close))),
+        Pending::Paren(_) | Pending::If(_) | Pending::Then(_) => {
+            unreachable!("only ')', 'then' and 'else' pop these")
+        }
+    }
+}
+
+// pops operators to the output until the '(', call, 'if' or 'then' on top of the stack,
+// which is left there
+fn pop_until_paren(ops: &mut Vec<Pending>, out: &mut Vec<(RpnItem, Span)>, close: Span) {
+    while let Some(top) = ops.pop() {
+        let open = matches!(top, Pending::Paren(_) | Pending::Call(..));
+        if open || matches!(top, Pending::If(_) | Pending::Then(_)) {
+            ops.push(top);
+            return;
+        }
+        emit(top, out, close);
+    }
+}
+
+// pops what binds before an operator of binding power `power`, ex: * before + comes along
+fn pop_stronger(ops: &mut Vec<Pending>, out: &mut Vec<(RpnItem, Span)>, power: u8, at: Span) {
+    while let Some(Pending::Op(top, _)) = ops.last() {
+        if !top.binds_before(power) {
+            break;
+        }
+        let top = ops.pop().expect("it was just there");
+        emit(top, out, at);
+    }
+}
+
+fn shunting_yard<'a>(
+    input: &'a str,
+    operators: &'a OperatorTable,
+) -> Result<Vec<(RpnItem, Span)>, Error> {
+    let tokens = lexer::tokenize(input, &operators.symbols())?;
+    let mut out = Vec::new();
+    let mut ops: Vec<Pending> = Vec::new();
+    // dupa un operator sau '(' vine un operand, dupa un operand vine un operator
+    let mut expect_operand = true;
+
+    let mut i = 0;
+    while i < tokens.len() {
+        let Lexeme { token, span } = tokens[i].clone();
+        // every '(', call, if and operator waiting here is a level the parser would be down
+        if ops.len() == MAX_DEPTH {
+            return Err(Error::TooDeep(span));
+        }
+
+        // in pozitie de operand doar prefix, dupa un operand infix sau postfix
+        let op = match token {
+            Token::Op(symbol) if expect_operand => operators.find(symbol, Fixity::Prefix),
+            Token::Op(symbol) => operators
+                .find(symbol, Fixity::Postfix)
+                .or_else(|| operators.find(symbol, Fixity::Infix)),
+            _ => None,
+        };
+        if let Some(op) = op {
+            match op.fixity {
+                Fixity::Prefix if matches!(op.kind, OpKind::Plus) => {}
+                Fixity::Prefix => ops.push(Pending::Op(op, span)), // prefix ops don't pop anything
+                Fixity::Postfix => {
+                    // its operand is already out, so it can go right after it
+                    pop_stronger(&mut ops, &mut out, op.power, span);
+                    out.push((op.rpn_item(), span));
+                }
+                Fixity::Infix => {
+                    pop_stronger(&mut ops, &mut out, op.power, span);
+                    ops.push(Pending::Op(op, span));
+                    expect_operand = true;
+                }
+            }
+            i += 1;
+            continue;
+        }
+
+        match token {
+            Token::Number(number) if expect_operand => {
+                // the minus right before it, -2147483648 (see negative_literal)
+                let folded = match ops.last() {
+                    Some(&Pending::Op(minus, at)) if tokens[i - 1].span == at => {
+                        negative_literal(minus, &tokens[i..], input, operators).map(|n| (n, at))
+                    }
+                    _ => None,
+                };
+                match folded {
+                    Some((negative, at)) => {
+                        ops.pop();
+                        out.push((RpnItem::Num(negative), at.to(span)));
+                    }
+                    None => out.push((RpnItem::Num(number), span)),
+                }
+                expect_operand = false;
+            }
+            Token::Ident(name @ ("true" | "false")) if expect_operand => {
+                out.push((RpnItem::Bool(name == "true"), span));
+                expect_operand = false;
+            }
+            Token::Ident("if") if expect_operand => ops.push(Pending::If(span)),
+            Token::Ident(word @ ("then" | "else")) if !expect_operand => {
+                pop_until_paren(&mut ops, &mut out, span);
+                match (word, ops.pop()) {
+                    ("then", Some(Pending::If(open))) => ops.push(Pending::Then(open)),
+                    ("else", Some(Pending::Then(open))) => ops.push(Pending::Else(open)),
+                    _ => {
+                        let found = token.to_string();
+                        return Err(Error::UnexpectedToken(found, "an operator or ')'", span));
+                    }
+                }
+                expect_operand = true;
+            }
+            Token::Ident(name) if expect_operand && !KEYWORDS.contains(&name) => {
+                if tokens[i + 1].token == Token::LParen {
+                    ops.push(Pending::Call(name, span, 0));
+                    i += 1; //sar peste '('
+                } else {
+                    out.push((RpnItem::Var(name.to_string()), span));
+                    expect_operand = false;
+                }
+            }
+            Token::LParen if expect_operand => ops.push(Pending::Paren(span)),
+            Token::Comma if !expect_operand => {
+                pop_until_paren(&mut ops, &mut out, span);
+                match ops.last_mut() {
+                    Some(Pending::Call(_, _, args)) => *args += 1,
+                    _ => {
+                        let found = token.to_string();
+                        return Err(Error::UnexpectedToken(found, "an operator or ')'", span));
+                    }
+                }
+                expect_operand = true;
+            }
+            // f() is the only place where ')' can come right where an operand should be
+            Token::RParen if !expect_operand || (i > 0 && tokens[i - 1].token == Token::LParen) => {
+                pop_until_paren(&mut ops, &mut out, span);
+                match ops.pop() {
+                    // () has nothing in it, the parser says the same
+                    Some(Pending::Paren(_)) if expect_operand => {
+                        let expected = "a number, a variable, '(' or 'if'";
+                        return Err(Error::UnexpectedToken(token.to_string(), expected, span));
+                    }
+                    Some(Pending::Paren(_)) => {}
+                    Some(Pending::Call(name, call_span, args)) => {
+                        let args = if expect_operand { args } else { args + 1 };
+                        emit(Pending::Call(name, call_span, args), &mut out, span);
+                    }
+                    Some(Pending::If(_)) => {
+                        return Err(Error::UnexpectedToken(token.to_string(), "'then'", span));
+                    }
+                    Some(Pending::Then(_)) => {
+                        return Err(Error::UnexpectedToken(token.to_string(), "'else'", span));
+                    }
+                    _ => return Err(Error::UnmatchedParen(')', span)),
+                }
+                expect_operand = false;
+            }
+            Token::Eof if !expect_operand => {
+                while let Some(top) = ops.pop() {
+                    match top {
+                        Pending::Paren(open) | Pending::Call(_, open, _) => {
+                            return Err(Error::UnmatchedParen('(', open));
+                        }
+                        Pending::If(_) => {
+                            return Err(Error::UnexpectedToken(token.to_string(), "'then'", span));
+                        }
+                        Pending::Then(_) => {
+                            return Err(Error::UnexpectedToken(token.to_string(), "'else'", span));
+                        }
+                        _ => emit(top, &mut out, span),
+                    }
+                }
+            }
+            _ => {
+                let expected = if expect_operand {
+                    "a number, a variable, '(' or 'if'"
+                } else {
+                    "an operator or ')'"
+                };
+                return Err(Error::UnexpectedToken(token.to_string(), expected, span));
+            }
+        }
+        i += 1;
+    }
+    within_depth(&out)?; //1 + 1 + ... nu tine nimic in asteptare, dar arborele tot creste
+    Ok(out)
+}
+
+// runs postfix items through a stack: leaves go on the stack, an operator replaces its
+// operands (in left to right order) with whatever combine makes of them
+fn fold_postfix<T>(
+    items: &[(RpnItem, Span)],
+    mut combine: impl FnMut(&RpnItem, Vec<T>, Span) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let mut stack: Vec<T> = Vec::new();
+    for (item, span) in items {
+        let needed = item.operands();
+        if stack.len() < needed {
+            return Err(Error::MissingOperand(item.to_string(), *span));
+        }
+        let operands = stack.split_off(stack.len() - needed);
+        stack.push(combine(item, operands, *span)?);
+    }
+
+    let end = items.last().map_or(Span::default(), |(_, span)| Span::new(span.end, span.end));
+    match stack.len() {
+        1 => Ok(stack.pop().expect("one value")),
+        0 => Err(Error::MissingOperand("the expression".to_string(), end)),
+        _ => Err(Error::ExtraOperand(stack.len(), end)),
+    }
+}
+
+// the tree the items make is held to MAX_DEPTH levels like a parsed one, TooDeep at the item
+// that goes over
+fn within_depth(items: &[(RpnItem, Span)]) -> Result<(), Error> {
+    fold_postfix(items, |_, operands: Vec<usize>, span| {
+        match operands.into_iter().max().unwrap_or(0) + 1 {
+            height @ 0..=MAX_DEPTH => Ok(height),
+            _ => Err(Error::TooDeep(span)),
+        }
+    })
+    .map(|_| ())
+}
+
+// the stack holds results, not values: an error only counts once something uses it
+fn eval_postfix(items: &[(RpnItem, Span)], env: &mut Environment) -> Result<Value, Error> {
+    fold_postfix(items, |item, operands, span| Ok(eval_item(item, operands, span, env)))?
+}
+
+fn eval_item(
+    item: &RpnItem,
+    operands: Vec<Result<Value, Error>>,
+    span: Span,
+    env: &mut Environment,
+) -> Result<Value, Error> {
+    match item {
+        RpnItem::And | RpnItem::Or => {
+            let [left, right]: [_; 2] = operands.try_into().expect("two operands");
+            let stop = matches!(item, RpnItem::Or); // || stops at true, && at false
+            if left?.boolean(span)? == stop {
+                return Ok(Value::Bool(stop));
+            }
+            return right?.boolean(span).map(Value::Bool);
+        }
+        RpnItem::If => {
+            let [cond, then, otherwise]: [_; 3] = operands.try_into().expect("three operands");
+            return if cond?.boolean(span)? { then } else { otherwise };
+        }
+        _ => {}
+    }
+
+    // the rest needs all of its operands
+    let values = operands.into_iter().collect::<Result<Vec<Value>, Error>>()?;
+    let num = |i: usize| values[i].clone().number(span);
+    match item {
+        RpnItem::Num(number) => {
+            number.literal(&env.options).map(Value::Num).ok_or(Error::Overflow(span))
+        }
+        RpnItem::Var(name) => {
+            env.get(name).ok_or_else(|| Error::UnknownVariable(name.clone(), span))
+        }
+        RpnItem::Binary(op) => {
+            op.apply(&num(0)?, &num(1)?, &env.options, span, span).map(Value::Num)
+        }
+        RpnItem::Neg => num(0)?.neg(&env.options).map(Value::Num).ok_or(Error::Overflow(span)),
+        RpnItem::Call(name, _) => env.call(name, &values, span),
+        RpnItem::Custom(op) => env.apply_operator(&op.symbol, op.fixity, &values, span),
+        RpnItem::Bool(b) => Ok(Value::Bool(*b)),
+        RpnItem::Error => Err(Error::Unparsed(span)),
+        RpnItem::Compare(op) => op.apply(&values[0], &values[1], span).map(Value::Bool),
+        RpnItem::Not => values[0].boolean(span).map(|b| Value::Bool(!b)),
+        RpnItem::And | RpnItem::Or | RpnItem::If => unreachable!("handled above"),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Notation {
+    Infix,
+    Prefix,
+    Postfix,
+    Sexpr, // (+ 1 (* 2 x))
+}
+
+impl Notation {
+    fn from_name(name: &str) -> Option<Notation> {
+        match name {
+            "infix" => Some(Notation::Infix),
+            "prefix" => Some(Notation::Prefix),
+            "postfix" | "rpn" => Some(Notation::Postfix),
+            "sexpr" => Some(Notation::Sexpr),
+            _ => None,
+        }
+    }
+
+    // every notation is read into postfix items and written back out from them,
+    // so any direction is read + write
+    fn read(self, text: &str, operators: &OperatorTable) -> Result<Vec<(RpnItem, Span)>, Error> {
+        match self {
+            Notation::Infix => shunting_yard(text, operators),
+            Notation::Postfix => {
+                let items = read_words(text, operators)?;
+                within_depth(&items)?; //si verific ca e bine format
+                Ok(items)
+            }
+            Notation::Prefix => {
+                // read backwards, prefix is postfix in a mirror: "+ 1 2" reversed is "2 1 +"
+                let mut items = read_words(text, operators)?;
+                items.reverse();
+                let items =
+                    fold_postfix(&items, |item, mut operands: Vec<Vec<(RpnItem, Span)>>, span| {
+                        operands.reverse(); //au venit in oglinda
+                        let mut postfix: Vec<_> = operands.into_iter().flatten().collect();
+                        postfix.push((item.clone(), span));
+                        Ok(postfix)
+                    })?;
+                within_depth(&items)?;
+                Ok(items)
+            }
+            Notation::Sexpr => {
+                let mut items = Vec::new();
+                read_sexpr(text, operators)?.postfix(&mut items);
+                Ok(items)
+            }
+        }
+    }
+
+    fn write(self, items: &[(RpnItem, Span)]) -> Result<String, Error> {
+        match self {
+            Notation::Postfix => {
+                let words: Vec<String> = items.iter().map(|(item, _)| item.to_string()).collect();
+                Ok(words.join(" "))
+            }
+            Notation::Prefix => fold_postfix(items, |item, operands, _| {
+                let mut words = vec![item.to_string()];
+                words.extend(operands);
+                Ok(words.join(" "))
+            }),
+            Notation::Infix => {
+                let printed = fold_postfix(items, |item, operands, _| {
+                    Ok(infix_text(item, operands))
+                })?;
+                Ok(printed.text)
+            }
+            Notation::Sexpr => {
+                fold_postfix(items, |item, operands, _| Ok(sexpr_text(item, operands)))
+            }
+        }
+    }
+}
+
+// the precedence of an operand that no operator can split
+const ATOM_PRECEDENCE: u8 = u8::MAX;
+
+// an operand as infix text, with how tightly its loosest operator binds; one that starts with
+// a prefix operator (or is an if) can come right after another operator without parens: --x,
+// 2 ^ -1, 1 + if c then 2 else 3
+#[derive(Debug, Clone)]
+struct Printed {
+    text: String,
+    precedence: u8,
+    prefix: bool,
+}
+
+impl Printed {
+    fn atom(text: String) -> Printed {
+        Printed {
+            text,
+            precedence: ATOM_PRECEDENCE,
+            prefix: false,
+        }
+    }
+}
+
+// puts in only the parens the precedence asks for; the operand on the side an operator doesn't
+// associate to keeps them at the same precedence, so 1 - (2 - 3) and (2 ^ 3) ^ 2 stay as they were
+// a prefix operator keeps them on an operand of its own binding power: -(a @ b) when @ binds
+// like the minus
+fn infix_text(item: &RpnItem, operands: Vec<Printed>) -> Printed {
+    // the text to put in and its precedence there; in -!a == b the ! still takes everything
+    // after it, so the whole thing is only as tight as the loosest prefix operator in it
+    let wrap = |operand: &Printed, min: u8, after_operator: bool| {
+        if operand.precedence >= min || (after_operator && operand.prefix) {
+            (operand.text.clone(), operand.precedence)
+        } else {
+            (format!("({})", operand.text), ATOM_PRECEDENCE)
+        }
+    };
+    let infix = |symbol: &str, p: u8, assoc: Assoc| {
+        let (left_min, right_min) = match assoc {
+            Assoc::Left => (p, p + 1),
+            Assoc::Right => (p + 1, p),
+        };
+        let (left, _) = wrap(&operands[0], left_min, false);
+        let (right, right_precedence) = wrap(&operands[1], right_min, true);
+        Printed {
+            text: format!("{} {} {}", left, symbol, right),
+            precedence: p.min(right_precedence),
+            prefix: false,
+        }
+    };
+    let prefix = |symbol: &str, p: u8| {
+        let (operand, precedence) = wrap(&operands[0], p + 1, true);
+        Printed {
+            text: format!("{}{}", symbol, operand),
+            precedence: p.min(precedence),
+            prefix: true,
+        }
+    };
+
+    match item {
+        // a fraction (only the simplifier puts one in a tree) reads back as a division: (1/3) ^ 2
+        RpnItem::Num(number @ Number::Ratio(_)) if number.as_decimal().is_none() => Printed {
+            text: number.to_string(),
+            precedence: BinOp::Div.precedence(),
+            prefix: false,
+        },
+        RpnItem::Num(_) => {
+            // -2 reads back as a negation, which matters under a ^: (-2) ^ 2
+            let text = item.to_string();
+            let negative = text.starts_with('-');
+            let precedence = if negative { NEG_PRECEDENCE } else { ATOM_PRECEDENCE };
+            Printed { text, precedence, prefix: negative }
+        }
+        RpnItem::Var(name) => Printed::atom(name.clone()),
+        RpnItem::Binary(op) => infix(op.symbol(), op.precedence(), op.associativity()),
+        RpnItem::Neg => prefix("-", NEG_PRECEDENCE),
+        RpnItem::Call(name, _) => {
+            let args: Vec<&str> = operands.iter().map(|arg| arg.text.as_str()).collect();
+            Printed::atom(format!("{}({})", name, args.join(", ")))
+        }
+        RpnItem::Custom(op) => match op.fixity {
+            Fixity::Prefix => prefix(&op.symbol, op.power),
+            Fixity::Postfix => Printed {
+                text: format!("{}{}", wrap(&operands[0], op.power + 1, false).0, op.symbol),
+                precedence: op.power,
+                prefix: false,
+            },
+            Fixity::Infix => infix(&op.symbol, op.power, op.assoc),
+        },
+        RpnItem::Bool(b) => Printed::atom(b.to_string()),
+        RpnItem::Error => Printed::atom(item.to_string()),
+        RpnItem::Compare(op) => infix(op.symbol(), COMPARE_PRECEDENCE, Assoc::Left),
+        RpnItem::And => infix("&&", AND_PRECEDENCE, Assoc::Left),
+        RpnItem::Or => infix("||", OR_PRECEDENCE, Assoc::Left),
+        RpnItem::Not => prefix("!", NOT_PRECEDENCE),
+        // keywords around every part, so no parens inside; the else branch takes whatever comes
+        // after it, so it's like a prefix operator that binds looser than anything
+        RpnItem::If => {
+            let [cond, then, otherwise] = [0, 1, 2].map(|i| &operands[i].text);
+            Printed {
+                text: format!("if {} then {} else {}", cond, then, otherwise),
+                precedence: 0,
+                prefix: true,
+            }
+        }
+    }
+}
+
+// postfix/prefix text: words separated by whitespace
+fn read_words(text: &str, operators: &OperatorTable) -> Result<Vec<(RpnItem, Span)>, Error> {
+    let mut items = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(|ch: char| !ch.is_whitespace()) {
+        let len = rest[start..].find(char::is_whitespace).unwrap_or(rest.len() - start);
+        let word = &rest[start..start + len];
+        let offset = text.len() - rest.len() + start;
+        let span = Span::new(offset, offset + len);
+        items.push((read_word(word, span, operators)?, span));
+        rest = &rest[start + len..];
+    }
+    Ok(items)
+}
+
+fn read_word(word: &str, span: Span, operators: &OperatorTable) -> Result<RpnItem, Error> {
+    if let Some(op) = BinOp::from_symbol(word) {
+        return Ok(RpnItem::Binary(op));
+    }
+    if let Some(op) = CmpOp::from_symbol(word) {
+        return Ok(RpnItem::Compare(op));
+    }
+    match word {
+        "neg" => return Ok(RpnItem::Neg),
+        "not" => return Ok(RpnItem::Not),
+        "&&" => return Ok(RpnItem::And),
+        "||" => return Ok(RpnItem::Or),
+        "if" => return Ok(RpnItem::If),
+        "true" | "false" => return Ok(RpnItem::Bool(word == "true")),
+        _ => {}
+    }
+    // custom operators are written as their symbol, register made sure only one of them is
+    if let Some(op) = operators.defs.iter().find(|op| op.is_custom() && op.symbol == word) {
+        return Ok(op.rpn_item());
+    }
+    if let Some((name, arity)) = word.rsplit_once('/') {
+        if let Ok(arity) = arity.parse::<usize>() {
+            return Ok(RpnItem::Call(name.to_string(), arity));
+        }
+    }
+
+    // the rest is a number (negative ones are fine here) or a name, which the lexer knows
+    let tokens: Vec<Token> = lexer::tokenize(word, &["-"])
+        .map_err(|e| match e {
+            Error::InvalidCharacter(ch, at) => {
+                Error::InvalidCharacter(ch, Span::new(span.start + at.start, span.start + at.end))
+            }
+            _ => Error::Overflow(span),
+        })?
+        .iter()
+        .map(|lexeme| lexeme.token.clone())
+        .collect();
+    match &tokens[..] {
+        [Token::Number(number), Token::Eof] => Ok(RpnItem::Num(number.clone())),
+        [Token::Op("-"), Token::Number(number), Token::Eof] => {
+            // negated without a size limit, like any literal it's eval that decides if it fits
+            let exact = EvalOptions { backend: Backend::BigInt, ..EvalOptions::default() };
+            number.neg(&exact).map(RpnItem::Num).ok_or(Error::Overflow(span))
+        }
+        [Token::Ident(name), Token::Eof] => Ok(RpnItem::Var(name.to_string())),
+        _ => {
+            let expected = "a number, a name, an operator, neg, not, if or name/arity";
+            Err(Error::UnexpectedToken(format!("'{}'", word), expected, span))
+        }
+    }
+}
+
+//--------------TRACER---------------
+
+// one reduction: the part that got worked out, what it turned into, and the whole expression
+// right after
+#[derive(Debug, Clone)]
+struct Step {
+    before: Node,
+    after: Node,
+    whole: Node,
+}
+
+// evaluates like Node::eval, in the same order and with the same errors, but also writes every
+// value back into its own copy of the tree so a step can show all of the expression
+// a user function is one step, f(3) -> 9; its body isn't traced
+struct Tracer<'a> {
+    env: &'a mut Environment,
+    tree: Node,
+    steps: Vec<Step>,
+}
+
+impl Tracer<'_> {
+    fn at(&mut self, path: &[usize]) -> &mut Node {
+        path.iter().fold(&mut self.tree, |node, &i| node.expr.children_mut().swap_remove(i))
+    }
+
+    // the value of the node at path, which is then replaced by it
+    fn eval(&mut self, path: &mut Vec<usize>) -> Result<Value, Error> {
+        let node = self.at(path).clone();
+        let span = node.span;
+        let options = self.env.options;
+        let value = match &node.expr {
+            Expression::Val(number) => {
+                let value = number.literal(&options).ok_or(Error::Overflow(span))?;
+                Value::Num(value) // a step only if the backend reads it as something else
+            }
+            Expression::Bool(b) => return Ok(Value::Bool(*b)),
+            Expression::Error => return Err(Error::Unparsed(span)),
+            Expression::Var(name) => {
+                self.env.get(name).ok_or_else(|| Error::UnknownVariable(name.clone(), span))?
+            }
+            Expression::Add(left, right)
+            | Expression::Sub(left, right)
+            | Expression::Mul(left, right)
+            | Expression::Div(left, right)
+            | Expression::FloorDiv(left, right)
+            | Expression::Mod(left, right)
+            | Expression::Pow(left, right) => {
+                let (op, _, _) = node.expr.as_binary().expect("a binary node");
+                let l = self.operand(path, 0)?.number(left.span)?;
+                let r = self.operand(path, 1)?.number(right.span)?;
+                Value::Num(op.apply(&l, &r, &options, span, right.span)?)
+            }
+            Expression::Neg(operand) => {
+                let value = self.operand(path, 0)?.number(operand.span)?;
+                Value::Num(value.neg(&options).ok_or(Error::Overflow(span))?)
+            }
+            Expression::Call(name, args) => {
+                let values = self.operands(path, args.len())?;
+                self.env.call(name, &values, span)?
+            }
+            Expression::Custom(op, operands) => {
+                let values = self.operands(path, operands.len())?;
+                self.env.apply_operator(&op.symbol, op.fixity, &values, span)?
+            }
+            Expression::Compare(op, _, _) => {
+                let (l, r) = (self.operand(path, 0)?, self.operand(path, 1)?);
+                Value::Bool(op.apply(&l, &r, span)?)
+            }
+            Expression::And(left, right) | Expression::Or(left, right) => {
+                let stop = matches!(node.expr, Expression::Or(..)); // || stops at true, && at false
+                if self.operand(path, 0)?.boolean(left.span)? == stop {
+                    Value::Bool(stop)
+                } else {
+                    Value::Bool(self.operand(path, 1)?.boolean(right.span)?)
+                }
+            }
+            Expression::Not(operand) => {
+                Value::Bool(!self.operand(path, 0)?.boolean(operand.span)?)
+            }
+            // the if goes away and leaves the branch, which is then worked out in its place
+            Expression::If(cond, then, otherwise) => {
+                let pick = self.operand(path, 0)?.boolean(cond.span)?;
+                let branch = if pick { then } else { otherwise };
+                self.replace(path, (**branch).clone());
+                return self.eval(path);
+            }
+        };
+
+        let after = match &value {
+            Value::Num(number) => Expression::Val(number.clone()),
+            Value::Bool(b) => Expression::Bool(*b),
+        };
+        self.replace(path, Node::new(after, span));
+        Ok(value)
+    }
+
+    fn operand(&mut self, path: &mut Vec<usize>, i: usize) -> Result<Value, Error> {
+        path.push(i);
+        let value = self.eval(path);
+        path.pop();
+        value
+    }
+
+    // all of them, left to right, stopping at the first error
+    fn operands(&mut self, path: &mut Vec<usize>, count: usize) -> Result<Vec<Value>, Error> {
+        (0..count).map(|i| self.operand(path, i)).collect()
+    }
+
+    // a literal that reads as itself (-3 is Neg(3) until it's worked out) doesn't count as a step
+    fn replace(&mut self, path: &[usize], after: Node) {
+        let before = std::mem::replace(self.at(path), after.clone());
+        if before.to_string() != after.to_string() {
+            let whole = self.tree.clone();
+            self.steps.push(Step { before, after, whole });
+        }
+    }
+}
+
+// the whole expression after every step, and on the right what that step did:
+//   2 + 3 * 4
+// → 2 + 12       3 * 4 = 12
+// → 14           2 + 12 = 14
+fn trace_text(start: &Node, steps: &[Step], result: &Result<Value, Error>) -> String {
+    let wholes: Vec<String> = steps.iter().map(|step| step.whole.to_string()).collect();
+    let width = wholes.iter().map(|whole| whole.chars().count()).max().unwrap_or(0);
+    let mut lines = vec![format!("  {}", start)];
+    for (step, whole) in steps.iter().zip(&wholes) {
+        lines.push(format!("→ {:<width$}   {} = {}", whole, step.before, step.after));
+    }
+    if let Err(e) = result {
+        lines.push(format!("→ error: {}", e));
+    }
+    lines.join("\n")
+}
+
+//--------------TREE VIEWS---------------
+
+// what a node shows in a drawing: the operator or the value, without the operands
+fn label(expr: &Expression) -> String {
+    match expr {
+        Expression::Call(name, _) => format!("{}()", name),
+        _ => expr.rpn_item().to_string(),
+    }
+}
+
+// the operands of an if are named on their edges, the others go left to right
+fn roles(expr: &Expression) -> &'static [&'static str] {
+    match expr {
+        Expression::If(..) => &["cond", "then", "else"],
+        _ => &[],
+    }
+}
+
+// the syntax tree with box-drawing lines, for the terminal:
+// +
+// ├── 2
+// └── *
+//     ├── 3
+//     └── 4
+fn ascii_tree(expr: &Expression) -> String {
+    let mut out = String::new();
+    ascii_lines(expr, "", "", &mut out);
+    out
+}
+
+// first goes before the label, rest before every line of the operands
+fn ascii_lines(expr: &Expression, first: &str, rest: &str, out: &mut String) {
+    out.push_str(&format!("{}{}\n", first, label(expr)));
+    let children = expr.children();
+    for (i, child) in children.iter().enumerate() {
+        let (branch, indent) = if i + 1 == children.len() {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+        let role = roles(expr).get(i).map_or(String::new(), |role| format!("{}: ", role));
+        let first = format!("{}{}{}", rest, branch, role);
+        ascii_lines(&child.expr, &first, &format!("{}{}", rest, indent), out);
+    }
+}
+
+// Graphviz, for dot -Tsvg: leaves are boxes, operators are coloured by what they work on, and
+// ordering=out keeps the left operand on the left
+fn dot(expr: &Expression) -> String {
+    let mut out = String::from("digraph expression {\n    ordering=out;\n");
+    out.push_str("    node [fontname=\"Helvetica\", style=filled];\n");
+    dot_node(expr, &mut 0, &mut out);
+    out.push_str("}\n");
+    out
+}
+
+// numbered in pre-order; returns the number it gave expr
+fn dot_node(expr: &Expression, next: &mut usize, out: &mut String) -> usize {
+    let id = *next;
+    *next += 1;
+    let (shape, colour) = match expr {
+        Expression::Val(_) | Expression::Bool(_) => ("box", "#e9ecef"),
+        Expression::Var(_) => ("box", "#a8dadc"),
+        Expression::Compare(..) | Expression::And(..) | Expression::Or(..) | Expression::Not(_) => {
+            ("circle", "#90be6d")
+        }
+        Expression::Call(..) => ("ellipse", "#cdb4db"),
+        Expression::Custom(..) => ("circle", "#ffafcc"),
+        Expression::If(..) => ("diamond", "#ffd166"),
+        _ => ("circle", "#f4a261"), // + - * / // % ^ neg
+    };
+    let text = label(expr).replace('\\', "\\\\").replace('"', "\\\"");
+    out.push_str(&format!(
+        "    n{} [label=\"{}\", shape={}, fillcolor=\"{}\"];\n",
+        id, text, shape, colour
+    ));
+    for (i, child) in expr.children().into_iter().enumerate() {
+        let child_id = dot_node(&child.expr, next, out);
+        let role = roles(expr).get(i);
+        let attributes = role.map_or(String::new(), |role| format!(" [label=\"{}\"]", role));
+        out.push_str(&format!("    n{} -> n{}{};\n", id, child_id, attributes));
+    }
+    id
+}
+
+//--------------SERIALIZATION---------------
+/*
+Trees written out for another program or for later, as JSON or as S-expressions, and read
+back into the same Node (spans point into the document that was read).
+
+JSON, one object per node, "node" says which kind:
+    {"format": "basic-parser", "version": 1, "expr": {"node": "binary", "op": "+",
+        "left": {"node": "number", "int": 1}, "right": {"node": "var", "name": "x"}}}
+
+    number      one of "int": 7, "float": 2.5, "wide": "8589934592", "big": "..." (digits in
+                a string, so nothing rounds them through an f64) or "ratio": "1/3" (also
+                "1e-400", for decimals an f64 can't hold)
+    var         "name"                     bool      "value"
+    binary      "op" (+ - * / // % ^), "left", "right"
+    compare     "op" (== != < <= > >=), "left", "right"
+    and, or     "left", "right"            neg, not  "operand"
+    if          "cond", "then", "else"     call      "name", "args" (a list)
+    custom      "symbol", "fixity", "power", "assoc" (left or right), "operands" (a list)
+    error       nothing, a part parse_file couldn't read
+
+S-expressions are what :print shows, (+ 1 (* 2 x)), with the words of postfix text: neg,
+not, if, a ratio is 1/3 and a function only needs name/arity when it's called neg or not.
+(basic-parser 1 <expr>) says which version wrote it, a bare expression is the current one.
+
+New kinds of node and new fields don't change the version: an old file reads the same as
+before, and an older reader fails on a node kind it doesn't know (it skips fields it doesn't
+know). The version only goes up if something already there changes meaning, and a reader
+refuses versions newer than its own instead of guessing.
+ */
+
+const FORMAT_NAME: &str = "basic-parser";
+const FORMAT_VERSION: u32 = 1;
+
+fn check_version(version: &str, span: Span) -> Result<(), Error> {
+    match version.parse::<u32>() {
+        Ok(version) if version <= FORMAT_VERSION => Ok(()),
+        Ok(version) => Err(Error::InvalidDocument(
+            format!("it's format version {}, this program reads up to {}", version, FORMAT_VERSION),
+            span,
+        )),
+        Err(_) => Err(Error::UnexpectedToken(format!("'{}'", version), "a version number", span)),
+    }
+}
+
+// what the lexer would read as a variable or a function name
+fn is_name(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars.next().is_some_and(|ch| ch.is_alphabetic() || ch == '_')
+        && chars.all(|ch| ch.is_alphanumeric() || ch == '_')
+        && !KEYWORDS.contains(&word)
+}
+
+// -12, with no size limit
+fn parse_integer(text: &str) -> Option<BigInt> {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let n = BigInt::parse(digits);
+    Some(if digits.len() < text.len() { n.neg() } else { n })
+}
+
+// -1/3 as the exact fraction; 4/2 is the int 2
+fn parse_ratio(text: &str) -> Option<Number> {
+    let (num, den) = text.split_once('/')?;
+    let (num, den) = (parse_integer(num)?, parse_integer(den)?);
+    if den.is_zero() {
+        return None;
+    }
+    let exact = EvalOptions { backend: Backend::Rational, ..EvalOptions::default() };
+    Number::from_rational(Rational::new(num, den), &exact)
+}
+
+// the S-expression of item applied to operands already written out
+fn sexpr_text(item: &RpnItem, operands: Vec<String>) -> String {
+    let head = match item {
+        RpnItem::Num(_) | RpnItem::Var(_) | RpnItem::Bool(_) | RpnItem::Error => {
+            return item.to_string();
+        }
+        RpnItem::Call(name, _) if name != "neg" && name != "not" => name.clone(),
+        _ => item.to_string(),
+    };
+    let mut parts = vec![head];
+    parts.extend(operands);
+    format!("({})", parts.join(" "))
+}
+
+// the words and the parens, each with where it is
+fn sexpr_tokens(text: &str) -> Vec<(&str, Span)> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(|ch: char| !ch.is_whitespace()) {
+        let is_paren = |ch: char| ch == '(' || ch == ')';
+        let len = if rest[start..].starts_with(is_paren) {
+            1
+        } else {
+            let end = rest[start..].find(|ch: char| ch.is_whitespace() || is_paren(ch));
+            end.unwrap_or(rest.len() - start)
+        };
+        let offset = text.len() - rest.len() + start;
+        tokens.push((&rest[start..start + len], Span::new(offset, offset + len)));
+        rest = &rest[start + len..];
+    }
+    tokens
+}
+
+struct SexprReader<'a> {
+    tokens: Vec<(&'a str, Span)>,
+    next: usize,
+    end: usize, // where the text ends, for the errors at the end of it
+    operators: &'a OperatorTable,
+    depth: usize, // lists open around the form we're reading, MAX_DEPTH like the parser
+}
+
+impl SexprReader<'_> {
+    fn expected(&self, what: &'static str) -> Error {
+        match self.tokens.get(self.next) {
+            Some((word, span)) => Error::UnexpectedToken(format!("'{}'", word), what, *span),
+            None => {
+                let span = Span::new(self.end, self.end);
+                Error::UnexpectedToken("end of input".to_string(), what, span)
+            }
+        }
+    }
+
+    fn form(&mut self) -> Result<Node, Error> {
+        let (word, span) = match self.tokens.get(self.next) {
+            Some(&token) => token,
+            None => return Err(self.expected("an expression")),
+        };
+        self.next += 1;
+        match word {
+            "(" if self.depth == MAX_DEPTH => Err(Error::TooDeep(span)),
+            "(" => {
+                self.depth += 1;
+                let node = self.list(span);
+                self.depth -= 1;
+                node
+            }
+            ")" => Err(Error::UnmatchedParen(')', span)),
+            _ => self.atom(word, span),
+        }
+    }
+
+    fn atom(&self, word: &str, span: Span) -> Result<Node, Error> {
+        if let Some(number) = parse_ratio(word) {
+            return Ok(Node::new(Expression::Val(number), span));
+        }
+        let expr = match read_word(word, span, self.operators)? {
+            item @ (RpnItem::Num(_) | RpnItem::Var(_) | RpnItem::Bool(_)) => {
+                Expression::from_item(item, Vec::new())
+            }
+            // only the head of a list is an operator, (+ neg 1) adds a variable called neg
+            _ if is_name(word) => Expression::Var(word.to_string()),
+            _ => {
+                let expected = "a number, a name, true, false or '('";
+                return Err(Error::UnexpectedToken(format!("'{}'", word), expected, span));
+            }
+        };
+        Ok(Node::new(expr, span))
+    }
+
+    // after the '(': the operator or function, then exactly as many operands as it takes
+    fn list(&mut self, open: Span) -> Result<Node, Error> {
+        let expected = "an operator or a function name";
+        let (head, span) = match self.tokens.get(self.next) {
+            Some(&(word, span)) if word != "(" && word != ")" => (word, span),
+            _ => return Err(self.expected(expected)),
+        };
+        let (item, count) = match read_word(head, span, self.operators)? {
+            RpnItem::Var(name) => (RpnItem::Call(name, 0), None), // as many as there are
+            RpnItem::Call(name, arity) if is_name(&name) => {
+                (RpnItem::Call(name, arity), Some(arity))
+            }
+            RpnItem::Num(_) | RpnItem::Bool(_) | RpnItem::Call(..) => {
+                return Err(self.expected(expected));
+            }
+            item => {
+                let count = item.operands();
+                (item, Some(count))
+            }
+        };
+        self.next += 1;
+
+        let mut operands = Vec::new();
+        let close = loop {
+            let complete = count.is_none_or(|count| operands.len() == count);
+            match self.tokens.get(self.next) {
+                Some(&(")", close)) if complete => break close,
+                Some(&(")", _)) => return Err(self.expected("an operand")),
+                Some(_) if complete && count.is_some() => return Err(self.expected("')'")),
+                Some(_) => operands.push(self.form()?),
+                None => return Err(Error::UnmatchedParen('(', open)),
+            }
+        };
+        self.next += 1;
+
+        let item = match item {
+            RpnItem::Call(name, _) => RpnItem::Call(name, operands.len()),
+            item => item,
+        };
+        Ok(Node::new(Expression::from_item(item, operands), open.to(close)))
+    }
+}
+
+// the second front end: (+ 1 (* 2 x)) is the same tree as 1 + 2 * x
+fn read_sexpr(text: &str, operators: &OperatorTable) -> Result<Node, Error> {
+    let tokens = sexpr_tokens(text);
+    let wrapped = tokens.len() > 1 && tokens[0].0 == "(" && tokens[1].0 == FORMAT_NAME;
+    let mut reader = SexprReader { tokens, next: 0, end: text.len(), operators, depth: 0 };
+
+    let node = if wrapped {
+        reader.next = 2;
+        match reader.tokens.get(2) {
+            Some(&(version, span)) => check_version(version, span)?,
+            None => return Err(reader.expected("a version number")),
+        }
+        reader.next = 3;
+        let node = reader.form()?;
+        match reader.tokens.get(reader.next) {
+            Some(&(")", _)) => reader.next += 1,
+            Some(_) => return Err(reader.expected("')'")),
+            None => return Err(Error::UnmatchedParen('(', reader.tokens[0].1)),
+        }
+        node
+    } else {
+        reader.form()?
+    };
+    match reader.tokens.get(reader.next) {
+        Some(&(word, span)) => Err(Error::TrailingInput(format!("'{}'", word), span)),
+        None => Ok(node),
+    }
+}
+
+// JSON if it looks like it, an S-expression otherwise
+fn read_document(text: &str, operators: &OperatorTable) -> Result<Node, Error> {
+    if text.trim_start().starts_with('{') {
+        read_json(text)
+    } else {
+        read_sexpr(text, operators)
+    }
+}
+
+// the whole document, with the format and the version around the tree
+fn to_json(expr: &Expression) -> String {
+    format!(
+        "{{\"format\": {}, \"version\": {}, \"expr\": {}}}",
+        json_string(FORMAT_NAME),
+        FORMAT_VERSION,
+        json_node(expr)
+    )
+}
+
+fn json_node(expr: &Expression) -> String {
+    let child = |node: &Node| json_node(&node.expr);
+    let list = |nodes: &[Node]| {
+        format!("[{}]", nodes.iter().map(child).collect::<Vec<_>>().join(", "))
+    };
+    let (kind, fields) = match expr {
+        Expression::Val(number) => {
+            let field = match number {
+                Number::Int(n) => ("int", n.to_string()),
+                Number::Wide(n) => ("wide", json_string(&n.to_string())),
+                Number::Big(n) => ("big", json_string(&n.to_string())),
+                Number::Ratio(r) => {
+                    ("ratio", json_string(&number.as_decimal().unwrap_or_else(|| r.to_string())))
+                }
+                Number::Float(x) => ("float", format!("{:?}", x)), // 1e20 is valid JSON
+            };
+            ("number", vec![field])
+        }
+        Expression::Var(name) => ("var", vec![("name", json_string(name))]),
+        Expression::Bool(b) => ("bool", vec![("value", b.to_string())]),
+        Expression::Error => ("error", Vec::new()),
+        Expression::Neg(operand) => ("neg", vec![("operand", child(operand))]),
+        Expression::Not(operand) => ("not", vec![("operand", child(operand))]),
+        Expression::Compare(op, left, right) => {
+            let op = ("op", json_string(op.symbol()));
+            ("compare", vec![op, ("left", child(left)), ("right", child(right))])
+        }
+        Expression::And(left, right) | Expression::Or(left, right) => {
+            let kind = if matches!(expr, Expression::And(..)) { "and" } else { "or" };
+            (kind, vec![("left", child(left)), ("right", child(right))])
+        }
+        Expression::If(cond, then, otherwise) => {
+            ("if", vec![("cond", child(cond)), ("then", child(then)), ("else", child(otherwise))])
+        }
+        Expression::Call(name, args) => {
+            ("call", vec![("name", json_string(name)), ("args", list(args))])
+        }
+        Expression::Custom(op, operands) => (
+            "custom",
+            vec![
+                ("symbol", json_string(&op.symbol)),
+                ("fixity", json_string(op.fixity.name())),
+                ("power", op.power.to_string()),
+                ("assoc", json_string(op.assoc.name())),
+                ("operands", list(operands)),
+            ],
+        ),
+        _ => {
+            let (op, left, right) = expr.as_binary().expect("the rest are binary");
+            let op = ("op", json_string(op.symbol()));
+            ("binary", vec![op, ("left", child(left)), ("right", child(right))])
+        }
+    };
+    let mut parts = vec![format!("\"node\": {}", json_string(kind))];
+    for (name, value) in fields {
+        parts.push(format!("{}: {}", json_string(name), value));
+    }
+    format!("{{{}}}", parts.join(", "))
+}
+
+// a parsed JSON value, numbers as they were written so "int": 2.0 can be told from 2
+#[derive(Debug, Clone)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    Str(String),
+    Array(Vec<(Json, Span)>),
+    Object(Vec<(String, (Json, Span))>),
+}
+
+impl Json {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "a boolean",
+            Json::Number(_) => "a number",
+            Json::Str(_) => "a string",
+            Json::Array(_) => "a list",
+            Json::Object(_) => "an object",
+        }
+    }
+}
+
+// just the JSON the documents need (which is all of it, minus nothing)
+struct JsonReader<'a> {
+    text: &'a str,
+    pos: usize,
+    depth: usize, // objects and arrays open around the value we're reading
+}
+
+impl JsonReader<'_> {
+    fn skip_space(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn expected(&mut self, what: &'static str) -> Error {
+        self.skip_space();
+        match self.text[self.pos..].chars().next() {
+            Some(ch) => {
+                let span = Span::new(self.pos, self.pos + ch.len_utf8());
+                Error::UnexpectedToken(format!("'{}'", ch), what, span)
+            }
+            None => {
+                let span = Span::new(self.pos, self.pos);
+                Error::UnexpectedToken("end of input".to_string(), what, span)
+            }
+        }
+    }
+
+    fn eat(&mut self, ch: char) -> bool {
+        self.skip_space();
+        let found = self.text[self.pos..].starts_with(ch);
+        if found {
+            self.pos += ch.len_utf8();
+        }
+        found
+    }
+
+    // a call is two levels (the node and its "args"), so a tree MAX_DEPTH high fits
+    fn value(&mut self) -> Result<(Json, Span), Error> {
+        self.skip_space();
+        if self.depth == 2 * MAX_DEPTH + 2 && self.text[self.pos..].starts_with(['{', '[']) {
+            return Err(Error::TooDeep(Span::new(self.pos, self.pos + 1)));
+        }
+        self.depth += 1;
+        let value = self.nested_value();
+        self.depth -= 1;
+        value
+    }
+
+    fn nested_value(&mut self) -> Result<(Json, Span), Error> {
+        let start = self.pos;
+        let rest = &self.text[start..];
+        let json = if self.eat('{') {
+            let mut fields = Vec::new();
+            if !self.eat('}') {
+                loop {
+                    self.skip_space();
+                    if !self.text[self.pos..].starts_with('"') {
+                        return Err(self.expected("a field name"));
+                    }
+                    let name = self.string()?;
+                    if !self.eat(':') {
+                        return Err(self.expected("':'"));
+                    }
+                    fields.push((name, self.value()?));
+                    if self.eat('}') {
+                        break;
+                    }
+                    if !self.eat(',') {
+                        return Err(self.expected("',' or '}'"));
+                    }
+                }
+            }
+            Json::Object(fields)
+        } else if self.eat('[') {
+            let mut items = Vec::new();
+            if !self.eat(']') {
+                loop {
+                    items.push(self.value()?);
+                    if self.eat(']') {
+                        break;
+                    }
+                    if !self.eat(',') {
+                        return Err(self.expected("',' or ']'"));
+                    }
+                }
+            }
+            Json::Array(items)
+        } else if rest.starts_with('"') {
+            Json::Str(self.string()?)
+        } else if let Some(word) = ["true", "false", "null"].iter().find(|w| rest.starts_with(*w)) {
+            self.pos += word.len();
+            match *word {
+                "null" => Json::Null,
+                _ => Json::Bool(*word == "true"),
+            }
+        } else {
+            let len = rest.find(|ch: char| !(ch.is_ascii_digit() || "+-.eE".contains(ch)));
+            let len = len.unwrap_or(rest.len());
+            if len == 0 {
+                return Err(self.expected("a JSON value"));
+            }
+            self.pos += len;
+            Json::Number(rest[..len].to_string())
+        };
+        Ok((json, Span::new(start, self.pos)))
+    }
+
+    // from the opening quote to the closing one
+    fn string(&mut self) -> Result<String, Error> {
+        let open = Span::new(self.pos, self.pos + 1);
+        self.pos += 1;
+        let mut text = String::new();
+        loop {
+            let ch = match self.text[self.pos..].chars().next() {
+                Some(ch) => ch,
+                None => return Err(Error::UnmatchedParen('"', open)),
+            };
+            self.pos += ch.len_utf8();
+            match ch {
+                '"' => return Ok(text),
+                '\\' => text.push(self.escape()?),
+                ch => text.push(ch),
+            }
+        }
+    }
+
+    // after a backslash: \n, \" ... and \u00e9, with a surrogate pair for what's past \uffff
+    fn escape(&mut self) -> Result<char, Error> {
+        let start = self.pos - 1;
+        let ch = self.text[self.pos..].chars().next();
+        self.pos += ch.map_or(0, char::len_utf8);
+        let simple = match ch {
+            Some('n') => Some('\n'),
+            Some('t') => Some('\t'),
+            Some('r') => Some('\r'),
+            Some('b') => Some('\u{8}'),
+            Some('f') => Some('\u{c}'),
+            Some(ch @ ('"' | '\\' | '/')) => Some(ch),
+            _ => None,
+        };
+        let code = match (ch, simple) {
+            (_, Some(ch)) => return Ok(ch),
+            (Some('u'), _) => self.hex4(),
+            _ => None,
+        };
+        let code = match code {
+            Some(high @ 0xd800..=0xdbff) if self.text[self.pos..].starts_with("\\u") => {
+                self.pos += 2;
+                match self.hex4() {
+                    Some(low @ 0xdc00..=0xdfff) => {
+                        Some(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))
+                    }
+                    _ => None,
+                }
+            }
+            code => code,
+        };
+        code.and_then(char::from_u32).ok_or_else(|| {
+            let text = &self.text[start..self.pos];
+            Error::UnexpectedToken(format!("'{}'", text), "an escape", Span::new(start, self.pos))
+        })
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        let hex = self.text.get(self.pos..self.pos + 4)?;
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        self.pos += 4;
+        u32::from_str_radix(hex, 16).ok()
+    }
+}
+
+// the field of an object; missing is an error, pointing at the object
+fn field<'j>(object: &'j (Json, Span), name: &str) -> Result<&'j (Json, Span), Error> {
+    let found = match &object.0 {
+        Json::Object(fields) => fields.iter().find(|(key, _)| key == name).map(|(_, value)| value),
+        _ => None,
+    };
+    found.ok_or_else(|| Error::InvalidDocument(format!("no \"{}\" here", name), object.1))
+}
+
+fn wrong_type(name: &str, expected: &str, found: &(Json, Span)) -> Error {
+    let what = format!("\"{}\" should be {}, not {}", name, expected, found.0.type_name());
+    Error::InvalidDocument(what, found.1)
+}
+
+fn string_field<'j>(object: &'j (Json, Span), name: &str) -> Result<&'j str, Error> {
+    match field(object, name)? {
+        (Json::Str(text), _) => Ok(text),
+        found => Err(wrong_type(name, "a string", found)),
+    }
+}
+
+// a string field that has to be one of the names from_name knows: "+", "infix", "left"
+fn named<T>(
+    object: &(Json, Span),
+    name: &str,
+    from_name: fn(&str) -> Option<T>,
+) -> Result<T, Error> {
+    let text = string_field(object, name)?;
+    from_name(text).ok_or_else(|| {
+        let span = field(object, name).map_or(object.1, |found| found.1);
+        Error::InvalidDocument(format!("unknown {} \"{}\"", name, text), span)
+    })
+}
+
+fn list_field(object: &(Json, Span), name: &str) -> Result<Vec<Node>, Error> {
+    match field(object, name)? {
+        (Json::Array(items), _) => items.iter().map(json_to_node).collect(),
+        found => Err(wrong_type(name, "a list", found)),
+    }
+}
+
+// the digits of a JSON number as T, or what's wrong with them
+fn number_field<T: std::str::FromStr>(
+    object: &(Json, Span),
+    name: &str,
+    expected: &str,
+) -> Result<T, Error> {
+    match field(object, name)? {
+        (Json::Number(text), span) => text.parse().map_err(|_| {
+            let what = format!("\"{}\" should be {}, not {}", name, expected, text);
+            Error::InvalidDocument(what, *span)
+        }),
+        found => Err(wrong_type(name, expected, found)),
+    }
+}
+
+fn read_json(text: &str) -> Result<Node, Error> {
+    let mut reader = JsonReader { text, pos: 0, depth: 0 };
+    let document = reader.value()?;
+    reader.skip_space();
+    if reader.pos < text.len() {
+        return Err(reader.expected("the end of the document"));
+    }
+    if let Ok(format) = field(&document, "format") {
+        if !matches!(&format.0, Json::Str(name) if name == FORMAT_NAME) {
+            let what = format!("the format should be \"{}\"", FORMAT_NAME);
+            return Err(Error::InvalidDocument(what, format.1));
+        }
+    }
+    let version: u32 = number_field(&document, "version", "a version number")?;
+    check_version(&version.to_string(), field(&document, "version")?.1)?;
+    let node = json_to_node(field(&document, "expr")?)?;
+    // a chain of "neg" is one level a node, higher than the parser would ever make
+    match node.height() {
+        0..=MAX_DEPTH => Ok(node),
+        _ => Err(Error::TooDeep(node.span)),
+    }
+}
+
+fn json_to_node(object: &(Json, Span)) -> Result<Node, Error> {
+    let child = |name| json_to_node(field(object, name)?).map(Box::new);
+    let name = || {
+        let text = string_field(object, "name")?;
+        match is_name(text) {
+            true => Ok(text.to_string()),
+            false => {
+                let span = field(object, "name")?.1;
+                Err(Error::InvalidDocument(format!("\"{}\" isn't a name", text), span))
+            }
+        }
+    };
+
+    let kind = string_field(object, "node")?;
+    let expr = match kind {
+        "number" => Expression::Val(json_number(object)?),
+        "var" => Expression::Var(name()?),
+        "bool" => match field(object, "value")? {
+            (Json::Bool(b), _) => Expression::Bool(*b),
+            found => return Err(wrong_type("value", "true or false", found)),
+        },
+        "binary" => {
+            let op = named(object, "op", BinOp::from_symbol)?;
+            Expression::binary(op, *child("left")?, *child("right")?)
+        }
+        "compare" => {
+            let op = named(object, "op", CmpOp::from_symbol)?;
+            Expression::Compare(op, child("left")?, child("right")?)
+        }
+        "error" => Expression::Error,
+        "and" => Expression::And(child("left")?, child("right")?),
+        "or" => Expression::Or(child("left")?, child("right")?),
+        "neg" => Expression::Neg(child("operand")?),
+        "not" => Expression::Not(child("operand")?),
+        "if" => Expression::If(child("cond")?, child("then")?, child("else")?),
+        "call" => Expression::Call(name()?, list_field(object, "args")?),
+        "custom" => {
+            let op = CustomOp {
+                symbol: string_field(object, "symbol")?.to_string(),
+                fixity: named(object, "fixity", Fixity::from_name)?,
+                power: number_field(object, "power", "a number from 0 to 255")?,
+                assoc: named(object, "assoc", Assoc::from_name)?,
+            };
+            let operands = list_field(object, "operands")?;
+            if operands.len() != op.fixity.arity() {
+                let (fixity, arity) = (op.fixity.name(), op.fixity.arity());
+                let operands = if arity == 1 { "one operand" } else { "two operands" };
+                let what = format!("{} {} takes {}", fixity, op.symbol, operands);
+                return Err(Error::InvalidDocument(what, object.1));
+            }
+            Expression::Custom(op, operands)
+        }
+        _ => {
+            let span = field(object, "node")?.1;
+            return Err(Error::InvalidDocument(format!("unknown node \"{}\"", kind), span));
+        }
+    };
+    Ok(Node::new(expr, object.1))
+}
+
+// whichever of the number fields is there, as the same Number the lexer would have made
+fn json_number(object: &(Json, Span)) -> Result<Number, Error> {
+    let exact = EvalOptions { backend: Backend::BigInt, ..EvalOptions::default() };
+    let invalid = |name: &str, text: &str| {
+        let span = field(object, name).map_or(object.1, |found| found.1);
+        Error::InvalidDocument(format!("\"{}\" isn't a valid {}", text, name), span)
+    };
+    if field(object, "int").is_ok() {
+        return number_field(object, "int", "an i32").map(Number::Int);
+    }
+    if field(object, "float").is_ok() {
+        let x: f64 = number_field(object, "float", "a number")?;
+        return Number::float(x).ok_or_else(|| invalid("float", &x.to_string()));
+    }
+    if field(object, "wide").is_ok() {
+        let text = string_field(object, "wide")?;
+        let n: i64 = text.parse().map_err(|_| invalid("wide", text))?;
+        return Ok(i32::try_from(n).map_or(Number::Wide(n), Number::Int));
+    }
+    if field(object, "big").is_ok() {
+        let text = string_field(object, "big")?;
+        let n = parse_integer(text).ok_or_else(|| invalid("big", text))?;
+        return Number::fit_big(n, &exact).ok_or_else(|| invalid("big", text));
+    }
+    if field(object, "ratio").is_ok() {
+        let text = string_field(object, "ratio")?;
+        // or 1e-400, see Number::as_decimal
+        let decimal = || Rational::parse_decimal(text, MAX_BIG_BITS * 3 / 10).map(Number::Ratio);
+        let decimal = || decimal().filter(|number| number.as_decimal().is_some());
+        return parse_ratio(text).or_else(decimal).ok_or_else(|| invalid("ratio", text));
+    }
+    let what = "a number needs int, float, wide, big or ratio".to_string();
+    Err(Error::InvalidDocument(what, object.1))
+}
+
+//--------------SIMPLIFIER---------------
+
+// one pass that makes a tree smaller without changing what it evaluates to: not the value, not
+// the error (division by zero, overflow, a type error), not even -0.0 versus 0.0
+// variables are not looked at for their values, only for their types, so the result is good for
+// as long as x stays an int (or a float, or a boolean) whatever the number in it
+
+// what a subtree comes out as when it doesn't fail; Int is Int, Wide or Big, Exact can also be
+// a Ratio, Number is any of them or a Float, Any may even be a boolean
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Int,
+    Exact,
+    Float,
+    Number,
+    Bool,
+    Any,
+}
+
+impl Kind {
+    fn of(number: &Number) -> Kind {
+        match number {
+            Number::Int(_) | Number::Wide(_) | Number::Big(_) => Kind::Int,
+            Number::Ratio(_) => Kind::Exact,
+            Number::Float(_) => Kind::Float,
+        }
+    }
+
+    fn numeric(self) -> bool {
+        matches!(self, Kind::Int | Kind::Exact | Kind::Float | Kind::Number)
+    }
+
+    fn exact(self) -> bool {
+        matches!(self, Kind::Int | Kind::Exact)
+    }
+
+    // one or the other, for the two branches of an if
+    fn join(self, other: Kind) -> Kind {
+        match (self, other) {
+            _ if self == other => self,
+            _ if self.exact() && other.exact() => Kind::Exact,
+            _ if self.numeric() && other.numeric() => Kind::Number,
+            _ => Kind::Any,
+        }
+    }
+}
+
+// safe: evaluating it can't fail, whatever the values of the variables
+#[derive(Debug, Clone, Copy)]
+struct Facts {
+    kind: Kind,
+    safe: bool,
+}
+
+// only int + - * that wrap or saturate can't fail; everything else may overflow, and floats
+// overflow too (to inf)
+fn facts(node: &Node, env: &Environment) -> Facts {
+    let options = &env.options;
+    let cannot_overflow = options.backend == Backend::Machine
+        && matches!(options.overflow, OverflowMode::Wrapping | OverflowMode::Saturating);
+    let safe = |kind| Facts { kind, safe: true };
+    let unsafe_ = |kind| Facts { kind, safe: false };
+
+    match &node.expr {
+        Expression::Val(number) => match number.literal(options) {
+            Some(number) => safe(Kind::of(&number)),
+            None => unsafe_(Kind::Number), // too big for the machine backend
+        },
+        Expression::Error => unsafe_(Kind::Any),
+        Expression::Bool(_) => safe(Kind::Bool),
+        Expression::Var(name) => match env.get(name) {
+            Some(Value::Num(number)) => safe(Kind::of(&number)),
+            Some(Value::Bool(_)) => safe(Kind::Bool),
+            None => unsafe_(Kind::Any),
+        },
+        Expression::Add(..)
+        | Expression::Sub(..)
+        | Expression::Mul(..)
+        | Expression::Div(..)
+        | Expression::FloorDiv(..)
+        | Expression::Mod(..)
+        | Expression::Pow(..) => {
+            let (op, left, right) = node.expr.as_binary().expect("a binary node");
+            let (l, r) = (facts(left, env), facts(right, env));
+            let exact_op = !matches!(op, BinOp::Div | BinOp::Pow); // 7 / 2 and 2 ^ -1 are floats
+            let kind = match (l.kind, r.kind) {
+                (Kind::Int, Kind::Int) if exact_op => Kind::Int,
+                (a, b) if exact_op && a.exact() && b.exact() => Kind::Exact,
+                (Kind::Float, _) | (_, Kind::Float) => Kind::Float,
+                _ => Kind::Number,
+            };
+            let wraps = matches!(op, BinOp::Add | BinOp::Sub | BinOp::Mul) && cannot_overflow;
+            Facts { kind, safe: wraps && kind == Kind::Int && l.safe && r.safe }
+        }
+        Expression::Neg(operand) => {
+            let operand = facts(operand, env);
+            let kind = if operand.kind.numeric() { operand.kind } else { Kind::Number };
+            Facts { kind, safe: cannot_overflow && kind == Kind::Int && operand.safe }
+        }
+        Expression::Call(name, _) if env.functions.contains_key(name) => unsafe_(Kind::Any),
+        Expression::Call(..) | Expression::Custom(..) => unsafe_(Kind::Number),
+        Expression::Compare(op, left, right) => {
+            let (l, r) = (facts(left, env), facts(right, env));
+            let equality = matches!(op, CmpOp::Eq | CmpOp::Ne);
+            let comparable = (l.kind.numeric() && r.kind.numeric())
+                || (equality && l.kind == Kind::Bool && r.kind == Kind::Bool);
+            Facts { kind: Kind::Bool, safe: l.safe && r.safe && comparable }
+        }
+        Expression::And(left, right) | Expression::Or(left, right) => {
+            let (l, r) = (facts(left, env), facts(right, env));
+            let booleans = l.kind == Kind::Bool && r.kind == Kind::Bool;
+            Facts { kind: Kind::Bool, safe: l.safe && r.safe && booleans }
+        }
+        Expression::Not(operand) => {
+            let operand = facts(operand, env);
+            Facts { kind: Kind::Bool, safe: operand.safe && operand.kind == Kind::Bool }
+        }
+        Expression::If(cond, then, otherwise) => {
+            let (c, t, e) = (facts(cond, env), facts(then, env), facts(otherwise, env));
+            let safe = c.safe && c.kind == Kind::Bool && t.safe && e.safe;
+            Facts { kind: t.kind.join(e.kind), safe }
+        }
+    }
+}
+
+// bottom up: the operands first, then whatever they make possible here
+fn simplify(node: &Node, env: &mut Environment) -> Node {
+    let operands = node.expr.children().into_iter().map(|child| simplify(child, env)).collect();
+    let node = Node::new(node.expr.with_children(operands), node.span);
+    match fold(&node, env) {
+        Some(folded) => folded,
+        None => rewrite(node, env),
+    }
+}
+
+// an operator over values only is worked out now, unless that fails (then the error has to stay
+// for later) or it calls a user function
+fn fold(node: &Node, env: &mut Environment) -> Option<Node> {
+    let operands = node.expr.children();
+    let user_call =
+        matches!(&node.expr, Expression::Call(name, _) if env.functions.contains_key(name));
+    let leaf = matches!(node.expr, Expression::Val(_) | Expression::Var(_) | Expression::Bool(_));
+    let constant = operands.iter().all(|operand| {
+        matches!(operand.expr, Expression::Val(_) | Expression::Bool(_))
+    });
+    if leaf || user_call || !constant {
+        return None;
+    }
+    let value = node.eval(env).ok()?;
+    literal(value, node.span, &env.options)
+}
+
+// a value as a node that evaluates back to exactly it; a Float doesn't in the rational backend
+// (0.5 is read as 1/2), so there it stays what it was
+fn literal(value: Value, span: Span, options: &EvalOptions) -> Option<Node> {
+    let expr = match value {
+        Value::Num(number) if number.literal(options).as_ref() == Some(&number) => {
+            Expression::Val(number)
+        }
+        Value::Num(_) => return None,
+        Value::Bool(b) => Expression::Bool(b),
+    };
+    Some(Node::new(expr, span))
+}
+
+fn is_int(node: &Node, n: i32) -> bool {
+    node.expr == Expression::Val(Number::Int(n))
+}
+
+fn is_bool(node: &Node, b: bool) -> bool {
+    node.expr == Expression::Bool(b)
+}
+
+// the identities; each one only where it can't change the outcome:
+// x + 0 = x not for floats (-0.0 + 0 is 0.0), x * 0 = 0 only for an x that can't fail and
+// isn't a float (-2.5 * 0 is -0.0), true && b = b only if b is a boolean (else a type error)
+fn rewrite(node: Node, env: &Environment) -> Node {
+    let span = node.span;
+    let zero = || Node::new(Expression::Val(Number::Int(0)), span);
+    let constant = |b| Node::new(Expression::Bool(b), span);
+    let or = matches!(node.expr, Expression::Or(..));
+
+    match node.expr {
+        Expression::Add(left, right) => {
+            if is_int(&right, 0) && facts(&left, env).kind.exact() {
+                return *left;
+            }
+            if is_int(&left, 0) && facts(&right, env).kind.exact() {
+                return *right;
+            }
+            like_terms(&left, &right, false, span, env)
+                .unwrap_or_else(|| commute(BinOp::Add, *left, *right, span, env))
+        }
+        Expression::Sub(left, right) => {
+            let l = facts(&left, env);
+            if is_int(&right, 0) && l.kind.numeric() {
+                return *left;
+            }
+            // x - x is 0, or 0.0 for a float; an int - an int can't be -0.0
+            if left == right && l.safe {
+                let difference = match l.kind {
+                    kind if kind.exact() => Some(zero()),
+                    Kind::Float => literal(Number::Float(0.0).into(), span, &env.options),
+                    _ => None,
+                };
+                if let Some(difference) = difference {
+                    return difference;
+                }
+            }
+            like_terms(&left, &right, true, span, env)
+                .unwrap_or_else(|| Node::new(Expression::Sub(left, right), span))
+        }
+        Expression::Mul(left, right) => {
+            let (l, r) = (facts(&left, env), facts(&right, env));
+            if is_int(&right, 1) && l.kind.numeric() {
+                return *left;
+            }
+            if is_int(&left, 1) && r.kind.numeric() {
+                return *right;
+            }
+            let vanishes = |factor: Facts| factor.safe && factor.kind.exact();
+            if (is_int(&right, 0) && vanishes(l)) || (is_int(&left, 0) && vanishes(r)) {
+                return zero();
+            }
+            commute(BinOp::Mul, *left, *right, span, env)
+        }
+        Expression::Div(left, right) if is_int(&right, 1) && facts(&left, env).kind.numeric() => {
+            *left
+        }
+        Expression::Not(operand) => match operand.expr {
+            Expression::Not(inner) if facts(&inner, env).kind == Kind::Bool => *inner,
+            _ => Node::new(Expression::Not(operand), span),
+        },
+        // false && b never looks at b, true && b is b once b is known to be a boolean
+        Expression::And(left, right) | Expression::Or(left, right) => {
+            let (l, r) = (facts(&left, env), facts(&right, env));
+            if is_bool(&left, or) {
+                return constant(or);
+            }
+            if is_bool(&left, !or) && r.kind == Kind::Bool {
+                return *right;
+            }
+            if is_bool(&right, !or) && l.kind == Kind::Bool {
+                return *left;
+            }
+            if is_bool(&right, or) && l.safe && l.kind == Kind::Bool {
+                return constant(or);
+            }
+            let expr = if or { Expression::Or(left, right) } else { Expression::And(left, right) };
+            Node::new(expr, span)
+        }
+        Expression::If(cond, then, otherwise) => {
+            let c = facts(&cond, env);
+            if is_bool(&cond, true) {
+                return *then;
+            }
+            if is_bool(&cond, false) {
+                return *otherwise;
+            }
+            if then == otherwise && c.safe && c.kind == Kind::Bool {
+                return *then;
+            }
+            Node::new(Expression::If(cond, then, otherwise), span)
+        }
+        expr => Node::new(expr, span),
+    }
+}
+
+// k * t or t * k with an int literal k, anything else is 1 * itself
+fn coefficient(node: &Node) -> (i32, &Node) {
+    if let Expression::Mul(left, right) = &node.expr {
+        match (&left.expr, &right.expr) {
+            (Expression::Val(Number::Int(k)), _) => return (*k, right),
+            (_, Expression::Val(Number::Int(k))) => return (*k, left),
+            _ => {}
+        }
+    }
+    (1, node)
+}
+
+// a*t + b*t = (a + b)*t, a*t - b*t = (a - b)*t, for an integer t that can't fail
+// only with a and b (or a and -b) of the same sign: then a*t and b*t are no further from zero
+// than (a + b)*t, so one side overflows (or saturates, the same way) exactly when the other does
+fn like_terms(
+    left: &Node,
+    right: &Node,
+    subtract: bool,
+    span: Span,
+    env: &Environment,
+) -> Option<Node> {
+    let ((a, term), (b, other)) = (coefficient(left), coefficient(right));
+    let (a, b) = (a as i64, if subtract { -(b as i64) } else { b as i64 });
+    let facts = facts(term, env);
+    if term != other || !facts.safe || facts.kind != Kind::Int || a.signum() * b.signum() < 0 {
+        return None;
+    }
+    let sum = i32::try_from(a + b).ok()?;
+    let number = |n| Box::new(Node::new(Expression::Val(Number::Int(n)), span));
+    Some(match sum {
+        0 => *number(0),
+        1 => term.clone(),
+        _ => Node::new(Expression::Mul(number(sum), Box::new(term.clone())), span),
+    })
+}
+
+// a + b and a * b in one order: numbers last in a sum and first in a product (x + 1, 2 * x), the
+// rest by their text; only when one side is a number that can't fail, so whichever error the
+// other side has (not being a number included) still comes out
+fn commute(op: BinOp, left: Node, right: Node, span: Span, env: &Environment) -> Node {
+    let key = |node: &Node| {
+        let number = matches!(node.expr, Expression::Val(_));
+        (number != (op == BinOp::Mul), node.to_string())
+    };
+    let clean = |node: &Node| {
+        let facts = facts(node, env);
+        facts.safe && facts.kind.numeric()
+    };
+    let swap = (clean(&left) || clean(&right)) && key(&right) < key(&left);
+    let (left, right) = if swap { (right, left) } else { (left, right) };
+    Node::new(Expression::binary(op, left, right), span)
+}
+
+//--------------DERIVATIVES---------------
+
+// d/dvar, as another expression; every other variable is a constant
+// the result is put together without the obvious zeros and ones and then simplified
+fn derive(node: &Node, var: &str, env: &mut Environment) -> Result<Node, Error> {
+    let derivative = Derivative { var, env, inlined: Vec::new() }.of(node)?;
+    Ok(simplify(&derivative, env))
+}
+
+struct Derivative<'a> {
+    var: &'a str,
+    env: &'a Environment,
+    inlined: Vec<String>, // the user functions we're inside of, a recursive one has no end
+}
+
+impl Derivative<'_> {
+    fn of(&mut self, node: &Node) -> Result<Node, Error> {
+        let span = node.span;
+        match &node.expr {
+            Expression::Bool(_)
+            | Expression::Compare(..)
+            | Expression::And(..)
+            | Expression::Or(..)
+            | Expression::Not(_) => Err(Error::NotDifferentiable("a boolean".to_string(), span)),
+            Expression::Error => Err(Error::Unparsed(span)),
+            // the condition stays as it is, each branch gets its own derivative
+            Expression::If(cond, then, otherwise) => {
+                let (then, otherwise) = (self.of(then)?, self.of(otherwise)?);
+                if then == otherwise {
+                    return Ok(then);
+                }
+                let branches = Expression::If(cond.clone(), Box::new(then), Box::new(otherwise));
+                Ok(Node::new(branches, span))
+            }
+            Expression::Var(name) => Ok(int((name == self.var) as i32, span)),
+            _ if !self.mentions(node) => Ok(int(0, span)),
+            Expression::Add(u, v) => Ok(combine(BinOp::Add, self.of(u)?, self.of(v)?, span)),
+            Expression::Sub(u, v) => Ok(combine(BinOp::Sub, self.of(u)?, self.of(v)?, span)),
+            // (u v)' = u' v + u v'
+            Expression::Mul(u, v) => {
+                let left = combine(BinOp::Mul, self.of(u)?, (**v).clone(), span);
+                let right = combine(BinOp::Mul, (**u).clone(), self.of(v)?, span);
+                Ok(combine(BinOp::Add, left, right, span))
+            }
+            // (u / v)' = (u' v - u v') / v^2
+            Expression::Div(u, v) => {
+                if !self.mentions(v) {
+                    return Ok(combine(BinOp::Div, self.of(u)?, (**v).clone(), span));
+                }
+                let left = combine(BinOp::Mul, self.of(u)?, (**v).clone(), span);
+                let right = combine(BinOp::Mul, (**u).clone(), self.of(v)?, span);
+                let numerator = combine(BinOp::Sub, left, right, span);
+                let square = combine(BinOp::Pow, (**v).clone(), int(2, span), span);
+                Ok(combine(BinOp::Div, numerator, square, span))
+            }
+            Expression::Pow(u, v) => self.power(node, u, v),
+            Expression::Neg(u) => Ok(combine(BinOp::Sub, int(0, span), self.of(u)?, span)),
+            // u % v = u - v * (u // v), and u // v is flat between its steps
+            Expression::Mod(u, v) => {
+                let steps = Node::new(Expression::FloorDiv(u.clone(), v.clone()), span);
+                let right = combine(BinOp::Mul, self.of(v)?, steps, span);
+                Ok(combine(BinOp::Sub, self.of(u)?, right, span))
+            }
+            Expression::FloorDiv(..) => Ok(int(0, span)),
+            Expression::Call(name, args) if self.env.functions.contains_key(name) => {
+                self.inline(name, args, span)
+            }
+            Expression::Call(name, args) => self.builtin(node, name, args),
+            Expression::Custom(op, _) => {
+                Err(Error::NotDifferentiable(format!("the operator '{}'", op.symbol), span))
+            }
+            Expression::Val(_) => Ok(int(0, span)),
+        }
+    }
+
+    // whether var can change the value: it's in there, or a user function is called (its body
+    // may use the global var)
+    fn mentions(&self, node: &Node) -> bool {
+        match &node.expr {
+            Expression::Var(name) => name == self.var,
+            Expression::Call(name, _) if self.env.functions.contains_key(name) => true,
+            _ => node.expr.children().into_iter().any(|child| self.mentions(child)),
+        }
+    }
+
+    // u ^ v, also for pow(u, v): the power rule when only u has var in it, the exponential one
+    // when only v does, and both at once otherwise
+    fn power(&mut self, node: &Node, u: &Node, v: &Node) -> Result<Node, Error> {
+        let span = node.span;
+        let ln = |u: &Node| call("ln", vec![u.clone()], span);
+        if !self.mentions(v) {
+            // v u^(v - 1) u'
+            let exponent = match v.expr {
+                Expression::Val(Number::Int(k)) if k > i32::MIN => int(k - 1, span),
+                _ => combine(BinOp::Sub, v.clone(), int(1, span), span),
+            };
+            let power = combine(BinOp::Pow, u.clone(), exponent, span);
+            let outer = combine(BinOp::Mul, v.clone(), power, span);
+            return Ok(combine(BinOp::Mul, outer, self.of(u)?, span));
+        }
+        if !self.mentions(u) {
+            // u^v ln(u) v'
+            let outer = combine(BinOp::Mul, node.clone(), ln(u), span);
+            return Ok(combine(BinOp::Mul, outer, self.of(v)?, span));
+        }
+        // u^v (v' ln(u) + v u' / u)
+        let left = combine(BinOp::Mul, self.of(v)?, ln(u), span);
+        let right = combine(BinOp::Mul, v.clone(), self.of(u)?, span);
+        let inner = combine(BinOp::Add, left, combine(BinOp::Div, right, u.clone(), span), span);
+        Ok(combine(BinOp::Mul, node.clone(), inner, span))
+    }
+
+    // the chain rule: f(u)' = f'(u) u'
+    fn builtin(&mut self, node: &Node, name: &str, args: &[Node]) -> Result<Node, Error> {
+        let span = node.span;
+        let builtin = BUILTINS.iter().find(|builtin| builtin.name == name);
+        let builtin = builtin.ok_or_else(|| Error::UnknownFunction(name.to_string(), span))?;
+        builtin.arity.check(name, args.len(), span)?;
+        let u = &args[0];
+        let outer = match name {
+            "pow" => return self.power(node, u, &args[1]),
+            "min" | "max" => return self.extremum(name, args, span),
+            "abs" => combine(BinOp::Div, u.clone(), node.clone(), span),
+            "sqrt" => {
+                let twice = combine(BinOp::Mul, int(2, span), node.clone(), span);
+                combine(BinOp::Div, int(1, span), twice, span)
+            }
+            "ln" => combine(BinOp::Div, int(1, span), u.clone(), span),
+            "exp" => node.clone(),
+            "floor" | "ceil" => return Ok(int(0, span)), // steps, flat everywhere but the jumps
+            _ => return Err(Error::NotDifferentiable(name.to_string(), span)),
+        };
+        Ok(combine(BinOp::Mul, outer, self.of(u)?, span))
+    }
+
+    // min(a, b, c) is a where a <= min(b, c), else min(b, c); the same test builtin_min makes
+    fn extremum(&mut self, name: &str, args: &[Node], span: Span) -> Result<Node, Error> {
+        let (first, rest) = args.split_first().expect("arity is at least 1");
+        if rest.is_empty() {
+            return self.of(first);
+        }
+        let rest = match rest {
+            [only] => only.clone(),
+            _ => call(name, rest.to_vec(), span),
+        };
+        let op = if name == "min" { CmpOp::Le } else { CmpOp::Ge };
+        let cond = Expression::Compare(op, Box::new(first.clone()), Box::new(rest.clone()));
+        let (then, otherwise) = (self.of(first)?, self.of(&rest)?);
+        if then == otherwise {
+            return Ok(then);
+        }
+        let cond = Box::new(Node::new(cond, span));
+        Ok(Node::new(Expression::If(cond, Box::new(then), Box::new(otherwise)), span))
+    }
+
+    // a user function is differentiated through its body, with the arguments put in
+    fn inline(&mut self, name: &str, args: &[Node], span: Span) -> Result<Node, Error> {
+        if self.inlined.iter().any(|inlined| inlined == name) {
+            let what = format!("the recursive function '{}'", name);
+            return Err(Error::NotDifferentiable(what, span));
+        }
+        let function = &self.env.functions[name];
+        Arity::Exactly(function.params.len()).check(name, args.len(), span)?;
+        let bindings = function.params.iter().map(String::as_str).zip(args).collect();
+        let body = substitute(&function.body, &bindings, span);
+
+        self.inlined.push(name.to_string());
+        let derivative = self.of(&body);
+        self.inlined.pop();
+        derivative
+    }
+}
+
+fn int(n: i32, span: Span) -> Node {
+    Node::new(Expression::Val(Number::Int(n)), span)
+}
+
+fn call(name: &str, args: Vec<Node>, span: Span) -> Node {
+    Node::new(Expression::Call(name.to_string(), args), span)
+}
+
+// a op b without the terms that are 0 and the factors that are 1; the derivative of anything
+// without var in it is a literal 0, so most of the product rule goes away here
+// (unlike the simplifier this doesn't have to keep anything as it was, it makes a new expression)
+fn combine(op: BinOp, a: Node, b: Node, span: Span) -> Node {
+    match op {
+        BinOp::Add if is_int(&a, 0) => b,
+        BinOp::Add | BinOp::Sub if is_int(&b, 0) => a,
+        BinOp::Sub if is_int(&a, 0) => Node::new(Expression::Neg(Box::new(b)), span),
+        BinOp::Mul if is_int(&a, 0) || is_int(&b, 0) => int(0, span),
+        BinOp::Mul if is_int(&a, 1) => b,
+        BinOp::Mul | BinOp::Div | BinOp::Pow if is_int(&b, 1) => a,
+        BinOp::Div if is_int(&a, 0) => int(0, span),
+        BinOp::Pow if is_int(&b, 0) => int(1, span),
+        _ => Node::new(Expression::binary(op, a, b), span),
+    }
+}
+
+// a function body with the arguments in place of the parameters, all of it pointing at the call
+fn substitute(node: &Node, bindings: &HashMap<&str, &Node>, span: Span) -> Node {
+    if let Expression::Var(name) = &node.expr {
+        if let Some(arg) = bindings.get(name.as_str()) {
+            return (*arg).clone();
+        }
+    }
+    let operands = node.expr.children().into_iter().map(|child| substitute(child, bindings, span));
+    Node::new(node.expr.with_children(operands.collect()), span)
+}
+
+//--------------BYTECODE VM---------------
+/*
+eval walks the boxed tree every time. For a formula evaluated over and over we compile
+it once into a flat list of instructions for a stack machine: operands are pushed,
+an operator pops its operands and pushes the result. Same idea as the RPN queue,
+but variables are resolved to slots once per run instead of by name at every use.
+
+&&, || and if compile to jumps, so (unlike with RPN) only what has to run runs:
+    if c then a else b   =>   <c> jf else  <a> jump end  else: <b>  end:
+ */
+
+// how many values the VM keeps on its stack; compile refuses anything deeper
+const VM_STACK_SIZE: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Instr {
+    Push(Number),
+    Load(usize), // slot in Program::names
+    Add,
+    Sub,
+    Mul,
+    Div,
+    FloorDiv,
+    Mod,
+    Pow,
+    Neg,
+    Call(usize, usize), // function (index in Program::functions), argument count
+    Custom(usize, usize), // operator (index in Program::operators), operand count
+    Bool(bool),
+    Compare(CmpOp),
+    Not,
+    Jump(usize),
+    JumpIfFalse(usize), // pops the condition, which has to be a boolean
+}
+
+impl Instr {
+    fn binary(op: BinOp) -> Instr {
+        match op {
+            BinOp::Add => Instr::Add,
+            BinOp::Sub => Instr::Sub,
+            BinOp::Mul => Instr::Mul,
+            BinOp::Div => Instr::Div,
+            BinOp::FloorDiv => Instr::FloorDiv,
+            BinOp::Mod => Instr::Mod,
+            BinOp::Pow => Instr::Pow,
+        }
+    }
+
+    // how much the stack grows (or shrinks) after it runs
+    fn stack_effect(&self) -> isize {
+        match self {
+            Instr::Push(_) | Instr::Load(_) | Instr::Bool(_) => 1,
+            Instr::Add
+            | Instr::Sub
+            | Instr::Mul
+            | Instr::Div
+            | Instr::FloorDiv
+            | Instr::Mod
+            | Instr::Pow
+            | Instr::Compare(_)
+            | Instr::JumpIfFalse(_) => -1,
+            Instr::Neg | Instr::Not | Instr::Jump(_) => 0,
+            Instr::Call(_, argc) => 1 - *argc as isize,
+            Instr::Custom(_, arity) => 1 - *arity as isize,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Program {
+    code: Vec<Instr>,
+    spans: Vec<(Span, Span)>, // per instruction, for the errors: its node and the right operand
+    names: Vec<String>,
+    functions: Vec<String>,
+    operators: Vec<(String, Fixity)>,
+    max_stack: usize,
+}
+
+impl Program {
+    fn compile(node: &Node) -> Result<Program, Error> {
+        if let Some(span) = node.unparsed() {
+            return Err(Error::Unparsed(span));
+        }
+        let mut program = Program {
+            code: Vec::new(),
+            spans: Vec::new(),
+            names: Vec::new(),
+            functions: Vec::new(),
+            operators: Vec::new(),
+            max_stack: 0,
+        };
+        let mut depth = 0;
+        program.emit_node(node, &mut depth);
+
+        if program.max_stack > VM_STACK_SIZE {
+            return Err(Error::TooComplex(program.max_stack, node.span));
+        }
+        Ok(program)
+    }
+
+    fn emit(&mut self, instr: Instr, span: Span, depth: &mut usize) {
+        self.emit_spans(instr, span, span, depth);
+    }
+
+    // a binary instruction has the right operand's span too, a division by zero points at it
+    fn emit_spans(&mut self, instr: Instr, span: Span, right_span: Span, depth: &mut usize) {
+        *depth = (*depth as isize + instr.stack_effect()) as usize;
+        self.max_stack = self.max_stack.max(*depth);
+        self.code.push(instr);
+        self.spans.push((span, right_span));
+    }
+
+    // index of name in the table, added the first time it's seen
+    fn intern(table: &mut Vec<String>, name: &str) -> usize {
+        match table.iter().position(|known| known == name) {
+            Some(index) => index,
+            None => {
+                table.push(name.to_string());
+                table.len() - 1
+            }
+        }
+    }
+
+    fn emit_node(&mut self, node: &Node, depth: &mut usize) {
+        match &node.expr {
+            Expression::Val(number) => self.emit(Instr::Push(number.clone()), node.span, depth),
+            Expression::Error => unreachable!("compile doesn't take a tree with Error nodes"),
+            Expression::Var(name) => {
+                let slot = Program::intern(&mut self.names, name);
+                self.emit(Instr::Load(slot), node.span, depth);
+            }
+            Expression::Add(left, right)
+            | Expression::Sub(left, right)
+            | Expression::Mul(left, right)
+            | Expression::Div(left, right)
+            | Expression::FloorDiv(left, right)
+            | Expression::Mod(left, right)
+            | Expression::Pow(left, right) => {
+                let (op, _, _) = node.expr.as_binary().expect("a binary node");
+                self.emit_node(left, depth);
+                self.emit_node(right, depth);
+                self.emit_spans(Instr::binary(op), node.span, right.span, depth);
+            }
+            Expression::Neg(operand) => {
+                self.emit_node(operand, depth);
+                self.emit(Instr::Neg, node.span, depth);
+            }
+            Expression::Call(name, args) => {
+                for arg in args {
+                    self.emit_node(arg, depth);
+                }
+                let function = Program::intern(&mut self.functions, name);
+                self.emit(Instr::Call(function, args.len()), node.span, depth);
+            }
+            Expression::Custom(op, operands) => {
+                for operand in operands {
+                    self.emit_node(operand, depth);
+                }
+                let key = (op.symbol.clone(), op.fixity);
+                let index = match self.operators.iter().position(|known| *known == key) {
+                    Some(index) => index,
+                    None => {
+                        self.operators.push(key);
+                        self.operators.len() - 1
+                    }
+                };
+                self.emit(Instr::Custom(index, operands.len()), node.span, depth);
+            }
+            Expression::Bool(b) => self.emit(Instr::Bool(*b), node.span, depth),
+            Expression::Compare(op, left, right) => {
+                self.emit_node(left, depth);
+                self.emit_node(right, depth);
+                self.emit(Instr::Compare(*op), node.span, depth);
+            }
+            Expression::And(left, right) => self.emit_logic(left, right, false, node.span, depth),
+            Expression::Or(left, right) => self.emit_logic(left, right, true, node.span, depth),
+            Expression::Not(operand) => {
+                self.emit_node(operand, depth);
+                self.emit(Instr::Not, node.span, depth);
+            }
+            Expression::If(cond, then, otherwise) => {
+                self.emit_node(cond, depth);
+                let to_else = self.emit_jump(Instr::JumpIfFalse(0), cond.span, depth);
+                self.emit_node(then, depth);
+                let to_end = self.emit_jump(Instr::Jump(0), node.span, depth);
+                *depth -= 1; // the else branch starts without the value of the then branch
+                self.patch(to_else);
+                self.emit_node(otherwise, depth);
+                self.patch(to_end);
+            }
+        }
+    }
+
+    // && (stop = false) and || (stop = true): each side goes through a jf, so it has to be a
+    // boolean, and the first one that equals stop jumps to the end with stop as the result
+    fn emit_logic(&mut self, left: &Node, right: &Node, stop: bool, span: Span, depth: &mut usize) {
+        let mut exits = Vec::new();
+        for side in [left, right] {
+            self.emit_node(side, depth);
+            if stop {
+                self.emit(Instr::Not, side.span, depth);
+            }
+            exits.push(self.emit_jump(Instr::JumpIfFalse(0), side.span, depth));
+        }
+        self.emit(Instr::Bool(!stop), span, depth);
+        let to_end = self.emit_jump(Instr::Jump(0), span, depth);
+        *depth -= 1;
+        for exit in exits {
+            self.patch(exit);
+        }
+        self.emit(Instr::Bool(stop), span, depth);
+        self.patch(to_end);
+    }
+
+    // a jump to somewhere not emitted yet; patch points it at the next instruction
+    fn emit_jump(&mut self, jump: Instr, span: Span, depth: &mut usize) -> usize {
+        self.emit(jump, span, depth);
+        self.code.len() - 1
+    }
+
+    fn patch(&mut self, at: usize) {
+        let next = self.code.len();
+        match &mut self.code[at] {
+            Instr::Jump(target) | Instr::JumpIfFalse(target) => *target = next,
+            _ => unreachable!("only jumps are patched"),
+        }
+    }
+}
+
+// the disassembler
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "; {} instructions, stack depth {}", self.code.len(), self.max_stack)?;
+        for (address, instr) in self.code.iter().enumerate() {
+            write!(f, "{:04}  ", address)?;
+            match instr {
+                Instr::Push(number) => writeln!(f, "push   {}", number)?,
+                Instr::Load(slot) => {
+                    writeln!(f, "load   {:<8} ; slot {}", self.names[*slot], slot)?
+                }
+                Instr::Add => writeln!(f, "add")?,
+                Instr::Sub => writeln!(f, "sub")?,
+                Instr::Mul => writeln!(f, "mul")?,
+                Instr::Div => writeln!(f, "div")?,
+                Instr::FloorDiv => writeln!(f, "idiv")?,
+                Instr::Mod => writeln!(f, "mod")?,
+                Instr::Pow => writeln!(f, "pow")?,
+                Instr::Neg => writeln!(f, "neg")?,
+                Instr::Call(function, argc) => {
+                    writeln!(f, "call   {}/{}", self.functions[*function], argc)?
+                }
+                Instr::Custom(index, _) => {
+                    let (symbol, fixity) = &self.operators[*index];
+                    writeln!(f, "op     {} ({})", symbol, fixity.name())?
+                }
+                Instr::Bool(b) => writeln!(f, "push   {}", b)?,
+                Instr::Compare(op) => writeln!(f, "{}", op.mnemonic())?,
+                Instr::Not => writeln!(f, "not")?,
+                Instr::Jump(target) => writeln!(f, "jump   {:04}", target)?,
+                Instr::JumpIfFalse(target) => writeln!(f, "jf     {:04}", target)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// the stack and the slots are kept between runs, so running a program doesn't allocate
+struct Vm {
+    stack: Vec<Value>,
+    slots: Vec<Option<Value>>,
+}
+
+impl Vm {
+    fn new() -> Vm {
+        Vm {
+            stack: Vec::with_capacity(VM_STACK_SIZE),
+            slots: Vec::new(),
+        }
+    }
+
+    // variables come from env, looked up once per run
+    fn run(&mut self, program: &Program, env: &mut Environment) -> Result<Value, Error> {
+        self.slots.clear();
+        self.slots.extend(program.names.iter().map(|name| env.get(name)));
+        self.stack.clear();
+
+        let mut pc = 0;
+        while pc < program.code.len() {
+            let (span, right_span) = program.spans[pc];
+            match program.code[pc] {
+                Instr::Push(ref number) => {
+                    let value = number.literal(&env.options).ok_or(Error::Overflow(span))?;
+                    self.stack.push(Value::Num(value));
+                }
+                Instr::Load(slot) => {
+                    let value = self.slots[slot]
+                        .clone()
+                        .ok_or_else(|| Error::UnknownVariable(program.names[slot].clone(), span))?;
+                    self.stack.push(value);
+                }
+                Instr::Add => self.binary(BinOp::Add, env, span, right_span)?,
+                Instr::Sub => self.binary(BinOp::Sub, env, span, right_span)?,
+                Instr::Mul => self.binary(BinOp::Mul, env, span, right_span)?,
+                Instr::Div => self.binary(BinOp::Div, env, span, right_span)?,
+                Instr::FloorDiv => self.binary(BinOp::FloorDiv, env, span, right_span)?,
+                Instr::Mod => self.binary(BinOp::Mod, env, span, right_span)?,
+                Instr::Pow => self.binary(BinOp::Pow, env, span, right_span)?,
+                Instr::Neg => {
+                    let value = self.pop().number(span)?;
+                    let value = value.neg(&env.options).ok_or(Error::Overflow(span))?;
+                    self.stack.push(Value::Num(value));
+                }
+                Instr::Call(function, argc) => {
+                    let start = self.stack.len() - argc;
+                    let value = env.call(&program.functions[function], &self.stack[start..], span)?;
+                    self.stack.truncate(start);
+                    self.stack.push(value);
+                }
+                Instr::Custom(index, arity) => {
+                    let (symbol, fixity) = &program.operators[index];
+                    let start = self.stack.len() - arity;
+                    let value = env.apply_operator(symbol, *fixity, &self.stack[start..], span)?;
+                    self.stack.truncate(start);
+                    self.stack.push(value);
+                }
+                Instr::Bool(b) => self.stack.push(Value::Bool(b)),
+                Instr::Compare(op) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(Value::Bool(op.apply(&left, &right, span)?));
+                }
+                Instr::Not => {
+                    let value = self.pop().boolean(span)?;
+                    self.stack.push(Value::Bool(!value));
+                }
+                Instr::Jump(target) => {
+                    pc = target;
+                    continue;
+                }
+                Instr::JumpIfFalse(target) => {
+                    if !self.pop().boolean(span)? {
+                        pc = target;
+                        continue;
+                    }
+                }
+            }
+            pc += 1;
+        }
+        Ok(self.pop())
+    }
+
+    // compile checked the depths, so running out of values is a bug in the compiler
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("the compiler balanced the stack")
+    }
+
+    fn binary(
+        &mut self,
+        op: BinOp,
+        env: &Environment,
+        span: Span,
+        right_span: Span,
+    ) -> Result<(), Error> {
+        let right = self.pop().number(right_span)?;
+        let left = self.pop().number(span)?;
+        self.stack.push(Value::Num(op.apply(&left, &right, &env.options, span, right_span)?));
+        Ok(())
+    }
+}
+
+// the tree walker against the VM on the same formula, with x changing every iteration
+// (build with -O for numbers that mean anything)
+fn bench(input: &str, env: &mut Environment, iterations: i32) -> Result<(), Error> {
+    use std::hint::black_box;
+
+    let tree = Parser::new(input, &env.operators)?.parse()?;
+    let program = Program::compile(&tree)?;
+    let mut vm = Vm::new();
+
+    let start = Instant::now();
+    for i in 0..iterations {
+        env.set("x", Value::Num(Number::Int(i % 1000)));
+        black_box(tree.eval(env)?);
+    }
+    let tree_time = start.elapsed();
+
+    let start = Instant::now();
+    for i in 0..iterations {
+        env.set("x", Value::Num(Number::Int(i % 1000)));
+        black_box(vm.run(&program, env)?);
+    }
+    let vm_time = start.elapsed();
+
+    let per_eval = |time: Duration| time.as_nanos() as f64 / iterations as f64;
+    println!("{} evaluations, x = 0..999", iterations);
+    println!("  tree walker  {:>8.1} ns/eval", per_eval(tree_time));
+    println!("  bytecode vm  {:>8.1} ns/eval", per_eval(vm_time));
+    println!("  speedup      {:>8.2}x", tree_time.as_secs_f64() / vm_time.as_secs_f64());
+    Ok(())
+}
+
+//--------------ASSEMBLY---------------
+/*
+The same trees lowered to assembly for a small load/store CPU, in the style of RISC-V RV32I
+with mul, div and rem from the M extension. All registers are 32 bits:
+    zero        always 0
+    t0 .. t6    temporaries, the only ones the allocator hands out
+    a0          the result, when the program returns
+    sp          the stack pointer; spilled values live at 0(sp), 4(sp), ...
+
+    li   rd, n            rd = n
+    mv   rd, rs           rd = rs
+    lw   rd, name         rd = the variable name (a boolean reads as 0 or 1)
+    lw   rd, n(sp)        load a spilled value back
+    sw   rs, n(sp)        spill one
+    addi sp, sp, n        make room for the spills (and give it back at the end)
+    add  sub  mul  div  rem  slt  xor  and   rd, rs1, rs2
+    neg  rd, rs           rd = -rs
+    seqz rd, rs           rd = 1 if rs == 0 else 0 (snez: if rs != 0)
+    beqz rs, label        jump if rs == 0
+    j    label
+    ret                   the value is in a0
+
+Where it isn't RISC-V: add, sub, mul, neg and div do what the overflow setting says (trap, wrap
+or saturate) instead of always wrapping, div and rem trap on a zero divisor, and lw reads a
+variable by name, from the environment, when it runs. div rounds toward zero and rem has the
+sign of the dividend, like C, so // and % take a few more instructions.
+
+Booleans are 0 and 1. The types are worked out when compiling, from what the variables hold at
+that moment, so only ints and booleans get through: no floats, no / that could give a fraction
+(div=true), no user functions or custom operators.
+
+Registers are used like the VM's stack: an operator takes its operands off the top and puts the
+result back. When all of them are taken, the value that has waited longest (the one needed
+last) is stored on the stack and loaded again when its turn comes.
+ */
+
+// t0..t6
+const CPU_REGISTERS: usize = 7;
+// what // and % need at once (both operands, quotient and remainder); the tests compile
+// with this few, to have spills
+const MIN_CPU_REGISTERS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Reg {
+    Zero,
+    T(usize),
+    A0,
+}
+
+impl Reg {
+    // in the simulator's register file
+    fn index(self) -> usize {
+        match self {
+            Reg::Zero => 0,
+            Reg::T(n) => n + 1,
+            Reg::A0 => CPU_REGISTERS + 1,
+        }
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reg::Zero => write!(f, "zero"),
+            Reg::T(n) => write!(f, "t{}", n),
+            Reg::A0 => write!(f, "a0"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Alu {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Slt,
+    Xor,
+    And,
+}
+
+impl Alu {
+    fn mnemonic(self) -> &'static str {
+        match self {
+            Alu::Add => "add",
+            Alu::Sub => "sub",
+            Alu::Mul => "mul",
+            Alu::Div => "div",
+            Alu::Rem => "rem",
+            Alu::Slt => "slt",
+            Alu::Xor => "xor",
+            Alu::And => "and",
+        }
+    }
+
+    // exact in i128, then Number::fit does what the overflow setting says; span is the whole
+    // operation, right_span the divisor, like BinOp::apply
+    fn apply(
+        self,
+        a: i32,
+        b: i32,
+        options: &EvalOptions,
+        span: Span,
+        right_span: Span,
+    ) -> Result<i32, Error> {
+        let (a, b) = (a as i128, b as i128);
+        let exact = match self {
+            Alu::Add => a + b,
+            Alu::Sub => a - b,
+            Alu::Mul => a * b,
+            Alu::Div | Alu::Rem if b == 0 => return Err(Error::DivisionByZero(right_span)),
+            Alu::Div => a / b,
+            Alu::Rem => a % b,
+            Alu::Slt => (a < b) as i128,
+            Alu::Xor => a ^ b,
+            Alu::And => a & b,
+        };
+        match Number::fit(exact, options) {
+            Some(Number::Int(n)) => Ok(n),
+            _ => Err(Error::Overflow(span)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Asm {
+    Li(Reg, i32),
+    Mv(Reg, Reg),
+    Lw(Reg, String, Kind), // a variable, and what it held when this was compiled (Int or Bool)
+    Reload(Reg, usize),    // lw from a stack slot
+    Spill(Reg, usize),     // sw to one
+    Frame(i32),            // addi sp, sp, n
+    Op(Alu, Reg, Reg, Reg),
+    Neg(Reg, Reg),
+    Seqz(Reg, Reg),
+    Snez(Reg, Reg),
+    Beqz(Reg, usize), // label number
+    J(usize),
+    Label(usize),
+    Ret,
+}
+
+fn kind_name(kind: Kind) -> &'static str {
+    if kind == Kind::Bool {
+        "a boolean"
+    } else {
+        "a number"
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Assembly {
+    code: Vec<Asm>,
+    spans: Vec<(Span, Span)>, // per instruction, for the traps: its node and the divisor
+    frame: usize,             // stack slots for spills
+    kind: Kind,               // what a0 holds at the end, Int or Bool
+}
+
+// where a value that's computed but not used yet is kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Place {
+    Reg(usize), // t0, t1, ...
+    Slot(usize), // spilled, at 4 * slot(sp)
+}
+
+// the registers at one point of the code; both arms of an if have to leave them the same way
+#[derive(Debug, Clone)]
+struct Allocation {
+    values: Vec<Place>, // the oldest first, like a stack
+    busy: Vec<bool>,    // per register: holds one of values, or an operand being worked on
+    slots: Vec<bool>,   // per stack slot: in use
+}
+
+enum Arm<'n> {
+    Node(&'n Node), // a branch of an if
+    Bool(&'n Node), // the right side of && and ||, has to be a boolean
+    Const(bool),    // what && and || give when they stop early
+}
+
+struct Codegen<'a> {
+    env: &'a Environment,
+    code: Vec<Asm>,
+    spans: Vec<(Span, Span)>,
+    at: Allocation,
+    frame: usize,
+    labels: usize,
+}
+
+impl Assembly {
+    // registers: how many of t0..t6 it may use
+    fn compile(node: &Node, env: &Environment, registers: usize) -> Result<Assembly, Error> {
+        let options = &env.options;
+        if options.backend != Backend::Machine {
+            let what = format!("with backend={}", options.backend.name());
+            return Err(Error::NotCompilable(what, node.span));
+        }
+        if options.overflow == OverflowMode::Promote {
+            let what = "with overflow=promote".to_string();
+            return Err(Error::NotCompilable(what, node.span));
+        }
+
+        let registers = registers.clamp(MIN_CPU_REGISTERS, CPU_REGISTERS);
+        let mut codegen = Codegen {
+            env,
+            code: Vec::new(),
+            spans: Vec::new(),
+            at: Allocation { values: Vec::new(), busy: vec![false; registers], slots: Vec::new() },
+            frame: 0,
+            labels: 0,
+        };
+        let kind = codegen.node(node)?;
+        let result = codegen.pop(node.span);
+        codegen.emit(Asm::Mv(Reg::A0, Reg::T(result)), node.span);
+
+        // the frame size is only known now, so the addi that opens it goes in at the start
+        let bytes = 4 * codegen.frame as i32;
+        if bytes > 0 {
+            codegen.code.insert(0, Asm::Frame(-bytes));
+            codegen.spans.insert(0, (node.span, node.span));
+            codegen.emit(Asm::Frame(bytes), node.span);
+        }
+        codegen.emit(Asm::Ret, node.span);
+        Ok(Assembly { code: codegen.code, spans: codegen.spans, frame: codegen.frame, kind })
+    }
+
+    // the simulator; the registers and the stack start at 0 on every run and a trap is the
+    // same error eval gives
+    fn run(&self, env: &Environment) -> Result<Value, Error> {
+        let labels: HashMap<usize, usize> = (self.code.iter().enumerate())
+            .filter_map(|(at, asm)| match asm {
+                Asm::Label(label) => Some((*label, at)),
+                _ => None,
+            })
+            .collect();
+        let mut registers = [0; CPU_REGISTERS + 2]; // zero, t0..t6, a0
+        let mut stack = vec![0; self.frame];
+        let mut sp = 4 * self.frame as i32; // the top; the frame is below it
+        let word = |sp: i32, slot: usize| sp as usize / 4 + slot;
+
+        let mut pc = 0;
+        loop {
+            let (span, right_span) = self.spans[pc];
+            let read = |reg: Reg| registers[reg.index()];
+            let written = match &self.code[pc] {
+                Asm::Li(rd, n) => Some((*rd, *n)),
+                Asm::Mv(rd, rs) => Some((*rd, read(*rs))),
+                Asm::Lw(rd, name, kind) => {
+                    let value = env
+                        .get(name)
+                        .ok_or_else(|| Error::UnknownVariable(name.clone(), span))?;
+                    match (value, kind) {
+                        (Value::Num(Number::Int(n)), Kind::Int) => Some((*rd, n)),
+                        (Value::Bool(b), Kind::Bool) => Some((*rd, b as i32)),
+                        // it was something else when this was compiled
+                        (value, _) => {
+                            let what = format!("{} = {}", name, value);
+                            return Err(Error::NotCompilable(what, span));
+                        }
+                    }
+                }
+                Asm::Reload(rd, slot) => Some((*rd, stack[word(sp, *slot)])),
+                Asm::Spill(rs, slot) => {
+                    stack[word(sp, *slot)] = read(*rs);
+                    None
+                }
+                Asm::Frame(bytes) => {
+                    sp += bytes;
+                    None
+                }
+                Asm::Op(alu, rd, a, b) => {
+                    Some((*rd, alu.apply(read(*a), read(*b), &env.options, span, right_span)?))
+                }
+                Asm::Neg(rd, rs) => {
+                    Some((*rd, Alu::Sub.apply(0, read(*rs), &env.options, span, span)?))
+                }
+                Asm::Seqz(rd, rs) => Some((*rd, (read(*rs) == 0) as i32)),
+                Asm::Snez(rd, rs) => Some((*rd, (read(*rs) != 0) as i32)),
+                Asm::Beqz(rs, label) if read(*rs) == 0 => {
+                    pc = labels[label];
+                    continue;
+                }
+                Asm::J(label) => {
+                    pc = labels[label];
+                    continue;
+                }
+                Asm::Beqz(..) | Asm::Label(_) => None,
+                Asm::Ret => break,
+            };
+            if let Some((rd, value)) = written {
+                if rd != Reg::Zero {
+                    registers[rd.index()] = value;
+                }
+            }
+            pc += 1;
+        }
+
+        let a0 = registers[Reg::A0.index()];
+        Ok(match self.kind {
+            Kind::Bool => Value::Bool(a0 != 0),
+            _ => Value::Num(Number::Int(a0)),
+        })
+    }
+}
+
+impl fmt::Display for Assembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let count = self.code.iter().filter(|asm| !matches!(asm, Asm::Label(_))).count();
+        writeln!(f, "# {} instructions, {} spill slots", count, self.frame)?;
+        for asm in &self.code {
+            let (mnemonic, operands) = match asm {
+                Asm::Label(label) => {
+                    writeln!(f, ".L{}:", label)?;
+                    continue;
+                }
+                Asm::Li(rd, n) => ("li", format!("{}, {}", rd, n)),
+                Asm::Mv(rd, rs) => ("mv", format!("{}, {}", rd, rs)),
+                Asm::Lw(rd, name, _) => ("lw", format!("{}, {}", rd, name)),
+                Asm::Reload(rd, slot) => ("lw", format!("{}, {}(sp)", rd, 4 * slot)),
+                Asm::Spill(rs, slot) => ("sw", format!("{}, {}(sp)", rs, 4 * slot)),
+                Asm::Frame(bytes) => ("addi", format!("sp, sp, {}", bytes)),
+                Asm::Op(alu, rd, a, b) => (alu.mnemonic(), format!("{}, {}, {}", rd, a, b)),
+                Asm::Neg(rd, rs) => ("neg", format!("{}, {}", rd, rs)),
+                Asm::Seqz(rd, rs) => ("seqz", format!("{}, {}", rd, rs)),
+                Asm::Snez(rd, rs) => ("snez", format!("{}, {}", rd, rs)),
+                Asm::Beqz(rs, label) => ("beqz", format!("{}, .L{}", rs, label)),
+                Asm::J(label) => ("j", format!(".L{}", label)),
+                Asm::Ret => ("ret", String::new()),
+            };
+            writeln!(f, "{}", format!("    {:<5} {}", mnemonic, operands).trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+impl Codegen<'_> {
+    fn emit(&mut self, asm: Asm, span: Span) {
+        self.emit_spans(asm, span, span);
+    }
+
+    // div and rem have the divisor's span too, a division by zero points at it
+    fn emit_spans(&mut self, asm: Asm, span: Span, right_span: Span) {
+        self.code.push(asm);
+        self.spans.push((span, right_span));
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels += 1;
+        self.labels - 1
+    }
+
+    // a register for the caller; when none is free the oldest value in one goes on the stack
+    fn take(&mut self, span: Span) -> usize {
+        if let Some(free) = self.at.busy.iter().position(|busy| !busy) {
+            self.at.busy[free] = true;
+            return free;
+        }
+        let (index, reg) = (self.at.values.iter().enumerate())
+            .find_map(|(index, place)| match place {
+                Place::Reg(reg) => Some((index, *reg)),
+                Place::Slot(_) => None,
+            })
+            .expect("MIN_CPU_REGISTERS is more than any instruction sequence holds at once");
+        let slot = match self.at.slots.iter().position(|used| !used) {
+            Some(slot) => slot,
+            None => {
+                self.at.slots.push(false);
+                self.at.slots.len() - 1
+            }
+        };
+        self.at.slots[slot] = true;
+        self.frame = self.frame.max(self.at.slots.len());
+        self.at.values[index] = Place::Slot(slot);
+        self.emit(Asm::Spill(Reg::T(reg), slot), span);
+        reg // still busy, now with whatever the caller puts in it
+    }
+
+    fn release(&mut self, reg: usize) {
+        self.at.busy[reg] = false;
+    }
+
+    fn push(&mut self, reg: usize) {
+        self.at.values.push(Place::Reg(reg));
+    }
+
+    // the newest value, in a register that's the caller's until it's pushed or released
+    fn pop(&mut self, span: Span) -> usize {
+        match self.at.values.pop().expect("the value was pushed") {
+            Place::Reg(reg) => reg,
+            Place::Slot(slot) => {
+                let reg = self.take(span);
+                self.emit(Asm::Reload(Reg::T(reg), slot), span);
+                self.at.slots[slot] = false;
+                reg
+            }
+        }
+    }
+
+    fn constant(&mut self, n: i32, span: Span) {
+        let reg = self.take(span);
+        self.emit(Asm::Li(Reg::T(reg), n), span);
+        self.push(reg);
+    }
+
+    // node's value ends up on top of the values; returns its type, Int or Bool
+    fn node(&mut self, node: &Node) -> Result<Kind, Error> {
+        let span = node.span;
+        match &node.expr {
+            Expression::Error => return Err(Error::Unparsed(span)),
+            Expression::Val(number) => match number.literal(&self.env.options) {
+                Some(Number::Int(n)) => self.constant(n, span),
+                Some(_) => return Err(Error::NotCompilable(number.to_string(), span)),
+                None => return Err(Error::Overflow(span)),
+            },
+            Expression::Bool(b) => {
+                self.constant(*b as i32, span);
+                return Ok(Kind::Bool);
+            }
+            Expression::Var(name) => {
+                let kind = match self.env.get(name) {
+                    Some(Value::Num(Number::Int(_))) => Kind::Int,
+                    Some(Value::Bool(_)) => Kind::Bool,
+                    Some(value) => {
+                        return Err(Error::NotCompilable(format!("{} = {}", name, value), span))
+                    }
+                    None => return Err(Error::UnknownVariable(name.clone(), span)),
+                };
+                let reg = self.take(span);
+                self.emit(Asm::Lw(Reg::T(reg), name.clone(), kind), span);
+                self.push(reg);
+                return Ok(kind);
+            }
+            Expression::Add(..)
+            | Expression::Sub(..)
+            | Expression::Mul(..)
+            | Expression::Div(..)
+            | Expression::FloorDiv(..)
+            | Expression::Mod(..)
+            | Expression::Pow(..) => self.arithmetic(node)?,
+            Expression::Neg(operand) => {
+                self.expect(operand, Kind::Int)?;
+                let reg = self.pop(span);
+                self.emit(Asm::Neg(Reg::T(reg), Reg::T(reg)), span);
+                self.push(reg);
+            }
+            Expression::Call(name, args) => self.call(name, args, span)?,
+            Expression::Custom(op, _) => {
+                let what = format!("the operator '{}'", op.symbol);
+                return Err(Error::NotCompilable(what, span));
+            }
+            Expression::Compare(op, left, right) => {
+                self.compare(*op, left, right, span)?;
+                return Ok(Kind::Bool);
+            }
+            Expression::And(left, right) => {
+                return self.branch(left, Arm::Bool(right), Arm::Const(false), span)
+            }
+            Expression::Or(left, right) => {
+                return self.branch(left, Arm::Const(true), Arm::Bool(right), span)
+            }
+            Expression::Not(operand) => {
+                self.expect(operand, Kind::Bool)?;
+                let reg = self.pop(span);
+                self.emit(Asm::Seqz(Reg::T(reg), Reg::T(reg)), span);
+                self.push(reg);
+                return Ok(Kind::Bool);
+            }
+            Expression::If(cond, then, otherwise) => {
+                return self.branch(cond, Arm::Node(then), Arm::Node(otherwise), span)
+            }
+        }
+        Ok(Kind::Int)
+    }
+
+    // the same type errors eval gives, only they're found before anything runs
+    fn expect(&mut self, node: &Node, kind: Kind) -> Result<(), Error> {
+        let found = self.node(node)?;
+        if found != kind {
+            return Err(Error::TypeMismatch(kind_name(kind), kind_name(found), node.span));
+        }
+        Ok(())
+    }
+
+    fn arithmetic(&mut self, node: &Node) -> Result<(), Error> {
+        let (op, left, right) = node.expr.as_binary().expect("a binary node");
+        let division = self.env.options.division;
+        if op == BinOp::Pow {
+            return self.power(left, right, node.span);
+        }
+        if op == BinOp::Div && division == DivisionMode::True {
+            return Err(Error::NotCompilable(format!("{} with div=true", node), node.span));
+        }
+        self.expect(left, Kind::Int)?;
+        self.expect(right, Kind::Int)?;
+        let b = self.pop(right.span);
+        let a = self.pop(left.span);
+        let (span, divisor) = (node.span, right.span);
+
+        let result = match (op, division) {
+            (BinOp::Add | BinOp::Sub | BinOp::Mul, _) | (BinOp::Div, DivisionMode::Truncate) => {
+                let alu = match op {
+                    BinOp::Add => Alu::Add,
+                    BinOp::Sub => Alu::Sub,
+                    BinOp::Mul => Alu::Mul,
+                    _ => Alu::Div,
+                };
+                self.emit_spans(Asm::Op(alu, Reg::T(a), Reg::T(a), Reg::T(b)), span, divisor);
+                a
+            }
+            (BinOp::Mod, _) => self.floor_mod(a, b, span, divisor),
+            _ => self.floor_div(a, b, span, divisor),
+        };
+        for reg in [a, b] {
+            if reg != result {
+                self.release(reg);
+            }
+        }
+        self.push(result);
+        Ok(())
+    }
+
+    // div rounds toward zero, one less is the floor when there's a remainder of the other sign:
+    //   q = a div b,  r = a rem b,  q -= (r != 0) & ((r ^ b) < 0)
+    // a is the scratch register; it and b are the caller's to release
+    fn floor_div(&mut self, a: usize, b: usize, span: Span, divisor: Span) -> usize {
+        let (q, r) = (self.take(span), self.take(span));
+        let [ta, tb, tq, tr] = [a, b, q, r].map(Reg::T);
+        self.emit_spans(Asm::Op(Alu::Div, tq, ta, tb), span, divisor);
+        self.emit_spans(Asm::Op(Alu::Rem, tr, ta, tb), span, divisor);
+        self.emit(Asm::Op(Alu::Xor, ta, tr, tb), span);
+        self.emit(Asm::Op(Alu::Slt, ta, ta, Reg::Zero), span);
+        self.emit(Asm::Snez(tr, tr), span);
+        self.emit(Asm::Op(Alu::And, ta, ta, tr), span);
+        self.emit(Asm::Op(Alu::Sub, tq, tq, ta), span);
+        self.release(r);
+        q
+    }
+
+    // rem has the sign of a; the floor one has b's, so b is added when they differ:
+    //   r = a rem b,  r += b * ((r != 0) & ((r ^ b) < 0))
+    fn floor_mod(&mut self, a: usize, b: usize, span: Span, divisor: Span) -> usize {
+        let (r, t) = (self.take(span), self.take(span));
+        let [ta, tb, tr, tt] = [a, b, r, t].map(Reg::T);
+        self.emit_spans(Asm::Op(Alu::Rem, tr, ta, tb), span, divisor);
+        self.emit(Asm::Op(Alu::Xor, ta, tr, tb), span);
+        self.emit(Asm::Op(Alu::Slt, ta, ta, Reg::Zero), span);
+        self.emit(Asm::Snez(tt, tr), span);
+        self.emit(Asm::Op(Alu::And, ta, ta, tt), span);
+        self.emit(Asm::Op(Alu::Mul, ta, ta, tb), span);
+        self.emit(Asm::Op(Alu::Add, tr, tr, ta), span);
+        self.release(t);
+        r
+    }
+
+    // only a constant exponent: squaring and multiplying from the top bit down, every partial
+    // result is a lower power than the whole, so it overflows (or saturates) only when that does
+    fn power(&mut self, base: &Node, exponent: &Node, span: Span) -> Result<(), Error> {
+        let exp = match exponent.expr {
+            Expression::Val(Number::Int(exp)) if exp >= 0 => exp as u32,
+            _ => {
+                let what = format!("a power with exponent {}", exponent);
+                return Err(Error::NotCompilable(what, exponent.span));
+            }
+        };
+        self.expect(base, Kind::Int)?;
+        let b = self.pop(base.span);
+        let result = self.take(span);
+        let (tb, tr) = (Reg::T(b), Reg::T(result));
+        if exp == 0 {
+            self.emit(Asm::Li(tr, 1), span);
+        } else {
+            self.emit(Asm::Mv(tr, tb), span);
+            for bit in (0..31 - exp.leading_zeros()).rev() {
+                self.emit(Asm::Op(Alu::Mul, tr, tr, tr), span);
+                if exp >> bit & 1 == 1 {
+                    self.emit(Asm::Op(Alu::Mul, tr, tr, tb), span);
+                }
+            }
+        }
+        self.release(b);
+        self.push(result);
+        Ok(())
+    }
+
+    // abs, min, max and pow; the rest of the built-ins need floats
+    fn call(&mut self, name: &str, args: &[Node], span: Span) -> Result<(), Error> {
+        if self.env.functions.contains_key(name) {
+            return Err(Error::NotCompilable(format!("the function '{}'", name), span));
+        }
+        match BUILTINS.iter().find(|builtin| builtin.name == name) {
+            Some(builtin) => builtin.arity.check(name, args.len(), span)?,
+            None => return Err(Error::UnknownFunction(name.to_string(), span)),
+        }
+
+        match name {
+            "pow" => return self.power(&args[0], &args[1], span),
+            "abs" => {
+                self.expect(&args[0], Kind::Int)?;
+                let x = self.pop(span);
+                let negative = self.take(span);
+                let skip = self.label();
+                let (tx, tn) = (Reg::T(x), Reg::T(negative));
+                self.emit(Asm::Op(Alu::Slt, tn, tx, Reg::Zero), span);
+                self.emit(Asm::Beqz(tn, skip), span);
+                self.emit(Asm::Neg(tx, tx), span);
+                self.emit(Asm::Label(skip), span);
+                self.release(negative);
+                self.push(x);
+            }
+            "min" | "max" => {
+                self.expect(&args[0], Kind::Int)?;
+                for arg in &args[1..] {
+                    self.expect(arg, Kind::Int)?;
+                    let b = self.pop(arg.span);
+                    let a = self.pop(span);
+                    let less = self.take(span);
+                    let keep = self.label();
+                    let [ta, tb, tl] = [a, b, less].map(Reg::T);
+                    // a stays unless b is strictly smaller (bigger), ties keep the first like
+                    // builtin_min
+                    let (x, y) = if name == "min" { (tb, ta) } else { (ta, tb) };
+                    self.emit(Asm::Op(Alu::Slt, tl, x, y), span);
+                    self.emit(Asm::Beqz(tl, keep), span);
+                    self.emit(Asm::Mv(ta, tb), span);
+                    self.emit(Asm::Label(keep), span);
+                    self.release(less);
+                    self.release(b);
+                    self.push(a);
+                }
+            }
+            _ => return Err(Error::NotCompilable(name.to_string(), span)),
+        }
+        Ok(())
+    }
+
+    // slt is the only comparison, the others are made from it, xor and seqz
+    fn compare(&mut self, op: CmpOp, left: &Node, right: &Node, span: Span) -> Result<(), Error> {
+        let kinds = (self.node(left)?, self.node(right)?);
+        let equality = matches!(op, CmpOp::Eq | CmpOp::Ne);
+        match kinds {
+            (Kind::Int, Kind::Int) => {}
+            (Kind::Bool, Kind::Bool) if equality => {}
+            (Kind::Int, found) => {
+                return Err(Error::TypeMismatch("a number", kind_name(found), span))
+            }
+            (Kind::Bool, found) if equality => {
+                return Err(Error::TypeMismatch("a boolean", kind_name(found), span))
+            }
+            _ => return Err(Error::TypeMismatch("a number", "a boolean", span)),
+        }
+        let b = self.pop(right.span);
+        let a = self.pop(left.span);
+        let (ta, tb) = (Reg::T(a), Reg::T(b));
+        let code = match op {
+            CmpOp::Lt => vec![Asm::Op(Alu::Slt, ta, ta, tb)],
+            CmpOp::Gt => vec![Asm::Op(Alu::Slt, ta, tb, ta)],
+            CmpOp::Le => vec![Asm::Op(Alu::Slt, ta, tb, ta), Asm::Seqz(ta, ta)], // !(b < a)
+            CmpOp::Ge => vec![Asm::Op(Alu::Slt, ta, ta, tb), Asm::Seqz(ta, ta)],
+            CmpOp::Eq => vec![Asm::Op(Alu::Xor, ta, ta, tb), Asm::Seqz(ta, ta)],
+            CmpOp::Ne => vec![Asm::Op(Alu::Xor, ta, ta, tb), Asm::Snez(ta, ta)],
+        };
+        for asm in code {
+            self.emit(asm, span);
+        }
+        self.release(b);
+        self.push(a);
+        Ok(())
+    }
+
+    // if c then a else b; && is if c then b else false and || is if c then true else b
+    //   <c>  beqz c, else  <a>  mv c, a  j end  else:  <b>  mv c, b  end:
+    // c's register is free once beqz has read it, so both arms leave their value there
+    fn branch(
+        &mut self,
+        cond: &Node,
+        then: Arm,
+        otherwise: Arm,
+        span: Span,
+    ) -> Result<Kind, Error> {
+        self.expect(cond, Kind::Bool)?;
+        let result = self.pop(cond.span);
+        let (to_else, to_end) = (self.label(), self.label());
+        self.emit(Asm::Beqz(Reg::T(result), to_else), cond.span);
+        self.release(result);
+
+        let fork = self.at.clone();
+        let kind = self.arm(then, result, &fork, span)?;
+        self.emit(Asm::J(to_end), span);
+        self.emit(Asm::Label(to_else), span);
+        let other = self.arm(otherwise, result, &fork, span)?;
+        self.emit(Asm::Label(to_end), span);
+        if kind != other {
+            let what = format!("an if with {} and {} branch", kind_name(kind), kind_name(other));
+            return Err(Error::NotCompilable(what, span));
+        }
+        self.at.busy[result] = true;
+        self.push(result);
+        Ok(kind)
+    }
+
+    // one arm, ending with its value in result and everything else where it was at the fork:
+    // what the arm spilled is loaded back (it only ever spills older values, it doesn't use them)
+    fn arm(
+        &mut self,
+        arm: Arm,
+        result: usize,
+        fork: &Allocation,
+        span: Span,
+    ) -> Result<Kind, Error> {
+        let kind = match arm {
+            Arm::Node(node) => self.node(node)?,
+            Arm::Bool(node) => {
+                self.expect(node, Kind::Bool)?;
+                Kind::Bool
+            }
+            Arm::Const(b) => {
+                self.constant(b as i32, span);
+                Kind::Bool
+            }
+        };
+        let reg = self.pop(span);
+        if reg != result {
+            self.emit(Asm::Mv(Reg::T(result), Reg::T(reg)), span);
+        }
+        self.release(reg);
+
+        let reloads: Vec<Asm> = (self.at.values.iter().zip(&fork.values))
+            .filter_map(|(now, before)| match (now, before) {
+                (Place::Slot(slot), Place::Reg(reg)) => Some(Asm::Reload(Reg::T(*reg), *slot)),
+                _ => None,
+            })
+            .collect();
+        for reload in reloads {
+            self.emit(reload, span);
+        }
+        self.at = fork.clone();
+        Ok(kind)
+    }
+}
+
+//--------------BATCH---------------
+/*
+basic-parser --batch=formulas.txt [--format=csv|json] evaluates a whole file, one statement per
+line, and writes a table of the results to stdout: line number, input, value or error and how
+long it took. A line that fails is written down like any other and the next one goes on.
+# starts a comment (to the end of the line, // is already floor division) and blank lines are
+skipped, let and fn lines are there for the lines after them, like in the REPL. - is stdin.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Csv,
+    Json,
+}
+
+impl Format {
+    const ALL: [Format; 2] = [Format::Csv, Format::Json];
+
+    fn from_name(name: &str) -> Option<Format> {
+        Format::ALL.iter().copied().find(|format| format.name() == name)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Json => "json",
+        }
+    }
+
+    fn header(self) -> &'static str {
+        match self {
+            Format::Csv => "line,input,value,error,time_us\n",
+            Format::Json => "[",
+        }
+    }
+
+    // json needs a comma between the rows, first says there's none before this one
+    fn row(self, row: &Row, first: bool) -> String {
+        let (value, error) = match &row.outcome {
+            Ok(value) => (value.as_ref().map(Value::to_string), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let micros = row.time.as_secs_f64() * 1e6;
+        match self {
+            Format::Csv => {
+                let field = |text: Option<String>| csv_field(&text.unwrap_or_default());
+                let (input, value, error) = (csv_field(&row.input), field(value), field(error));
+                format!("{},{},{},{},{:.1}\n", row.line, input, value, error, micros)
+            }
+            Format::Json => {
+                let field = |text: Option<String>| match text {
+                    Some(text) => json_string(&text),
+                    None => "null".to_string(),
+                };
+                let fields = [
+                    ("line", row.line.to_string()),
+                    ("input", json_string(&row.input)),
+                    ("value", field(value)),
+                    ("error", field(error)),
+                    ("time_us", format!("{:.1}", micros)),
+                ];
+                let fields: Vec<String> =
+                    fields.iter().map(|(name, json)| format!("\"{}\": {}", name, json)).collect();
+                let separator = if first { "\n" } else { ",\n" };
+                format!("{}  {{{}}}", separator, fields.join(", "))
+            }
+        }
+    }
+
+    fn footer(self) -> &'static str {
+        match self {
+            Format::Csv => "",
+            Format::Json => "\n]\n",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Row {
+    line: usize,   // from 1, the skipped lines count too
+    input: String, // without the comment
+    outcome: Result<Option<Value>, Error>, // fn has no value
+    time: Duration, // parsing and evaluating
+}
+
+// quoted only when it has to be (RFC 4180): a comma, a quote or a line break in it
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for ch in text.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// every line of input through the parser and eval, the rows go to emit as they're done (a big
+// file isn't kept in memory); returns how many rows there were and how many of them failed
+fn batch(
+    mut input: impl BufRead,
+    env: &mut Environment,
+    mut emit: impl FnMut(&Row) -> io::Result<()>,
+) -> io::Result<(usize, usize)> {
+    let (mut rows, mut failed) = (0, 0);
+    let mut bytes = Vec::new();
+    let mut line = 0;
+    loop {
+        bytes.clear();
+        if input.read_until(b'\n', &mut bytes)? == 0 {
+            break;
+        }
+        line += 1;
+        // not UTF-8 is one bad line (with an invalid character in it), not the end of the run
+        let text = String::from_utf8_lossy(&bytes);
+        let text = text.split('#').next().unwrap_or_default().trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        let start = Instant::now();
+        let outcome = Parser::new(text, &env.operators)
+            .and_then(|mut parser| parser.parse_statement())
+            .and_then(|statement| statement.run(env));
+        let time = start.elapsed();
+        if let Ok(Some(value)) = &outcome {
+            env.set("_", value.clone());
+        }
+
+        rows += 1;
+        failed += outcome.is_err() as usize;
+        emit(&Row { line, input: text.to_string(), outcome, time })?;
+    }
+    Ok((rows, failed))
+}
+
+// --batch: the table on stdout
+fn batch_file(path: &str, format: Format, env: &mut Environment) -> io::Result<(usize, usize)> {
+    // opened first, so a missing file doesn't leave half a table behind
+    let input: Box<dyn BufRead> = match path {
+        "-" => Box::new(io::stdin().lock()),
+        _ => Box::new(io::BufReader::new(std::fs::File::open(path)?)),
+    };
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    out.write_all(format.header().as_bytes())?;
+    let mut first = true;
+    let mut emit = |row: &Row| {
+        let text = format.row(row, first);
+        first = false;
+        out.write_all(text.as_bytes())
+    };
+    let counts = batch(input, env, &mut emit)?;
+    out.write_all(format.footer().as_bytes())?;
+    out.flush()?;
+    Ok(counts)
+}
+
+//--------------RECOVERY---------------
+/*
+The parser stops at the first error, which is what the REPL wants. For a whole file (a batch
+file before it's run, or an editor showing everything that's wrong) parse_file doesn't: the
+lines are statements like in --batch (# comments, blank lines skipped) and on an error the
+parser writes it down and skips, panic mode, to where it can go on again:
+
+    inside ( ... )        to the ')' that closes it, the group is an Error node
+    a call argument       to the next ',' (or the ')'), f(1, <error>, 3)
+    anything else         to the end of the statement
+    a char the lexer
+    can't read            the lexer goes on after it, an operand next to it goes with it
+
+So one pass gives every diagnostic, sorted by position, and a tree for every line with
+<error> where something was skipped:
+
+    let y = (2 * ) + f(1, 2 3, 4)    let y = <error> + f(1, <error>, 4)
+    1 $ 2 + 3                        <error> + 3
+    1 + (3                           1 + <error>
+
+The Error nodes don't evaluate (Unparsed), so the trees are for looking at. basic-parser
+--lint=file prints the diagnostics and exits with 1 if there were any, :recover shows both.
+ */
+
+#[derive(Debug)]
+struct Recovered {
+    statements: Vec<Statement>, // one per line that isn't blank or a comment
+    diagnostics: Vec<Error>,    // the spans are into the whole text
+}
+
+fn parse_file(text: &str, operators: &OperatorTable) -> Recovered {
+    let symbols = operators.symbols();
+    let mut statements = Vec::new();
+    let mut diagnostics = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        // without the comment and the line break, an unfinished line ends where its text does
+        let code = line.split('#').next().unwrap_or_default().trim_end();
+        if code.is_empty() {
+            continue;
+        }
+
+        // the lexer sees the text up to the end of the line, so Eof is where the line ends
+        let (tokens, errors) = lexer::tokenize_lossy(&text[..start + code.len()], start, &symbols);
+        diagnostics.extend(errors);
+        let mut parser = Parser::recovering(tokens, text, operators);
+        // what's left for parse_statement to fail on is a bad let or fn, the line is lost
+        let statement = parser.parse_statement().unwrap_or_else(|e| {
+            let first = start + code.len() - code.trim_start().len();
+            let span = Span::new(first, start + code.len());
+            parser.diagnostics.push(e);
+            Statement::Expr(Node::new(Expression::Error, span))
+        });
+        statements.push(statement);
+        diagnostics.append(&mut parser.diagnostics);
+    }
+    diagnostics.sort_by_key(|e| e.span().start); // stable, the lexer's come first at a tie
+    Recovered { statements, diagnostics }
+}
+
+// --lint: the diagnostics on stderr, returns how many statements and errors there were
+fn lint_file(path: &str, operators: &OperatorTable) -> io::Result<(usize, usize)> {
+    let mut bytes = Vec::new();
+    match path {
+        "-" => io::stdin().lock().read_to_end(&mut bytes)?,
+        _ => std::fs::File::open(path)?.read_to_end(&mut bytes)?,
+    };
+    let text = String::from_utf8_lossy(&bytes);
+    let recovered = parse_file(&text, operators);
+    for e in &recovered.diagnostics {
+        eprintln!("{}\n", e.render(&text));
+    }
+    Ok((recovered.statements.len(), recovered.diagnostics.len()))
+}
+
+//--------------ROUTES---------------
+/*
+:check runs one expression through every way this program has of getting a value out of it
+(the tree, Shunting Yard and RPN, the conversions, the tracer, the VM, JSON) and prints what
+each one gave; they should all say the same, and the tests hold them to it.
+ */
+
+// the same expression through every route we have; they all have to come out the same
+fn all_paths(input: &str, env: &mut Environment) -> Vec<(&'static str, Result<Value, Error>)> {
+    let operators = env.operators.clone();
+    let operators = &operators;
+    let tree = Parser::new(input, operators).and_then(|mut parser| parser.parse());
+    let through_infix = |items: Result<Vec<(RpnItem, Span)>, Error>, env: &mut Environment| {
+        let text = Notation::Infix.write(&items?)?;
+        Parser::new(&text, operators)?.parse()?.eval(env)
+    };
+
+    vec![
+        ("tree", tree.as_ref().map_err(Error::clone).and_then(|expr| expr.eval(env))),
+        ("shunting yard + rpn", shunting_yard(input, operators)
+            .and_then(|items| eval_postfix(&items, env))),
+        ("tree -> rpn", tree.as_ref().map_err(Error::clone).and_then(|expr| {
+            let mut items = Vec::new();
+            expr.postfix(&mut items);
+            eval_postfix(&items, env)
+        })),
+        ("infix -> prefix -> rpn", Notation::Infix
+            .read(input, operators)
+            .and_then(|items| Notation::Prefix.write(&items))
+            .and_then(|text| Notation::Prefix.read(&text, operators))
+            .and_then(|items| eval_postfix(&items, env))),
+        ("infix -> postfix -> infix", {
+            let items = Notation::Infix
+                .read(input, operators)
+                .and_then(|items| Notation::Postfix.write(&items))
+                .and_then(|text| Notation::Postfix.read(&text, operators));
+            through_infix(items, env)
+        }),
+        ("step tracer", tree.as_ref().map_err(Error::clone).and_then(|expr| expr.trace(env).1)),
+        ("bytecode vm", tree.as_ref().map_err(Error::clone).and_then(|expr| {
+            Vm::new().run(&Program::compile(expr)?, env)
+        })),
+        ("tree -> json -> tree", tree.as_ref().map_err(Error::clone).and_then(|expr| {
+            read_json(&to_json(&expr.expr))?.eval(env)
+        })),
+        ("infix -> sexpr -> infix", {
+            let items = Notation::Infix
+                .read(input, operators)
+                .and_then(|items| Notation::Sexpr.write(&items))
+                .and_then(|text| Notation::Sexpr.read(&text, operators));
+            through_infix(items, env)
+        }),
+        ("infix -> prefix -> infix", {
+            let items = Notation::Infix
+                .read(input, operators)
+                .and_then(|items| Notation::Prefix.write(&items))
+                .and_then(|text| Notation::Prefix.read(&text, operators));
+            through_infix(items, env)
+        }),
+    ]
+}
+
+fn print_routes(results: &[(&str, Result<Value, Error>)]) {
+    for (route, result) in results {
+        match result {
+            Ok(value) => println!("  {:<28} {}", route, value),
+            Err(e) => println!("  {:<28} error: {}", route, e),
+        }
+    }
+}
+
+// a small line editor (arrows, backspace, history) for the REPL
+// no crates here, so raw mode is done by asking stty, like you would from a shell script
+mod line_editor {
+    use std::fs;
+    use std::io::{self, IsTerminal, Read, Write};
+    use std::path::PathBuf;
+    use std::process::{Command, Stdio};
+
+    const MAX_HISTORY: usize = 500;
+
+    pub(crate) struct LineEditor {
+        history: Vec<String>,
+        path: Option<PathBuf>, //unde salvez istoricul intre sesiuni
+        interactive: bool,
+    }
+
+    fn stty(args: &[&str]) -> Option<String> {
+        // stty works on its stdin, so it has to be our terminal
+        let output = Command::new("stty").args(args).stdin(Stdio::inherit()).output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        String::from_utf8(output.stdout).ok()
+    }
+
+    // the terminal stays raw while this is alive and gets restored on drop
+    struct RawMode {
+        saved: String,
+    }
+
+    impl RawMode {
+        fn enable() -> Option<RawMode> {
+            let saved = stty(&["-g"])?.trim().to_string();
+            // -isig: Ctrl-C comes to us as a byte instead of killing us with the terminal still raw
+            stty(&["-icanon", "-echo", "-isig", "min", "1"])?;
+            Some(RawMode { saved })
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            stty(&[&self.saved]);
+        }
+    }
+
+    fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+        let mut byte = [0u8; 1];
+        match input.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    fn redraw(out: &mut impl Write, prompt: &str, line: &[char], cursor: usize) -> io::Result<()> {
+        let text: String = line.iter().collect();
+        // \r la inceput de rand, \x1b[K sterge restul randului
+        write!(out, "\r{}{}\x1b[K", prompt, text)?;
+        if cursor < line.len() {
+            write!(out, "\x1b[{}D", line.len() - cursor)?;
+        }
+        out.flush()
+    }
+
+    impl LineEditor {
+        pub(crate) fn new(path: Option<PathBuf>) -> LineEditor {
+            let history = path
+                .as_ref()
+                .and_then(|path| fs::read_to_string(path).ok())
+                .map(|text| text.lines().map(str::to_string).collect())
+                .unwrap_or_default();
+            LineEditor {
+                history,
+                path,
+                interactive: io::stdin().is_terminal(),
+            }
+        }
+
+        pub(crate) fn is_interactive(&self) -> bool {
+            self.interactive
+        }
+
+        pub(crate) fn history(&self) -> &[String] {
+            &self.history
+        }
+
+        // None at the end of input (Ctrl-D or a closed pipe)
+        pub(crate) fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+            if self.interactive {
+                if let Some(_raw) = RawMode::enable() {
+                    return self.edit(prompt);
+                }
+            }
+
+            // piped input (or no stty): plain lines, no prompt
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()))
+        }
+
+        pub(crate) fn add_history(&mut self, line: &str) {
+            if line.is_empty() || self.history.last().is_some_and(|last| last == line) {
+                return;
+            }
+            self.history.push(line.to_string());
+            if self.history.len() > MAX_HISTORY {
+                self.history.remove(0);
+            }
+
+            if let Some(path) = &self.path {
+                // istoricul e mic, il rescriu tot
+                let mut text = self.history.join("\n");
+                text.push('\n');
+                if let Err(e) = fs::write(path, text) {
+                    eprintln!("could not save history to {}: {}", path.display(), e);
+                    self.path = None; //nu mai incerc
+                }
+            }
+        }
+
+        fn edit(&mut self, prompt: &str) -> io::Result<Option<String>> {
+            self.edit_from(&mut io::stdin().lock(), &mut io::stdout(), prompt)
+        }
+
+        // the keys come from stdin and the line goes to stdout, except in the tests
+        pub(crate) fn edit_from(
+            &mut self,
+            stdin: &mut impl Read,
+            out: &mut impl Write,
+            prompt: &str,
+        ) -> io::Result<Option<String>> {
+            let mut line: Vec<char> = Vec::new();
+            let mut cursor = 0;
+            // where we are in the history; history.len() is the line being typed
+            let mut browsing = self.history.len();
+            let mut draft = Vec::new();
+
+            redraw(out, prompt, &line, cursor)?;
+            loop {
+                let byte = match read_byte(stdin)? {
+                    Some(byte) => byte,
+                    None => return Ok(None),
+                };
+
+                match byte {
+                    b'\r' | b'\n' => {
+                        write!(out, "\r\n")?;
+                        return Ok(Some(line.iter().collect()));
+                    }
+                    // Ctrl-D: quit on an empty line, delete otherwise
+                    4 if line.is_empty() => {
+                        write!(out, "\r\n")?;
+                        return Ok(None);
+                    }
+                    4 if cursor < line.len() => {
+                        line.remove(cursor);
+                    }
+                    // Ctrl-C: drop the line and start over
+                    3 => {
+                        write!(out, "^C\r\n")?;
+                        line.clear();
+                        cursor = 0;
+                        browsing = self.history.len();
+                    }
+                    127 | 8 if cursor > 0 => {
+                        cursor -= 1;
+                        line.remove(cursor);
+                    }
+                    1 => cursor = 0,           // Ctrl-A
+                    5 => cursor = line.len(), // Ctrl-E
+                    // escape sequences: ESC [ A (sus), B (jos), C (dreapta), D (stanga), H, F, 3~
+                    0x1b => {
+                        if read_byte(stdin)? != Some(b'[') {
+                            continue;
+                        }
+                        match read_byte(stdin)? {
+                            Some(b'A') if browsing > 0 => {
+                                if browsing == self.history.len() {
+                                    draft = line.clone();
+                                }
+                                browsing -= 1;
+                                line = self.history[browsing].chars().collect();
+                                cursor = line.len();
+                            }
+                            Some(b'B') if browsing < self.history.len() => {
+                                browsing += 1;
+                                line = match self.history.get(browsing) {
+                                    Some(entry) => entry.chars().collect(),
+                                    None => draft.clone(),
+                                };
+                                cursor = line.len();
+                            }
+                            Some(b'C') if cursor < line.len() => cursor += 1,
+                            Some(b'D') if cursor > 0 => cursor -= 1,
+                            Some(b'H') => cursor = 0,
+                            Some(b'F') => cursor = line.len(),
+                            Some(b'3')
+                                if read_byte(stdin)? == Some(b'~') && cursor < line.len() =>
+                            {
+                                line.remove(cursor);
+                            }
+                            _ => {}
+                        }
+                    }
+                    // printable, possibly the first byte of a multi-byte utf-8 char
+                    first if first >= 0x20 => {
+                        let len = match first {
+                            0x00..=0x7f => 1,
+                            0xc0..=0xdf => 2,
+                            0xe0..=0xef => 3,
+                            _ => 4,
+                        };
+                        let mut bytes = vec![first];
+                        for _ in 1..len {
+                            bytes.extend(read_byte(stdin)?);
+                        }
+                        if let Ok(text) = std::str::from_utf8(&bytes) {
+                            for ch in text.chars() {
+                                line.insert(cursor, ch);
+                                cursor += 1;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+                redraw(out, prompt, &line, cursor)?;
+            }
+        }
+    }
+}
+
+use line_editor::LineEditor;
+
+const USAGE: &str = "usage: basic-parser [--div=true|trunc|floor] \
+[--overflow=checked|wrap|saturate|promote] [--backend=machine|bigint|rational] \
+[--tree=expr] [--dot=expr] [--batch[=file] [--format=csv|json]] [--lint[=file]] [name=expr]...";
+
+const HELP: &str = "\
+statements:
+  1 + 2 * x            evaluate an expression (_ holds the last result)
+  let x = 3 * y        bind a variable
+  fn f(x, y) = x + y   define a function
+  if x > 0 then x else -x   a conditional, only one branch is evaluated
+operators, loosest first:
+  ||   &&   !   == != < <= > >=   + -   * / // %   unary -   ^ (also **, right associative)
+  n! (factorial); && and || stop as soon as they know the answer
+commands:
+  :ast <statement>     show the syntax tree
+  :tree <expr>         draw the syntax tree
+  :dot <expr>          the syntax tree as Graphviz DOT (pipe it to dot -Tsvg)
+  :tokens <input>      show what the lexer produced
+  :print <expr>        print it back: minimal parens, all parens and as an s-expression
+  :json <expr>         the syntax tree as a JSON document
+  :read <document>     read a tree back from JSON or an s-expression, print and evaluate it
+  :recover <statement> parse past the errors: every diagnostic and the tree with <error> in it
+  :as <type> <expr>    evaluate with i32, i64, f64 or complex numbers (2i is imaginary),
+                       only + - * / and unary minus
+  :simplify <expr>     fold constants, drop x + 0, x * 1, x * 0, x - x, 2*x + 3*x is 5 * x
+                       (only where that can't change the result, errors included)
+  :derive <var> <expr>
+                       the derivative with respect to var (the others are constants)
+  :rpn <expr>          show the expression in postfix (RPN), via Shunting Yard
+  :conv <from> <to> <text>
+                       convert between infix, prefix, postfix and sexpr
+  :trace <expr>        evaluate one step at a time, innermost first, left to right
+  :check <expr>        evaluate through every route (tree, rpn, vm, conversions)
+  :bytecode <expr>     compile for the stack VM and show the disassembly
+  :asm <expr>          compile for a small RISC-V style CPU, show the assembly and run it
+                       (ints and booleans only; / needs :set div trunc or floor)
+  :bench <expr>        time the tree walker against the VM (x goes 0..999)
+  :vars                list variables and functions
+  :ops                 list the operators with their binding power
+  :history             list previous lines
+  :set [name value]    show or change a setting (div, overflow, backend)
+  :help                this text
+  :quit                exit (Ctrl-D works too)";
+
+// name=expr from the command line, ex: basic-parser x=3 y=x*2
+fn bind_arg(arg: &str, env: &mut Environment) -> Result<(), String> {
+    let (name, value) = arg.split_once('=').ok_or_else(|| format!("unknown option {}", arg))?;
+    let value = Parser::new(value, &env.operators)
+        .and_then(|mut parser| parser.parse())
+        .and_then(|expr| expr.eval(env))
+        .map_err(|e| e.render(value))?;
+    env.set(name.trim(), value);
+    Ok(())
+}
+
+fn run_statement(input: &str, env: &mut Environment) {
+    let result = Parser::new(input, &env.operators)
+        .and_then(|mut parser| parser.parse_statement())
+        .and_then(|statement| statement.run(env));
+
+    match result {
+        Ok(Some(value)) => {
+            println!("{}", value);
+            env.set("_", value);
+        }
+        Ok(None) => println!("Defined."),
+        Err(e) => println!("{}", e.render(input)),
+    }
+}
+
+// :command [argument]; returns false when the session should end
+fn run_command(line: &str, env: &mut Environment, editor: &LineEditor) -> bool {
+    let (command, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let arg = arg.trim();
+
+    match command {
+        "ast" => match Parser::new(arg, &env.operators).and_then(|mut p| p.parse_statement()) {
+            // {:#?} indents every level: 5 MB and seconds for 1 + 1 + ... at the depth limit
+            Ok(statement) => println!("{:?}", statement),
+            Err(e) => println!("{}", e.render(arg)),
+        },
+        "tree" | "dot" => match Parser::new(arg, &env.operators).and_then(|mut p| p.parse()) {
+            Ok(tree) if command == "tree" => print!("{}", ascii_tree(&tree.expr)),
+            Ok(tree) => print!("{}", dot(&tree.expr)),
+            Err(e) => println!("{}", e.render(arg)),
+        },
+        "tokens" => match lexer::tokenize(arg, &env.operators.symbols()) {
+            Ok(tokens) => {
+                for lexeme in tokens {
+                    let span = format!("{}..{}", lexeme.span.start, lexeme.span.end);
+                    println!("{:>8}  {}", span, lexeme.token);
+                }
+            }
+            Err(e) => println!("{}", e.render(arg)),
+        },
+        "print" => match Parser::new(arg, &env.operators).and_then(|mut parser| parser.parse()) {
+            Ok(tree) => {
+                println!("{}", tree);
+                println!("{}", tree.expr.parenthesized());
+                println!("{}", tree.expr.sexpr());
+            }
+            Err(e) => println!("{}", e.render(arg)),
+        },
+        "json" => match Parser::new(arg, &env.operators).and_then(|mut parser| parser.parse()) {
+            Ok(tree) => println!("{}", to_json(&tree.expr)),
+            Err(e) => println!("{}", e.render(arg)),
+        },
+        "read" => match read_document(arg, &env.operators) {
+            Ok(tree) => match tree.eval(env) {
+                Ok(value) => println!("{} = {}", tree, value),
+                Err(e) => println!("{}\n{}", tree, e.render(arg)),
+            },
+            Err(e) => println!("{}", e.render(arg)),
+        },
+        "recover" => {
+            let recovered = parse_file(arg, &env.operators);
+            for statement in &recovered.statements {
+                println!("{}", statement);
+            }
+            for e in &recovered.diagnostics {
+                println!("{}", e.render(arg));
+            }
+        }
+        "as" => {
+            // :as complex (1 + 2i) * (3 - 1i)
+            let (scalar, text) = arg.split_once(char::is_whitespace).unwrap_or((arg, ""));
+            let text = text.trim();
+            let result = match scalar {
+                "i32" => calculate_as::<i32>(text, env).map(|value| value.to_string()),
+                "i64" => calculate_as::<i64>(text, env).map(|value| value.to_string()),
+                "f64" => calculate_as::<f64>(text, env).map(|value| value.to_string()),
+                "complex" => calculate_as::<Complex>(text, env).map(|value| value.to_string()),
+                _ => {
+                    println!("usage: :as <i32|i64|f64|complex> <expr>");
+                    return true;
+                }
+            };
+            match result {
+                Ok(value) => println!("{}", value),
+                Err(e) => println!("{}", e.render(text)),
+            }
+        }
+        "simplify" => {
+            let tree = Parser::new(arg, &env.operators).and_then(|mut parser| parser.parse());
+            match tree {
+                Ok(tree) => println!("{}", simplify(&tree, env)),
+                Err(e) => println!("{}", e.render(arg)),
+            }
+        }
+        "derive" => match arg.split_once(char::is_whitespace) {
+            Some((var, text)) => {
+                let text = text.trim();
+                let tree = Parser::new(text, &env.operators).and_then(|mut parser| parser.parse());
+                match tree.and_then(|tree| derive(&tree, var, env)) {
+                    Ok(derivative) => println!("{}", derivative),
+                    Err(e) => println!("{}", e.render(text)),
+                }
+            }
+            None => println!("usage: :derive <var> <expr>"),
+        },
+        "trace" => match Parser::new(arg, &env.operators).and_then(|mut parser| parser.parse()) {
+            Ok(tree) => {
+                let (steps, result) = tree.trace(env);
+                println!("{}", trace_text(&tree, &steps, &result));
+            }
+            Err(e) => println!("{}", e.render(arg)),
+        },
+        "rpn" => match shunting_yard(arg, &env.operators)
+            .and_then(|items| Notation::Postfix.write(&items))
+        {
+            Ok(text) => println!("{}", text),
+            Err(e) => println!("{}", e.render(arg)),
+        },
+        "conv" => {
+            // :conv postfix infix 3 4 + 2 *
+            let mut words = arg.splitn(3, char::is_whitespace);
+            let from = words.next().and_then(Notation::from_name);
+            let to = words.next().and_then(Notation::from_name);
+            match (from, to, words.next()) {
+                (Some(from), Some(to), Some(text)) => {
+                    let text = text.trim();
+                    let operators = &env.operators;
+                    match from.read(text, operators).and_then(|items| to.write(&items)) {
+                        Ok(converted) => println!("{}", converted),
+                        Err(e) => println!("{}", e.render(text)),
+                    }
+                }
+                _ => println!("usage: :conv <from> <to> <text>, infix, prefix, postfix or sexpr"),
+            }
+        }
+        "bytecode" => {
+            let program = Parser::new(arg, &env.operators)
+                .and_then(|mut parser| parser.parse())
+                .and_then(|expr| Program::compile(&expr));
+            match program {
+                Ok(program) => print!("{}", program),
+                Err(e) => println!("{}", e.render(arg)),
+            }
+        }
+        "asm" => {
+            let program = Parser::new(arg, &env.operators)
+                .and_then(|mut parser| parser.parse())
+                .and_then(|expr| Assembly::compile(&expr, env, CPU_REGISTERS));
+            match program {
+                Ok(program) => {
+                    print!("{}", program);
+                    match program.run(env) {
+                        Ok(value) => println!("# a0 = {}", value),
+                        Err(e) => println!("{}", e.render(arg)),
+                    }
+                }
+                Err(e) => println!("{}", e.render(arg)),
+            }
+        }
+        "bench" => {
+            let mut scratch = env.clone();
+            if let Err(e) = bench(arg, &mut scratch, 200_000) {
+                println!("{}", e.render(arg));
+            }
+        }
+        "check" => {
+            let mut scratch = env.clone();
+            print_routes(&all_paths(arg, &mut scratch));
+        }
+        "ops" => {
+            let mut defs: Vec<&OpDef> = env.operators.defs.iter().collect();
+            defs.sort_by_key(|def| def.power);
+            for def in defs {
+                let assoc = match (def.fixity, def.assoc) {
+                    (Fixity::Infix, Assoc::Left) => "left",
+                    (Fixity::Infix, Assoc::Right) => "right",
+                    _ => "",
+                };
+                let (kind, fixity) = (def.kind_name(), def.fixity.name());
+                let symbol = &def.symbol;
+                println!("{:<4} {:<8} power {}  {:<8} {}", symbol, kind, def.power, fixity, assoc);
+            }
+        }
+        "vars" => {
+            let mut vars: Vec<_> = env.scopes[0].iter().collect();
+            vars.sort_by(|a, b| a.0.cmp(b.0));
+            for (name, value) in vars {
+                println!("{} = {}", name, value);
+            }
+            let mut functions: Vec<_> = env.functions.iter().collect();
+            functions.sort_by(|a, b| a.0.cmp(b.0));
+            for (name, function) in functions {
+                println!("fn {}({})", name, function.params.join(", "));
+            }
+        }
+        "history" => {
+            for (i, entry) in editor.history().iter().enumerate() {
+                println!("{:>4}  {}", i + 1, entry);
+            }
+        }
+        "set" => {
+            let mut words = arg.split_whitespace();
+            match (words.next(), words.next()) {
+                (None, _) => println!("{}", env.options.describe()),
+                (Some(name), Some(value)) => {
+                    if let Err(e) = env.options.set(name, value) {
+                        println!("{}", e);
+                    }
+                }
+                (Some(_), None) => println!("usage: :set <setting> <value>"),
+            }
+        }
+        "help" => println!("{}", HELP),
+        "quit" | "q" => return false,
+        _ => println!("unknown command :{} (try :help)", command),
+    }
+    true
+}
+
+fn main() {
+
+    // --div=... alege ce face int / int, --overflow=... ce se intampla cand nu incape in i32
+    let mut options = EvalOptions::default();
+    let mut bindings = Vec::new();
+    let mut drawing = None; // --tree= or --dot=, with the expression to draw
+    let mut batch_input = None; // --batch=file, - (or just --batch) for stdin
+    let mut lint_input = None; // --lint=file, the same
+    let mut format = Format::Csv;
+    for arg in env::args().skip(1) {
+        if let Some(text) = arg.strip_prefix("--tree=") {
+            drawing = Some((ascii_tree as fn(&Expression) -> String, text.to_string()));
+            continue;
+        }
+        if let Some(text) = arg.strip_prefix("--dot=") {
+            drawing = Some((dot, text.to_string()));
+            continue;
+        }
+        if arg == "--batch" {
+            batch_input = Some("-".to_string());
+            continue;
+        }
+        if let Some(path) = arg.strip_prefix("--batch=") {
+            batch_input = Some(path.to_string());
+            continue;
+        }
+        if arg == "--lint" {
+            lint_input = Some("-".to_string());
+            continue;
+        }
+        if let Some(path) = arg.strip_prefix("--lint=") {
+            lint_input = Some(path.to_string());
+            continue;
+        }
+        if let Some(name) = arg.strip_prefix("--format=") {
+            match Format::from_name(name) {
+                Some(chosen) => format = chosen,
+                None => {
+                    eprintln!("unknown format {}\n{}", name, USAGE);
+                    std::process::exit(2);
+                }
+            }
+            continue;
+        }
+        match arg.strip_prefix("--") {
+            Some(setting) => {
+                let (name, value) = setting.split_once('=').unwrap_or((setting, ""));
+                if let Err(e) = options.set(name, value) {
+                    eprintln!("{}\n{}", e, USAGE);
+                    std::process::exit(2);
+                }
+            }
+            None => bindings.push(arg),
+        }
+    }
+
+    let mut environment = Environment::new(options);
+    environment.operators.register_postfix("!", 10, factorial).expect("a valid operator");
+    for arg in &bindings {
+        if let Err(e) = bind_arg(arg, &mut environment) {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    }
+
+    // basic-parser --dot="1 + 2 * 3" | dot -Tsvg > tree.svg, draws it and doesn't start the REPL
+    if let Some((draw, text)) = drawing {
+        match Parser::new(&text, &environment.operators).and_then(|mut parser| parser.parse()) {
+            Ok(tree) => print!("{}", draw(&tree.expr)),
+            Err(e) => {
+                eprintln!("{}", e.render(&text));
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // only parses, so nothing runs and the bindings don't matter; 1 when there was an error
+    if let Some(path) = lint_input {
+        match lint_file(&path, &environment.operators) {
+            Ok((statements, errors)) => {
+                eprintln!("{} statements, {} errors", statements, errors);
+                std::process::exit(if errors == 0 { 0 } else { 1 });
+            }
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    // exits with 1 when a line failed, so a formula set can be checked from a script
+    if let Some(path) = batch_input {
+        match batch_file(&path, format, &mut environment) {
+            Ok((rows, failed)) => {
+                eprintln!("{} lines, {} errors", rows, failed);
+                std::process::exit(if failed == 0 { 0 } else { 1 });
+            }
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(".basic_parser_history"));
+    let mut editor = LineEditor::new(history);
+    if editor.is_interactive() {
+        println!("basic-parser, :help for commands, Ctrl-D to quit");
+    }
+
+    // o eroare afiseaza mesajul si trece la linia urmatoare, nu mai opreste programul
+    loop {
+        let line = match editor.read_line("> ") {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("could not read input: {}", e);
+                break;
+            }
+        };
+        let input = line.trim(); //shadowing
+        if input.is_empty() {
+            continue;
+        }
+        editor.add_history(input);
+
+        match input.strip_prefix(':') {
+            Some(command) => {
+                if !run_command(command, &mut environment, &editor) {
+                    break;
+                }
+            }
+            None => run_statement(input, &mut environment),
+        }
+    }
+
+}
+
+//--------------TESTS---------------
+/*
+rustc --edition 2021 --test basic-parser.rs && ./basic-parser runs them. The cases are tables
+of input and expected output; the random expressions come from a fixed seed, so a failure
+comes back on every run, and a failing test prints everything that disagreed.
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // xorshift, good enough to make up test expressions
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    // a random well-formed infix expression (as text, with random spacing and extra parens)
+    fn random_infix(rng: &mut Rng, depth: u32) -> String {
+        const LEAVES: &[&str] = &["0", "1", "2", "7", "12", "2.5", "0.5", "1e3", "x", "y"];
+        if depth == 0 || rng.below(4) == 0 {
+            return LEAVES[rng.below(LEAVES.len())].to_string();
+        }
+        match rng.below(9) {
+            0 => format!("-{}", random_infix(rng, depth - 1)),
+            6 => format!("~{}", random_infix(rng, depth - 1)),
+            7 => format!("{}{}", random_infix(rng, depth - 1), ["!", "?"][rng.below(2)]),
+            8 => {
+                let cond = random_condition(rng, depth - 1);
+                let then = random_infix(rng, depth - 1);
+                let otherwise = random_infix(rng, depth - 1);
+                format!("if {} then {} else {}", cond, then, otherwise)
+            }
+            1 => format!("({})", random_infix(rng, depth - 1)),
+            2 => {
+                let name = ["abs", "min", "max"][rng.below(3)];
+                let count = if name == "abs" { 1 } else { 1 + rng.below(3) };
+                let args: Vec<String> = (0..count).map(|_| random_infix(rng, depth - 1)).collect();
+                format!("{}({})", name, args.join(", "))
+            }
+            _ => {
+                let op = ["+", "-", "*", "/", "//", "%", "^", "**", "@"][rng.below(9)];
+                let space = if rng.below(2) == 0 { " " } else { "" };
+                let left = random_infix(rng, depth - 1);
+                let right = random_infix(rng, depth - 1);
+                format!("{}{}{}{}{}", left, space, op, space, right)
+            }
+        }
+    }
+
+    // something that comes out true or false (or an error), for && || ! and if
+    fn random_condition(rng: &mut Rng, depth: u32) -> String {
+        if depth == 0 || rng.below(3) == 0 {
+            if rng.below(5) == 0 {
+                return ["true", "false"][rng.below(2)].to_string();
+            }
+            let op = CmpOp::ALL[rng.below(CmpOp::ALL.len())].symbol();
+            let (left, right) = (random_infix(rng, depth / 2), random_infix(rng, depth / 2));
+            return format!("{} {} {}", left, op, right);
+        }
+        match rng.below(4) {
+            0 => format!("!{}", random_condition(rng, depth - 1)),
+            1 => format!("({})", random_condition(rng, depth - 1)),
+            _ => {
+                let op = ["&&", "||"][rng.below(2)];
+                let left = random_condition(rng, depth - 1);
+                format!("{} {} {}", left, op, random_condition(rng, depth - 1))
+            }
+        }
+    }
+
+    // a sum of k * x, x * k, x and k, for the simplifier: like terms to combine, + 0 and * 1 to
+    // drop, and coefficients near the i32 edges so combining them can overflow
+    fn random_terms(rng: &mut Rng, depth: u32) -> String {
+        const COEFFICIENTS: &[&str] = &["0", "1", "2", "3", "-1", "-2", "65536", "1073741824"];
+        let mut text = String::new();
+        for i in 0..2 + rng.below(3) {
+            let k = COEFFICIENTS[rng.below(COEFFICIENTS.len())];
+            let var = ["x", "x", "y", "n"][rng.below(4)];
+            let term = match rng.below(5) {
+                0 => var.to_string(),
+                1 => format!("{} * {}", k, var),
+                2 => format!("{} * {}", var, k),
+                3 if depth > 0 => format!("({}) * {}", random_terms(rng, depth - 1), k),
+                _ => k.to_string(),
+            };
+            if i > 0 {
+                text.push_str([" + ", " - "][rng.below(2)]);
+            }
+            text.push_str(&term);
+        }
+        text
+    }
+
+    // something smooth in x for the derivative checks: no steps, no kinks, no booleans
+    fn random_smooth(rng: &mut Rng, depth: u32) -> String {
+        const LEAVES: &[&str] = &["x", "x", "2", "3", "0.5", "y"];
+        if depth == 0 || rng.below(4) == 0 {
+            return LEAVES[rng.below(LEAVES.len())].to_string();
+        }
+        let operand = |rng: &mut Rng| random_smooth(rng, depth - 1);
+        match rng.below(8) {
+            0 => format!("-{}", operand(rng)),
+            1 => format!("{}({})", ["sqrt", "exp", "ln"][rng.below(3)], operand(rng)),
+            2 => format!("({}) ^ {}", operand(rng), ["2", "3", "-1", "0.5"][rng.below(4)]),
+            3 => format!("pow({}, {})", operand(rng), operand(rng)),
+            _ => {
+                let op = ["+", "-", "*", "/"][rng.below(4)];
+                format!("({}) {} ({})", operand(rng), op, operand(rng))
+            }
+        }
+    }
+
+    // an int expression over x and k that the cpu takes: nothing with floats, and nested deep
+    // enough that four registers run out
+    fn random_integer(rng: &mut Rng, depth: u32) -> String {
+        const LEAVES: &[&str] = &["0", "1", "2", "3", "-7", "x", "k", "2147483647"];
+        if depth == 0 || rng.below(5) == 0 {
+            return LEAVES[rng.below(LEAVES.len())].to_string();
+        }
+        let operand = |rng: &mut Rng| random_integer(rng, depth - 1);
+        match rng.below(8) {
+            0 => format!("-{}", operand(rng)),
+            1 => format!("({}) ^ {}", operand(rng), [0, 1, 2, 3, 5, 31][rng.below(6)]),
+            2 => {
+                let name = ["abs", "min", "max"][rng.below(3)];
+                let count = if name == "abs" { 1 } else { 2 + rng.below(2) };
+                let args: Vec<String> = (0..count).map(|_| operand(rng)).collect();
+                format!("{}({})", name, args.join(", "))
+            }
+            3 => {
+                let cond = random_flag(rng, depth - 1);
+                format!("(if {} then {} else {})", cond, operand(rng), operand(rng))
+            }
+            _ => {
+                let op = ["+", "-", "*", "/", "//", "%"][rng.below(6)];
+                format!("({} {} {})", operand(rng), op, operand(rng))
+            }
+        }
+    }
+
+    // a boolean for random_integer's ifs, p is a boolean variable
+    fn random_flag(rng: &mut Rng, depth: u32) -> String {
+        if depth == 0 || rng.below(5) == 0 {
+            return ["true", "false", "p"][rng.below(3)].to_string();
+        }
+        let flag = |rng: &mut Rng| random_flag(rng, depth - 1);
+        match rng.below(5) {
+            0 => format!("!{}", flag(rng)),
+            1 => {
+                let op = ["&&", "||", "==", "!="][rng.below(4)];
+                format!("({} {} {})", flag(rng), op, flag(rng))
+            }
+            2 => format!("(if {} then {} else {})", flag(rng), flag(rng), flag(rng)),
+            _ => {
+                let op = CmpOp::ALL[rng.below(CmpOp::ALL.len())].symbol();
+                let left = random_integer(rng, depth - 1);
+                format!("({} {} {})", left, op, random_integer(rng, depth - 1))
+            }
+        }
+    }
+
+    // + - * / and unary minus over int literals, what every Scalar can read and calculate; 46341
+    // squared is just past i32::MAX
+    fn random_field(rng: &mut Rng, depth: u32) -> String {
+        const LEAVES: &[&str] = &["0", "1", "2", "3", "7", "46341", "2147483647"];
+        if depth == 0 || rng.below(5) == 0 {
+            return LEAVES[rng.below(LEAVES.len())].to_string();
+        }
+        let operand = |rng: &mut Rng| random_field(rng, depth - 1);
+        match rng.below(6) {
+            0 => format!("-{}", operand(rng)),
+            1 => format!("({})", operand(rng)),
+            _ => {
+                let op = ["+", "-", "*", "/"][rng.below(4)];
+                format!("{} {} {}", operand(rng), op, operand(rng))
+            }
+        }
+    }
+
+    // a tree built directly, not parsed, so it has shapes the parser only makes from parens
+    // (the spans are all default, they don't count for ==)
+    fn random_tree(rng: &mut Rng, depth: u32, operators: &OperatorTable) -> Node {
+        let node = |expr| Node::new(expr, Span::default());
+        if depth == 0 || rng.below(5) == 0 {
+            return node(match rng.below(4) {
+                0 => Expression::Var(["x", "y", "n"][rng.below(3)].to_string()),
+                1 => Expression::Bool(rng.below(2) == 0),
+                2 => Expression::Val(Number::Float([0.5, 2.5, 1e20][rng.below(3)])),
+                _ => Expression::Val(Number::Int(rng.below(100) as i32)),
+            });
+        }
+        let child = |rng: &mut Rng| Box::new(random_tree(rng, depth - 1, operators));
+        match rng.below(6) {
+            0 => {
+                let count = rng.below(4);
+                let args = (0..count).map(|_| *child(rng)).collect();
+                node(Expression::Call(["max", "f"][rng.below(2)].to_string(), args))
+            }
+            1 => node(Expression::If(child(rng), child(rng), child(rng))),
+            _ => {
+                // any operator from the table; unary plus doesn't make a node
+                let defs: Vec<&OpDef> =
+                    operators.defs.iter().filter(|def| !matches!(def.kind, OpKind::Plus)).collect();
+                let def = defs[rng.below(defs.len())];
+                let operands = (0..def.fixity.arity()).map(|_| *child(rng)).collect();
+                def.node(operands, Span::default())
+            }
+        }
+    }
+
+    // same value, or the same kind of error (spans are allowed to differ between routes)
+    fn same_outcome(a: &Result<Value, Error>, b: &Result<Value, Error>) -> bool {
+        match (a, b) {
+            (Ok(x), Ok(y)) => x == y,
+            (Err(x), Err(y)) => std::mem::discriminant(x) == std::mem::discriminant(y),
+            _ => false,
+        }
+    }
+
+    fn self_test_env() -> Environment {
+        let mut env = Environment::new(EvalOptions::default());
+        env.set("x", Value::Num(Number::Int(7)));
+        env.set("y", Value::Num(Number::Float(-2.5)));
+
+        // one of each fixity; @ has the same binding power as unary minus on purpose
+        let operators = &mut env.operators;
+        let twice_minus = |args: &[Number], options: &EvalOptions| {
+            let twice = args[0].add(&args[0], options);
+            twice.and_then(|twice| twice.sub(&args[1], options)).ok_or("overflow".to_string())
+        };
+        let plus_one = |args: &[Number], options: &EvalOptions| {
+            args[0].add(&Number::Int(1), options).ok_or("overflow".to_string())
+        };
+        let square = |args: &[Number], options: &EvalOptions| {
+            args[0].mul(&args[0], options).ok_or("overflow".to_string())
+        };
+        let registered = operators
+            .register_postfix("!", 10, factorial)
+            .and(operators.register_infix("@", NEG_PRECEDENCE, Assoc::Right, twice_minus))
+            .and(operators.register_prefix("~", 6, plus_one))
+            .and(operators.register_postfix("?", 5, square));
+        registered.expect("valid operators");
+        env
+    }
+
+    // every overflow mode at the i32 edges, expected results for checked, wrap, saturate, promote
+    const OVERFLOW_CASES: &[(&str, [&str; 4])] = &[
+        ("-2147483648", ["-2147483648", "-2147483648", "-2147483648", "-2147483648"]),
+        ("-2147483647 - 1", ["-2147483648", "-2147483648", "-2147483648", "-2147483648"]),
+        // the minus is only part of the literal when nothing binds tighter
+        ("-2147483648 ^ 2", ["overflow", "0", "-2147483647", "-4611686018427387904"]),
+        ("2 - 2147483648", ["overflow", "-2147483646", "-2147483645", "-2147483646"]),
+        ("2147483647 + 1", ["overflow", "-2147483648", "2147483647", "2147483648"]),
+        ("-2147483647 - 2", ["overflow", "2147483647", "-2147483648", "-2147483649"]),
+        ("2147483647 + 1 - 1", ["overflow", "2147483647", "2147483646", "2147483647"]),
+        ("65536 * 65536", ["overflow", "0", "2147483647", "4294967296"]),
+        ("-65536 * 65536", ["overflow", "0", "-2147483648", "-4294967296"]),
+        ("-2147483648 / -1", ["overflow", "-2147483648", "2147483647", "2147483648"]),
+        ("--2147483648", ["overflow", "-2147483648", "2147483647", "2147483648"]),
+        ("abs(-2147483648)", ["overflow", "-2147483648", "2147483647", "2147483648"]),
+        ("gcd(-2147483648, 0)", ["overflow", "-2147483648", "2147483647", "2147483648"]),
+        ("pow(2, 31)", ["overflow", "-2147483648", "2147483647", "2147483648"]),
+        ("pow(2, 64)", ["overflow", "0", "2147483647", "overflow"]),
+        ("pow(-2, 201)", ["overflow", "0", "-2147483648", "overflow"]),
+        // the exponent doesn't fit in an i32 (in checked mode that's already the overflow), and
+        // for wrap and saturate it's wrapped or clamped before pow sees it
+        ("pow(1, 5000000000)", ["overflow", "1", "1", "1"]),
+        ("pow(-1, 4294967297) + pow(0, 4294967298)", ["overflow", "-1", "-1", "-1"]),
+        ("pow(3, 4294967297)", ["overflow", "3", "2147483647", "overflow"]),
+        ("pow(3, 1073741825) + pow(-6, 2147483647)", ["overflow", "3", "-1", "overflow"]),
+        ("pow(-3, 2147483647)", ["overflow", "1431655765", "-2147483648", "overflow"]),
+        (
+            "2147483647 * 2147483647 * 2147483647",
+            ["overflow", "2147483647", "2147483647", "overflow"],
+        ),
+    ];
+
+    // precedence and associativity, where every route agreeing isn't enough
+    const OPERATOR_CASES: &[(&str, &str)] = &[
+        ("-2 ^ 2", "-4"),
+        ("-2 ** 2", "-4"),
+        ("(-2) ^ 2", "4"),
+        ("2 ^ 3 ^ 2", "512"),
+        ("(2 ^ 3) ^ 2", "64"),
+        ("2 ^ -1", "0.5"),
+        ("2 * 3 ^ 2", "18"),
+        ("10 - 2 ^ 3 % 5", "7"),
+        ("2 ^ 3 // 3", "2"),
+        ("-7 // 2", "-4"),
+        ("7 // -2", "-4"),
+        ("-7 % 3", "2"),
+        ("7 % -3", "-2"),
+        ("7.5 // 2", "3.0"),
+        ("-7.5 % 2", "0.5"),
+        ("-7 // 2 * 2 + -7 % 2", "-7"),
+        ("7 % 0", "division by zero"),
+        ("0 ^ -1", "division by zero"),
+        // the custom operators from self_test_env
+        ("3!!", "720"),
+        ("-3!", "-6"),
+        ("2 ^ 3!", "64"),
+        ("x! / 7!", "1"),
+        ("1 @ 2 @ 3", "1"),
+        ("-2 @ 3", "-7"),
+        ("-(2 @ 3)", "-1"),
+        ("~2 * 3", "9"),
+        ("~(2 * 3)", "7"),
+        ("1 + 2?", "9"),
+        ("2 * 3? + 1", "37"),
+        ("2.5!", "factorial needs an integer between 0 and 1000"),
+    ];
+
+    // comparisons, && || ! and if, with the type errors and what short-circuiting saves us from
+    const LOGIC_CASES: &[(&str, &str)] = &[
+        ("1 < 2 && 2 < 3", "true"),
+        ("x > 10 || x == 7", "true"),
+        ("!(x > 3)", "false"),
+        ("!x > 3", "false"),
+        ("!!true", "true"),
+        ("1 == 1.0", "true"),
+        ("0.5 >= 1 / 2", "true"),
+        ("5 != 5", "false"),
+        ("true == false", "false"),
+        ("x! > 5000 && y < 0", "true"),
+        ("1 + 2 * 3 == 7 && 2 ^ 3 >= 8", "true"),
+        ("if x > 5 then x * 2 else x", "14"),
+        ("if y > 0 then 1 else if y < -2 then 2 else 3", "2"),
+        ("1 + if true then 1 else 2", "2"),
+        ("(if false then 1 else 2) * 3", "6"),
+        ("if x > 0 then x else 0 + 1", "7"),
+        ("max(if x > 0 then x else 0, 3)", "7"),
+        ("false && 1 / 0 == 0", "false"),
+        ("true || z", "true"),
+        ("if true then 1 else 1 / 0", "1"),
+        ("if x < 0 then z else x", "7"),
+        ("1 / 0 == 0 && false", "division by zero"),
+        ("true + 1", "type error: expected a number, found a boolean"),
+        ("-true", "type error: expected a number, found a boolean"),
+        ("abs(1 < 2)", "type error: expected a number, found a boolean"),
+        ("1 && true", "type error: expected a boolean, found a number"),
+        ("true && 1", "type error: expected a boolean, found a number"),
+        ("!1", "type error: expected a boolean, found a number"),
+        ("if 1 then 2 else 3", "type error: expected a boolean, found a number"),
+        ("1 < 2 < 3", "type error: expected a number, found a boolean"),
+        ("true == 1", "type error: expected a boolean, found a number"),
+        ("1 == true", "type error: expected a number, found a boolean"),
+        ("true < false", "type error: expected a number, found a boolean"),
+    ];
+
+    // machine, bigint, rational
+    const BACKEND_CASES: &[(&str, [&str; 3])] = &[
+        ("1/3 + 1/6", ["0.5", "0.5", "1/2"]),
+        ("0.1 + 0.2", ["0.30000000000000004", "0.30000000000000004", "3/10"]),
+        ("-7 / 2", ["-3.5", "-3.5", "-7/2"]),
+        ("floor(-7 / 2) + ceil(7 / 2)", ["0", "0", "0"]),
+        ("(2 / 3) * 1.5", ["1.0", "1.0", "1"]),
+        ("pow(2 / 3, -2)", ["2.2500000000000004", "2.2500000000000004", "9/4"]),
+        ("pow(2, -1)", ["0.5", "0.5", "1/2"]),
+        ("max(1 / 3, 0.3)", ["0.3333333333333333", "0.3333333333333333", "1/3"]),
+        ("2147483647 + 1", ["overflow", "2147483648", "2147483648"]),
+        ("pow(2, 64) - pow(2, 64) + 1", ["overflow", "1", "1"]),
+        ("pow(2, 200) / pow(2, 199)", ["overflow", "2", "2"]),
+        ("gcd(pow(2, 100), pow(6, 50))", ["overflow", "1125899906842624", "1125899906842624"]),
+        ("-pow(2, 127) / -1 - pow(2, 127) + 1", ["overflow", "1", "1"]),
+        (
+            "100000000000000000000 * 3",
+            ["overflow", "300000000000000000000", "300000000000000000000"],
+        ),
+        (
+            "pow(2, 100) / 3",
+            ["overflow", "4.2255020007607644e29", "1267650600228229401496703205376/3"],
+        ),
+        (
+            "pow(7, 300) % pow(3, 150) == pow(7, 300) - pow(7, 300) // pow(3, 150) * pow(3, 150)",
+            ["overflow", "true", "true"],
+        ),
+        ("1e400 / 1e399", ["overflow", "overflow", "10"]),
+        ("2.5e-330 * 4e330", ["overflow", "overflow", "10"]),
+        ("-1.5e-400 ^ 2 * 4e800", ["overflow", "overflow", "-9"]),
+        ("1e-400 == 0", ["true", "true", "false"]),
+        ("1e-99999 + 1e4000 // 1e3999", ["overflow", "overflow", "10"]),
+        ("1e99999", ["overflow", "overflow", "overflow"]),
+        ("pow(9, 9999999)", ["overflow", "overflow", "overflow"]),
+        ("pow(-1, 9999999999) + pow(0, 9999999998)", ["overflow", "-1", "-1"]),
+        ("1 / (1 / 3 - 1 / 3)", ["division by zero", "division by zero", "division by zero"]),
+    ];
+
+    // what :simplify prints, with x = 7 and y = -2.5 (n isn't bound); the ones that stay are where
+    // the shorter form could come out different
+    const SIMPLIFY_CASES: &[(&str, &str)] = &[
+        ("1 + 2 * 3", "7"),
+        ("x + 0", "x"),
+        ("0 + x * 1", "x"),
+        ("x - 0", "x"),
+        ("x / 1", "x"),
+        ("x * 0", "0"),
+        ("x - x", "0"),
+        ("y - y", "0.0"),
+        ("y + 0", "y + 0"),
+        ("y * 0", "0 * y"),
+        ("n * 0", "0 * n"),
+        ("(x + 1) * 0", "0 * (x + 1)"),
+        ("1 / 0 * 0", "0 * (1 / 0)"),
+        ("3 + x", "x + 3"),
+        ("x * 4", "4 * x"),
+        ("2 * x + 3 * x", "5 * x"),
+        ("x * 2 + x", "3 * x"),
+        ("2 * x - -3 * x", "5 * x"),
+        ("2 * x - 3 * x", "2 * x - 3 * x"),
+        ("x + x + x - x", "3 * x - x"),
+        ("2147483647 + 1 - 1", "1 + 2147483647 - 1"),
+        ("max(1, 2) + x", "x + 2"),
+        ("if 1 < 2 then x else 1 / 0", "x"),
+        ("if x > 1 then y else y", "y"),
+        ("true && x > 1", "x > 1"),
+        ("false && 1 / 0 > 0", "false"),
+        ("x > 1 || true", "true"),
+        ("!!(x > 1)", "x > 1"),
+        ("!!x", "!!x"),
+    ];
+
+    // what :derive x prints, with x = 7 and y = -2.5
+    const DERIVATIVE_CASES: &[(&str, &str)] = &[
+        ("x ^ 2", "2 * x"),
+        ("3 * x + 1", "3"),
+        ("x * x", "2 * x"),
+        ("y * x ^ 3 - x", "3 * x ^ 2 * y - 1"),
+        ("1 / x", "-1 / x ^ 2"),
+        ("x / 2", "0.5"),
+        ("sqrt(x)", "1 / (2 * sqrt(x))"),
+        ("sqrt(x ^ 2 + 1)", "1 / (2 * sqrt(x ^ 2 + 1)) * (2 * x)"),
+        ("2 ^ x", "0.6931471805599453 * 2 ^ x"),
+        ("x ^ x", "x ^ x * (ln(x) + x / x)"),
+        ("exp(2 * x)", "2 * exp(2 * x)"),
+        ("ln(x)", "1 / x"),
+        ("abs(x)", "x / abs(x)"),
+        ("max(x, 3)", "if x >= 3 then 1 else 0"),
+        ("if x > 0 then x ^ 2 else -x", "if x > 0 then 2 * x else -1"),
+        ("y + 1", "0"),
+        ("x % 3", "1"),
+        ("f(x)", "unknown function 'f'"),
+        ("x > 1", "can't differentiate a boolean"),
+        ("gcd(x, 4)", "can't differentiate gcd"),
+        ("x!", "can't differentiate the operator '!'"),
+    ];
+
+    // the whole expression after each step of :trace, with x = 7
+    const TRACE_CASES: &[(&str, &[&str])] = &[
+        ("2 + 3 * 4", &["2 + 12", "14"]),
+        ("(1 + 2) * (3 + 4)", &["3 * (3 + 4)", "3 * 7", "21"]),
+        ("x * 2 - 1", &["7 * 2 - 1", "14 - 1", "13"]),
+        ("2 - -3", &["5"]),
+        ("max(1, 2 + 3) * 2", &["max(1, 5) * 2", "5 * 2", "10"]),
+        ("false && 1 / 0 > 0", &["false"]),
+        ("if x > 5 then x * 2 else 1 / 0", &[
+            "if 7 > 5 then x * 2 else 1 / 0",
+            "if true then x * 2 else 1 / 0",
+            "x * 2",
+            "7 * 2",
+            "14",
+        ]),
+        ("1 + 2 * (3 / 0)", &[]),
+    ];
+
+    // :asm with div=trunc, x = 7, k = -3 and p = true: what the simulator gives (the same as eval
+    // whenever it compiles)
+    const ASM_CASES: &[(&str, &str)] = &[
+        ("x * 2 + 1", "15"),
+        ("x / k", "-2"),
+        ("x // k", "-3"),
+        ("x % k", "-2"),
+        ("-x % 3", "2"),
+        ("k ^ 5 + pow(x, 0)", "-242"),
+        ("2 ^ 31", "overflow"),
+        ("-(-2147483647 - 1)", "overflow"),
+        ("abs(k) + min(x, k, 3) + max(k, 2)", "2"),
+        ("if x > k && !p then 1 else 2", "2"),
+        ("p == (x < k) || x >= 8", "false"),
+        ("x / (k + 3)", "division by zero"),
+        ("false && x / 0 == 1", "false"),
+        ("x * 2.5", "can't compile 2.5 for the cpu"),
+        ("sqrt(x)", "can't compile sqrt for the cpu"),
+        ("x ^ k", "can't compile a power with exponent k for the cpu"),
+        (
+            "if p then x else p",
+            "can't compile an if with a number and a boolean branch for the cpu",
+        ),
+        ("x + p", "type error: expected a number, found a boolean"),
+        ("p && x", "type error: expected a boolean, found a number"),
+        ("z + 1", "unknown variable 'z'"),
+    ];
+
+    // :read, JSON or an S-expression: the tree as infix, or why it couldn't be read
+    const DOCUMENT_CASES: &[(&str, &str)] = &[
+        ("(+ 1 (* 2 x))", "1 + 2 * x"),
+        ("(basic-parser 1 (neg (^ 2 2)))", "-2 ^ 2"),
+        ("(if (&& p (not q)) (max 1 -2/3 2.5) (! 5))", "if p && !q then max(1, -2/3, 2.5) else 5!"),
+        ("(neg/2 (f) neg)", "neg(f(), neg)"),
+        (r#"{"version": 1, "expr": {"node": "number", "big": "-12345678901234567890"}}"#,
+            "-12345678901234567890"),
+        (r#"{"version": 1, "expr": {"node": "call", "name": "max", "args": [], "new": null}}"#,
+            "max()"),
+        (r#"{"version":1,"expr":{"node":"var","name":"x𝑥"}}"#, "x𝑥"),
+        ("(basic-parser 2 1)",
+            "can't read the tree: it's format version 2, this program reads up to 1"),
+        (r#"{"format": "basic-parser", "version": 2, "expr": {"node": "lambda"}}"#,
+            "can't read the tree: it's format version 2, this program reads up to 1"),
+        (r#"{"version": 1, "expr": {"node": "lambda"}}"#,
+            "can't read the tree: unknown node \"lambda\""),
+        (r#"{"version": 1, "expr": {"node": "neg"}}"#, "can't read the tree: no \"operand\" here"),
+        (r#"{"version": 1, "expr": {"node": "binary", "op": "<"}}"#,
+            "can't read the tree: unknown op \"<\""),
+        (r#"{"version": 1, "expr": {"node": "number", "int": 2.5}}"#,
+            "can't read the tree: \"int\" should be an i32, not 2.5"),
+        (r#"{"version": 1, "expr": {"node": "custom", "symbol": "!", "fixity": "postfix",
+            "power": 10, "assoc": "left", "operands": []}}"#,
+            "can't read the tree: postfix ! takes one operand"),
+        (r#"{"version": 1 "expr": 1}"#, "expected ',' or '}', found '\"'"),
+        (
+            r#"{"expr": {"node": "bool", "value": true}}"#,
+            "can't read the tree: no \"version\" here",
+        ),
+        ("(+ 1)", "expected an operand, found ')'"),
+        ("(+ 1 2 3)", "expected ')', found '3'"),
+        ("(1 2)", "expected an operator or a function name, found '1'"),
+        ("(+ 1 (* 2 3)", "unmatched '('"),
+        ("(not true) false", "unexpected 'false' after expression"),
+    ];
+
+    // :as with x = 7 and y = -2.5 (which only f64 and complex can read)
+    const SCALAR_CASES: &[(&str, &str, &str)] = &[
+        ("complex", "(1+2i)*(3-1i)", "5 + 5i"),
+        ("complex", "(10 + 5i) / (1 + 2i)", "4 - 3i"),
+        ("complex", "-2i * 2i", "4"),
+        ("complex", "x * 1i - 0.5", "-0.5 + 7i"),
+        ("complex", "y * (0 - 1i)", "2.5i"),
+        ("complex", "1 / (1i - 1i)", "division by zero"),
+        ("complex", "2 ^ 3", "'^' isn't defined for complex"),
+        ("complex", "x > 1", "'>' isn't defined for complex"),
+        ("complex", "max(1i, 2)", "unknown function 'max'"),
+        ("i32", "7 / -2", "-3"),
+        ("i32", "2147483647 + 1", "overflow"),
+        ("i32", "-2147483647 - 1", "-2147483648"),
+        ("i32", "-2147483648 * 1", "-2147483648"),
+        ("i32", "-2147483648 ^ 2", "overflow"),
+        ("i32", "-2147483648 % -1", "'%' isn't defined for i32"),
+        ("i32", "2.5 * 2", "expected an integer, found number 2.5"),
+        ("i32", "y", "expected an integer, found number -2.5"),
+        ("i32", "n", "unknown variable 'n'"),
+        ("i32", "if x > 3 && true then x * 2 else 0", "14"),
+        ("i64", "2147483647 + 1", "2147483648"),
+        ("i64", "x * 3037000499 * 3037000499", "overflow"),
+        ("f64", "7 / 2 + y", "1"),
+        ("f64", "1 / 0", "division by zero"),
+        ("f64", "3i", "expected a real number, found imaginary number 3i"),
+        ("f64", "1e999 - 1e999", "overflow"),
+        ("f64", "x / y < 0 || 1 / 0 > 1", "true"),
+    ];
+
+    // parse_file: the text, the statements it got (joined by |) and the diagnostics as
+    // "byte offset: message" in the order they came out
+    const RECOVERY_CASES: &[(&str, &str, &str)] = &[
+        ("1 + 2\nlet x = 3", "1 + 2 | let x = 3", ""),
+        ("# only a comment\n\n   \n", "", ""),
+        ("1 + (2 * ) + 3", "1 + <error> + 3", "9: expected a number, a variable, '(' or 'if', \
+    found ')'"),
+        ("(1 + (2 *) + 3) * 4", "(1 + <error> + 3) * 4", "9: expected a number, a variable, \
+    '(' or 'if', found ')'"),
+        ("f(1, 2 3, 4)", "f(1, <error>, 4)", "7: expected ')' or ',', found number 3"),
+        ("max(1, , 3)", "max(1, <error>, 3)", "7: expected a number, a variable, '(' or 'if', \
+    found ','"),
+        ("1 $ 2 + 3", "<error> + 3", "2: invalid character '$'"),
+        ("2 * §", "2 * <error>", "4: invalid character '§'"),
+        ("1 + (3", "1 + <error>", "4: unmatched '('"),
+        ("1 + 2) * 3", "1 + 2", "5: unmatched ')'"),
+        ("1 2", "1", "2: unexpected number 2 after expression"),
+        ("let = 4\nfn f(x = x", "<error> | <error>", "4: expected a variable name, found '=' | \
+    15: expected ')' or ',', found '='"),
+        ("let y = 1e99999 + #\nf(,) #\n(7", "let y = <error> | f(<error>, <error>) | <error>", "\
+    8: arithmetic overflow | 17: expected a number, a variable, '(' or 'if', found end of input | \
+    22: expected a number, a variable, '(' or 'if', found ',' | 23: expected a number, a variable, \
+    '(' or 'if', found ')' | 27: unmatched '('"),
+    ];
+
+    // a file for --batch: comments, a blank line, a definition, lines that fail (one isn't even
+    // UTF-8) and _ from the last line that had a value; the tables are with the times zeroed
+    const BATCH_INPUT: &[u8] = b"# prices\nlet rate = 3\n1 + 2 * rate   # seven\n\n\
+    fn twice(a) = 2 * a\nrate / 0\n\"q\",1\n_ + 1\n1 +\xff 2\r\n";
+
+    const BATCH_CSV: &str = "\
+line,input,value,error,time_us
+2,let rate = 3,3,,0.0
+3,1 + 2 * rate,7,,0.0
+5,fn twice(a) = 2 * a,,,0.0
+6,rate / 0,,division by zero,0.0
+7,\"\"\"q\"\",1\",,\"invalid character '\"\"'\",0.0
+8,_ + 1,8,,0.0
+9,1 +\u{fffd} 2,,invalid character '\u{fffd}',0.0
+";
+
+    const BATCH_JSON: &str = r#"[
+  {"line": 2, "input": "let rate = 3", "value": "3", "error": null, "time_us": 0.0},
+  {"line": 3, "input": "1 + 2 * rate", "value": "7", "error": null, "time_us": 0.0},
+  {"line": 5, "input": "fn twice(a) = 2 * a", "value": null, "error": null, "time_us": 0.0},
+  {"line": 6, "input": "rate / 0", "value": null, "error": "division by zero", "time_us": 0.0},
+  {"line": 7, "input": "\"q\",1", "value": null, "error": "invalid character '\"'", "time_us": 0.0},
+  {"line": 8, "input": "_ + 1", "value": "8", "error": null, "time_us": 0.0},
+  {"line": 9, "input": "1 +� 2", "value": null, "error": "invalid character '�'", "time_us": 0.0}
+]
+"#;
+
+    fn outcome_text(result: &Result<Value, Error>) -> String {
+        match result {
+            Ok(value) => value.to_string(),
+            Err(Error::Overflow(_)) => "overflow".to_string(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    // the random inputs most tests share; a fixed seed, so a failure comes back every run
+    fn random_inputs() -> Vec<String> {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        (0..500).map(|_| random_infix(&mut rng, 4)).collect()
+    }
+
+    fn random_trees(operators: &OperatorTable) -> Vec<Node> {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        (0..300).map(|_| random_tree(&mut rng, 5, operators)).collect()
+    }
+
+    fn parse(input: &str, operators: &OperatorTable) -> Result<Node, Error> {
+        Parser::new(input, operators).and_then(|mut parser| parser.parse())
+    }
+
+    // every route agrees on the fixed cases and the random ones, for every backend
+    #[test]
+    fn every_route_agrees() {
+        const CASES: &[&str] = &[
+            "2 + 3 * 4", "(2 + 3) * 4", "2 * (3 + 4)", "1 - 2 - 3", "1 - (2 - 3)", "8 / 4 / 2",
+            "-3 * 4", "2 - -5", "--4", "-(2 + 3) * 2", "7 / 2", "1 / 0", "2147483647 + 1",
+            "max(1, x, y)", "abs(-x) * min(3, 2)", "x * y - 1e3 / 0.5", "max(abs(y), (x))",
+            ")", ") + 1", "()", "1 + ()", "(1))", "max()",
+        ];
+        let mut env = self_test_env();
+        let random = random_inputs();
+        let mut failures = 0;
+        for backend in Backend::ALL {
+            env.options.backend = backend;
+            for input in CASES.iter().copied().chain(random.iter().map(String::as_str)) {
+                let results = all_paths(input, &mut env);
+                if results.iter().all(|(_, result)| same_outcome(result, &results[0].1)) {
+                    continue;
+                }
+                failures += 1;
+                println!("mismatch for {} with backend={}", input, backend.name());
+                print_routes(&results);
+            }
+        }
+        assert_eq!(failures, 0);
+    }
+
+    // grouping at any depth, and an unbalanced bracket is reported where it is
+    #[test]
+    fn parentheses() {
+        let mut env = self_test_env();
+        let mut failures = 0;
+        for (input, expected) in [
+            ("2 * (3 + 4)", "14"),
+            ("(2 * 3) + 4", "10"),
+            ("((((1))))", "1"),
+            ("(1 + (2 * (3 + 4))) * 2", "30"),
+            ("10 - (2 - (3 - 4))", "7"),
+            ("(x)", "7"),
+        ] {
+            failures += !check_routes(input, expected, &mut env) as usize;
+        }
+        assert_eq!(failures, 0);
+
+        for (input, paren, at) in [
+            ("2 * (3 + 4", '(', 4),
+            ("((1)", '(', 0),
+            ("(1 + (2)", '(', 0),
+            ("2 * 3) + 4", ')', 5),
+            ("(1))", ')', 3),
+        ] {
+            match parse(input, &env.operators) {
+                Err(Error::UnmatchedParen(found, span)) => {
+                    assert_eq!((found, span.start), (paren, at), "{}", input)
+                }
+                other => panic!("{} gives {:?}", input, other),
+            }
+        }
+    }
+
+    // every stage gives an error with the bytes it's about instead of stopping the program, and
+    // render puts carets under them (columns are chars, not bytes)
+    #[test]
+    fn error_spans() {
+        let operators = OperatorTable::standard();
+        let mut env = Environment::new(EvalOptions::default());
+        for (input, message, start, end) in [
+            ("1 + * 2", "expected a number, a variable, '(' or 'if', found '*'", 4, 5),
+            ("1 +", "expected a number, a variable, '(' or 'if', found end of input", 3, 3),
+            ("1 2 3", "unexpected number 2 after expression", 2, 5),
+            ("2 $ 3", "invalid character '$'", 2, 3),
+            ("6 / (2 - 2)", "division by zero", 4, 11),
+            ("1e999", "arithmetic overflow", 0, 5),
+            ("2147483647 + 1", "arithmetic overflow", 0, 14),
+            ("-2147483648 / -1", "arithmetic overflow", 0, 16),
+        ] {
+            let tree = parse(input, &operators);
+            let e = tree.as_ref().map_err(Error::clone).and_then(|tree| tree.eval(&mut env));
+            let e = e.unwrap_err();
+            assert_eq!((e.to_string(), e.span()), (message.to_string(), Span::new(start, end)));
+            // the VM and the assembly point at the same bytes: the operation, or the divisor
+            // when it's zero
+            if let Ok(tree) = tree {
+                let e = Vm::new().run(&Program::compile(&tree).unwrap(), &mut env).unwrap_err();
+                assert_eq!((e.to_string(), e.span()), (message.to_string(), Span::new(start, end)));
+                let cpu_env = cpu_env(); //div=trunc, div=true nu se compileaza
+                let asm = Assembly::compile(&tree, &cpu_env, CPU_REGISTERS);
+                let e = asm.and_then(|asm| asm.run(&cpu_env)).unwrap_err();
+                assert_eq!((e.to_string(), e.span()), (message.to_string(), Span::new(start, end)));
+            }
+        }
+
+        let e = parse("6 / (2 - 2)", &operators).and_then(|tree| tree.eval(&mut env));
+        let rendered = "error: division by zero\n --> 1:5\n  |\n1 | 6 / (2 - 2)\n  |     ^^^^^^^";
+        assert_eq!(e.unwrap_err().render("6 / (2 - 2)"), rendered);
+        let source = "1 + 1\nx § 2";
+        let e = lexer::tokenize(source, &operators.symbols()).unwrap_err();
+        let rendered = "error: invalid character '§'\n --> 2:3\n  |\n2 | x § 2\n  |   ^";
+        assert_eq!(e.render(source), rendered);
+    }
+
+    // the lexer by itself: the tokens with their spans, always ending in an Eof where the input
+    // does, and the first character it can't read as an error
+    #[test]
+    fn lexer() {
+        let operators = OperatorTable::standard();
+        let symbols = operators.symbols();
+        let lexemes = |input| {
+            let tokens = lexer::tokenize(input, &symbols).unwrap();
+            tokens.into_iter().map(|l| (l.token, l.span.start, l.span.end)).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            lexemes("max(x1, 2.5e3) - _y"),
+            [
+                (Token::Ident("max"), 0, 3),
+                (Token::LParen, 3, 4),
+                (Token::Ident("x1"), 4, 6),
+                (Token::Comma, 6, 7),
+                (Token::Number(Number::Float(2500.0)), 8, 13),
+                (Token::RParen, 13, 14),
+                (Token::Op("-"), 15, 16),
+                (Token::Ident("_y"), 17, 19),
+                (Token::Eof, 19, 19),
+            ]
+        );
+        // 2e and 2in are a number and a name, 3i is imaginary
+        assert_eq!(
+            lexemes("7 2147483648 .5 1E-3 2e 2in 3i"),
+            [
+                (Token::Number(Number::Int(7)), 0, 1),
+                (Token::Number(Number::Big(BigInt::parse("2147483648"))), 2, 12),
+                (Token::Number(Number::Float(0.5)), 13, 15),
+                (Token::Number(Number::Float(0.001)), 16, 20),
+                (Token::Number(Number::Int(2)), 21, 22),
+                (Token::Ident("e"), 22, 23),
+                (Token::Number(Number::Int(2)), 24, 25),
+                (Token::Ident("in"), 25, 27),
+                (Token::Imaginary("3i"), 28, 30),
+                (Token::Eof, 30, 30),
+            ]
+        );
+        // the longest operator that fits, spaces or not
+        assert_eq!(
+            lexemes("2**3//4<=5&&!x"),
+            [
+                (Token::Number(Number::Int(2)), 0, 1),
+                (Token::Op("**"), 1, 3),
+                (Token::Number(Number::Int(3)), 3, 4),
+                (Token::Op("//"), 4, 6),
+                (Token::Number(Number::Int(4)), 6, 7),
+                (Token::Op("<="), 7, 9),
+                (Token::Number(Number::Int(5)), 9, 10),
+                (Token::Op("&&"), 10, 12),
+                (Token::Op("!"), 12, 13),
+                (Token::Ident("x"), 13, 14),
+                (Token::Eof, 14, 14),
+            ]
+        );
+        assert_eq!(lexemes(""), [(Token::Eof, 0, 0)]);
+        assert_eq!(lexemes("  \t "), [(Token::Eof, 4, 4)]);
+
+        for (input, message, start, end) in [
+            ("2 $ 3", "invalid character '$'", 2, 3),
+            ("x § 2", "invalid character '§'", 2, 4),
+            ("1 + 1e99999", "arithmetic overflow", 4, 11),
+        ] {
+            let e = lexer::tokenize(input, &symbols).unwrap_err();
+            assert_eq!((e.to_string(), e.span()), (message.to_string(), Span::new(start, end)));
+        }
+        // tokenize_lossy goes on after one
+        let (tokens, errors) = lexer::tokenize_lossy("1 $ 2", 0, &symbols);
+        let tokens: Vec<_> = tokens.into_iter().map(|l| (l.token, l.span.start)).collect();
+        let expected = [
+            (Token::Number(Number::Int(1)), 0),
+            (Token::Invalid, 2),
+            (Token::Number(Number::Int(2)), 4),
+            (Token::Eof, 5),
+        ];
+        assert_eq!(tokens, expected);
+        assert_eq!(errors.len(), 1);
+    }
+
+    // unary minus and plus (-2 ^ 2 and the like are in OPERATOR_CASES); the printers put back
+    // only the parens a Neg needs, and a unary plus is gone from the tree
+    #[test]
+    fn unary_operators() {
+        let mut env = self_test_env();
+        let mut failures = 0;
+        for (input, expected) in [
+            ("-3 * 4", "-12"),
+            ("2 - -5", "7"),
+            ("--4", "4"),
+            ("- - 4", "4"),
+            ("-x", "-7"),
+            ("-(2 + 3) * 2", "-10"),
+            ("+3", "3"),
+            ("2 - +3", "-1"),
+            ("-+-1", "1"),
+            ("-", "expected a number, a variable, '(' or 'if', found end of input"),
+        ] {
+            failures += !check_routes(input, expected, &mut env) as usize;
+        }
+        assert_eq!(failures, 0);
+
+        for (input, printed, parenthesized) in [
+            ("2 - -5", "2 - -5", "(2 - (-5))"),
+            ("-2 ^ 2", "-2 ^ 2", "(-(2 ^ 2))"),
+            ("(-2) ^ 2", "(-2) ^ 2", "((-2) ^ 2)"),
+            ("- - 4", "--4", "(-(-4))"),
+            ("-(2 + 3) * x", "-(2 + 3) * x", "((-(2 + 3)) * x)"),
+            ("+x", "x", "x"),
+        ] {
+            let tree = parse(input, &env.operators).unwrap();
+            let found = (tree.to_string(), tree.expr.parenthesized());
+            assert_eq!(found, (printed.to_string(), parenthesized.to_string()), "{}", input);
+        }
+    }
+
+    // decimal and scientific literals, ints that stay exact, and int / int in each division
+    // mode (true, trunc, floor); a float anywhere makes it real division
+    #[test]
+    fn literals_and_division() {
+        let mut env = self_test_env();
+        let mut failures = 0;
+        for (input, expected) in [
+            ("7 / 2", ["3.5", "3", "3"]),
+            ("-7 / 2", ["-3.5", "-3", "-4"]),
+            ("7 / -2", ["-3.5", "-3", "-4"]),
+            ("6 / 2", ["3", "3", "3"]),
+            ("1 / 3", ["0.3333333333333333", "0", "0"]),
+            ("7.0 / 2", ["3.5", "3.5", "3.5"]),
+            ("1.5 * 2", ["3.0", "3.0", "3.0"]),
+            ("6.02e23 / 2", ["3.01e23", "3.01e23", "3.01e23"]),
+            (".5 + 1", ["1.5", "1.5", "1.5"]),
+            ("1E3", ["1000.0", "1000.0", "1000.0"]),
+            ("2.5e-3 * 4", ["0.01", "0.01", "0.01"]),
+            ("2 + 3", ["5", "5", "5"]),
+        ] {
+            for (mode, expected) in DivisionMode::ALL.iter().copied().zip(expected) {
+                env.options.division = mode;
+                if !check_routes(input, expected, &mut env) {
+                    failures += 1;
+                    println!("    with div={}", mode.name());
+                }
+            }
+        }
+        assert_eq!(failures, 0);
+    }
+
+    fn run(line: &str, env: &mut Environment) -> Result<Option<Value>, Error> {
+        let statement = Parser::new(line, &env.operators)?.parse_statement()?;
+        statement.run(env)
+    }
+
+    // let binds in the innermost scope, a parsed formula is evaluated again with other values,
+    // and a name nothing bound is an error over that name
+    #[test]
+    fn variables() {
+        let mut env = Environment::new(EvalOptions::default());
+        let int = |n| Some(Value::Num(Number::Int(n)));
+        assert_eq!(run("let y = 3", &mut env).unwrap(), int(3));
+        assert_eq!(run("let x = 3 * y", &mut env).unwrap(), int(9));
+        assert_eq!(run("let y = y + 1", &mut env).unwrap(), int(4));
+        assert_eq!(run("x + y", &mut env).unwrap(), int(13));
+
+        let formula = parse("2 * r + 1", &env.operators).unwrap();
+        for r in [1, 2, 3] {
+            env.set("r", Value::Num(Number::Int(r)));
+            assert_eq!(formula.eval(&mut env).ok(), int(2 * r + 1));
+        }
+
+        match run("1 + nope", &mut env) {
+            Err(Error::UnknownVariable(name, span)) => {
+                assert_eq!((name.as_str(), span), ("nope", Span::new(4, 8)))
+            }
+            other => panic!("1 + nope gives {:?}", other),
+        }
+        let e = run("let if = 1", &mut env).unwrap_err();
+        assert_eq!(e.to_string(), "expected a variable name, found identifier 'if'");
+
+        // a scope on top shadows the globals and takes its bindings with it when it goes
+        env.scopes.push(HashMap::new());
+        env.set("y", Value::Bool(true));
+        assert_eq!(env.get("y"), Some(Value::Bool(true)));
+        assert_eq!(env.get("x"), int(9));
+        env.scopes.pop();
+        assert_eq!(env.get("y"), int(4));
+    }
+
+    // the built-ins, then user functions in the order they're typed in: arity, recursion up to
+    // max_depth (or less with a deep body), and a body that only sees its parameters and the
+    // globals
+    #[test]
+    fn functions() {
+        with_main_stack(|| {
+            let mut env = Environment::new(EvalOptions::default());
+            let mut failures = 0;
+            for (line, expected) in [
+                ("abs(-3)", "3"),
+                ("min(4, 2, 8)", "2"),
+                ("max(1, 2.5)", "2.5"),
+                ("pow(2, 10)", "1024"),
+                ("sqrt(16)", "4.0"),
+                ("gcd(12, 18)", "6"),
+                ("floor(2.7)", "2"),
+                ("ceil(-2.5)", "-2"),
+                ("sqrt(-1)", "sqrt of a negative number"),
+                ("abs(1, 2)", "'abs' takes 1 argument but got 2"),
+                ("max()", "'max' takes at least 1 argument but got 0"),
+                ("nope(1)", "unknown function 'nope'"),
+                ("fn f(x, y) = x*x + y", "defined"),
+                ("f(3, 1)", "10"),
+                ("f(1)", "'f' takes 2 arguments but got 1"),
+                ("fn fact(n) = if n < 2 then 1 else n * fact(n - 1)", "defined"),
+                ("fact(10)", "3628800"),
+                ("fn count(n) = if n < 1 then 0 else 1 + count(n - 1)", "defined"),
+                ("count(255)", "255"),
+                ("fn forever(n) = forever(n + 1)", "defined"),
+                ("forever(0)", "recursion limit reached while calling 'forever'"),
+                ("let x = 1", "1"),
+                ("fn g(y) = h()", "defined"),
+                ("fn h() = x + y", "defined"),
+                ("g(5)", "unknown variable 'y'"),
+                ("fn abs(x) = 42", "defined"),
+                ("abs(-1)", "42"),
+            ] {
+                let found = match run(line, &mut env) {
+                    Ok(Some(value)) => value.to_string(),
+                    Ok(None) => "defined".to_string(),
+                    Err(e) => e.to_string(),
+                };
+                if found != expected {
+                    failures += 1;
+                    println!("{}: expected {}, got {}", line, expected, found);
+                }
+            }
+            assert_eq!(failures, 0);
+
+            // a body 120 levels deep runs out of MAX_EVAL_DEPTH long before max_depth calls
+            let body = format!("{}deep(n - 1){}", "1 + (".repeat(120), ")".repeat(120));
+            run(&format!("fn deep(n) = if n < 1 then 0 else {}", body), &mut env).unwrap();
+            assert_eq!(run("deep(2)", &mut env).unwrap().unwrap().to_string(), "240");
+            let e = run("deep(255)", &mut env).unwrap_err();
+            assert_eq!(e.to_string(), "recursion limit reached while calling 'deep'");
+            assert_eq!(env.depth, 0);
+        });
+    }
+
+    fn type_in(editor: &mut LineEditor, keys: &[u8]) -> Option<String> {
+        editor.edit_from(&mut &keys[..], &mut io::sink(), "> ").expect("a line or the end")
+    }
+
+    // the REPL's line editor with keys typed in, and its history: no blank lines or repeats,
+    // saved as it grows and read back by the next session
+    #[test]
+    fn line_editing() {
+        let path = env::temp_dir().join(format!("basic_parser_history_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut editor = LineEditor::new(Some(path.clone()));
+        for line in ["1 + 2", "1 + 2", "", "let x = 3"] {
+            editor.add_history(line);
+        }
+        assert_eq!(editor.history(), ["1 + 2", "let x = 3"]);
+
+        for (keys, line) in [
+            (&b"2 * 3\r"[..], Some("2 * 3")),
+            (b"13\x1b[D\x1b[D(\x05)\r", Some("(13)")), // left, left, Ctrl-E
+            (b"ab\x7fc\r", Some("ac")),                // backspace
+            (b"ab\x01\x1b[3~\r", Some("b")),            // Ctrl-A, Delete
+            (b"junk\x03ok\r", Some("ok")),             // Ctrl-C starts over
+            (b"\x1b[A\r", Some("let x = 3")),          // up, the last entry
+            (b"\x1b[A\x1b[A\x1b[B\r", Some("let x = 3")),
+            (b"1 +\x1b[A\x1b[B\r", Some("1 +")),       // down again, back to the draft
+            ("§1\r".as_bytes(), Some("§1")),
+            (b"\x04", None), // Ctrl-D on an empty line
+            (b"", None),
+        ] {
+            assert_eq!(type_in(&mut editor, keys).as_deref(), line, "{:?}", keys);
+        }
+        assert_eq!(LineEditor::new(Some(path.clone())).history(), editor.history());
+        std::fs::remove_file(&path).expect("the history file");
+    }
+
+    // _ is the last value the REPL printed, a line that fails leaves it alone
+    #[test]
+    fn last_result() {
+        let mut env = Environment::new(EvalOptions::default());
+        let editor = LineEditor::new(None);
+        for (line, last) in [("1 + 2", 3), ("_ * 2", 6), ("1 / 0", 6), ("fn f(x) = x", 6)] {
+            run_statement(line, &mut env);
+            assert_eq!(env.get("_"), Some(Value::Num(Number::Int(last))), "{}", line);
+        }
+        assert!(run_command("vars", &mut env, &editor));
+        assert!(run_command("nonsense", &mut env, &editor));
+        assert!(!run_command("quit", &mut env, &editor));
+    }
+
+    // a test thread has 2 MB of stack and the program runs on the main thread's 8 MB; a debug
+    // build needs more than 2 MB for max_depth nested calls and takes about 7 KB a level for
+    // if ... then, so those tests and the trees near MAX_DEPTH get 8 MB here too
+    fn with_main_stack(test: fn()) {
+        let thread = std::thread::Builder::new().stack_size(8 << 20).spawn(test);
+        if let Err(panic) = thread.expect("a thread").join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+
+    // as deep as the limit is fine, one level more is a TooDeep error where it went over
+    #[test]
+    fn nesting_limit() {
+        with_main_stack(|| {
+            let operators = OperatorTable::standard();
+            let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+            let mut env = Environment::new(EvalOptions::default());
+            let deepest = parse(&nested(MAX_DEPTH - 1), &operators).unwrap();
+            assert_eq!(deepest.eval(&mut env).unwrap(), Value::Num(Number::Int(1)));
+            assert!(parse(&format!("{}1", "-".repeat(MAX_DEPTH - 1)), &operators).is_ok());
+
+            let too_deep = [
+                (nested(MAX_DEPTH), MAX_DEPTH),
+                (nested(10_000), MAX_DEPTH),
+                (format!("{}1", "-".repeat(10_000)), MAX_DEPTH),
+                (format!("{}1", "2 ^ ".repeat(10_000)), 4 * MAX_DEPTH),
+                (format!("{}1", "max(0, ".repeat(10_000)), 7 * (MAX_DEPTH - 1) + 4),
+                (format!("{}1", "if true then ".repeat(10_000)), 13 * (MAX_DEPTH - 1) + 3),
+            ];
+            for (input, at) in &too_deep {
+                match parse(input, &operators) {
+                    Err(Error::TooDeep(span)) => assert_eq!(span.start, *at, "{:.20}", input),
+                    other => panic!("{:.20}... gives {:?}", input, other.map(|_| ())),
+                }
+            }
+        });
+    }
+
+    // 1 + 1 + ... doesn't go deeper in the parser, only in the tree; a tree the parser lets
+    // through evaluates, one level deeper is TooDeep
+    #[test]
+    fn deep_trees() {
+        with_main_stack(|| {
+            let mut operators = OperatorTable::standard();
+            operators.register_postfix("!", 10, factorial).expect("a valid operator");
+            let mut env = Environment::new(EvalOptions::default());
+            let chain = |first: &str, then: &str, terms: usize| {
+                format!("{}{}", first, then.repeat(terms - 1))
+            };
+            let sum = parse(&chain("1", " + 1", MAX_DEPTH), &operators).unwrap();
+            assert_eq!(sum.eval(&mut env).unwrap(), Value::Num(Number::Int(MAX_DEPTH as i32)));
+            let all = parse(&chain("true", " && true", MAX_DEPTH), &operators).unwrap();
+            assert_eq!(all.eval(&mut env).unwrap(), Value::Bool(true));
+
+            // the operator that puts it one level over
+            for (first, then, terms) in [
+                ("1", " + 1", MAX_DEPTH + 1),
+                ("1", " + 1", 1000),
+                ("1", " * 1", 50_000),
+                ("true", " && true", 50_000),
+                ("x", " - x", 50_000),
+                ("3", "!", 50_000),
+            ] {
+                let input = chain(first, then, terms);
+                let operator = then.trim_start();
+                let at = first.len() + then.len() * MAX_DEPTH - operator.len();
+                match parse(&input, &operators) {
+                    Err(Error::TooDeep(span)) => assert_eq!(span.start, at, "{:.20}", input),
+                    other => panic!("{:.20}... gives {:?}", input, other.map(|_| ())),
+                }
+            }
+        });
+    }
+
+    // the parser and Shunting Yard stop at the same token, with the same message, also one level
+    // past MAX_DEPTH
+    #[test]
+    fn stray_parens() {
+        with_main_stack(|| {
+            let operators = OperatorTable::standard();
+            let deep = [
+                format!("{}1", "(".repeat(MAX_DEPTH + 1)),
+                format!("{}1", "-".repeat(MAX_DEPTH + 1)),
+                format!("{}1", "2 ^ ".repeat(MAX_DEPTH + 1)),
+                format!("{}1", "1 + ".repeat(MAX_DEPTH + 1)),
+                format!("{}1", "max(0, ".repeat(MAX_DEPTH + 1)),
+                format!("{}1", "if true then ".repeat(MAX_DEPTH + 1)),
+            ];
+            let stray = [")", ") + 1", "()", "1 + ()", "(1))", "(1 + 2", "max(1,)"];
+            for input in stray.iter().copied().chain(deep.iter().map(String::as_str)) {
+                let parsed = parse(input, &operators).map(|_| ()).unwrap_err();
+                let queued = shunting_yard(input, &operators).map(|_| ()).unwrap_err();
+                assert_eq!(queued.to_string(), parsed.to_string(), "{:.20}", input);
+                assert_eq!(queued.span(), parsed.span(), "{:.20}", input);
+            }
+
+            // prefix and postfix have no parens, the height of the tree is what's held to it
+            for (notation, word) in [(Notation::Prefix, "+ 1 "), (Notation::Postfix, "1 + ")] {
+                for (ops, fits) in [(MAX_DEPTH - 1, true), (MAX_DEPTH, false)] {
+                    let text = match notation {
+                        Notation::Prefix => format!("{}1", word.repeat(ops)),
+                        _ => format!("1 {}", word.repeat(ops)),
+                    };
+                    match notation.read(&text, &operators) {
+                        Ok(_) => assert!(fits, "{:?} {:.20}", notation, text),
+                        Err(e) => assert!(!fits && matches!(e, Error::TooDeep(_)), "{}", e),
+                    }
+                }
+            }
+        });
+    }
+
+    // all the routes agree and the first one gives expected
+    fn check_routes(input: &str, expected: &str, env: &mut Environment) -> bool {
+        let results = all_paths(input, env);
+        let agree = results.iter().all(|(_, result)| same_outcome(result, &results[0].1));
+        if agree && outcome_text(&results[0].1) == expected {
+            return true;
+        }
+        println!("{}: expected {}", input, expected);
+        print_routes(&results);
+        false
+    }
+
+    #[test]
+    fn overflow_modes() {
+        let mut env = self_test_env();
+        let mut failures = 0;
+        for (input, expected) in OVERFLOW_CASES {
+            for (mode, expected) in OverflowMode::ALL.iter().copied().zip(expected) {
+                env.options.overflow = mode;
+                if !check_routes(input, expected, &mut env) {
+                    failures += 1;
+                    println!("    with overflow={}", mode.name());
+                }
+            }
+        }
+        assert_eq!(failures, 0);
+    }
+
+    #[test]
+    fn operators_and_logic() {
+        let mut env = self_test_env();
+        let mut failures = 0;
+        for (input, expected) in OPERATOR_CASES.iter().chain(LOGIC_CASES) {
+            failures += !check_routes(input, expected, &mut env) as usize;
+        }
+        assert_eq!(failures, 0);
+    }
+
+    #[test]
+    fn backends() {
+        let mut env = self_test_env();
+        let mut failures = 0;
+        for (input, expected) in BACKEND_CASES {
+            for (backend, expected) in Backend::ALL.iter().copied().zip(expected) {
+                env.options.backend = backend;
+                if !check_routes(input, expected, &mut env) {
+                    failures += 1;
+                    println!("    with backend={}", backend.name());
+                }
+            }
+        }
+        assert_eq!(failures, 0);
+    }
+
+    // q * b + r == a with |r| < |b| and r taking a's sign, on numbers with a few limbs each; the
+    // top limbs come out 0 and 0xffffffff often, that's where the guessed quotient limb is off
+    #[test]
+    fn big_division() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let random_big = |rng: &mut Rng| {
+            let mut n = bignum::BigInt::zero();
+            let limb = bignum::BigInt::from_i128(1 << 32);
+            for _ in 0..1 + rng.below(6) {
+                let digit = match rng.below(4) {
+                    0 => 0,
+                    1 => u32::MAX as i128,
+                    _ => rng.next() as u32 as i128,
+                };
+                n = n.mul(&limb).add(&bignum::BigInt::from_i128(digit));
+            }
+            if rng.below(2) == 0 { n.neg() } else { n }
+        };
+        let mut failures = 0;
+        for _ in 0..2000 {
+            let (a, b) = (random_big(&mut rng), random_big(&mut rng));
+            if b.is_zero() {
+                continue;
+            }
+            let (q, r) = a.div_rem(&b);
+            let smaller = r.abs().sub(&b.abs()).is_negative();
+            let sign_ok = r.is_zero() || r.is_negative() == a.is_negative();
+            if q.mul(&b).add(&r) != a || !smaller || !sign_ok {
+                failures += 1;
+                println!("{} / {} gave {} rest {}", a, b, q, r);
+            }
+        }
+        assert_eq!(failures, 0);
+    }
+
+    // printing a tree and parsing the text gives the same tree back, for both infix renderings
+    #[test]
+    fn printed_trees_parse_back() {
+        let env = self_test_env();
+        let mut failures = 0;
+        for tree in &random_trees(&env.operators) {
+            for text in [tree.to_string(), tree.expr.parenthesized()] {
+                if parse(&text, &env.operators).as_ref().ok() == Some(tree) {
+                    continue;
+                }
+                failures += 1;
+                println!("{} doesn't parse back to {}", text, tree.expr.sexpr());
+            }
+        }
+        assert_eq!(failures, 0);
+    }
+
+    // written out and read back, the same tree: every random one, then each kind of number
+    // (a Wide in an S-expression is digits, so it reads back as the lexer's Big)
+    #[test]
+    fn documents_round_trip() {
+        let env = self_test_env();
+        let numbers = [
+            Number::Int(-5),
+            Number::Int(i32::MIN),
+            Number::Wide(1 << 40),
+            Number::Big(BigInt::parse("1000000000000000000000000000000").neg()),
+            Number::Ratio(Rational::new(BigInt::from_i128(-1), BigInt::from_i128(3))),
+            Number::Float(-0.5),
+        ];
+        let literals =
+            numbers.iter().map(|n| Node::new(Expression::Val(n.clone()), Span::default()));
+        let mut failures = 0;
+        for tree in random_trees(&env.operators).into_iter().chain(literals) {
+            let json = to_json(&tree.expr);
+            if read_json(&json).ok().as_ref() != Some(&tree) {
+                failures += 1;
+                println!("{} doesn't read back to {}", json, tree.expr.sexpr());
+            }
+            let text = tree.expr.sexpr();
+            let wide = matches!(tree.expr, Expression::Val(Number::Wide(_)));
+            if wide || read_sexpr(&text, &env.operators).ok().as_ref() == Some(&tree) {
+                continue;
+            }
+            failures += 1;
+            println!("{} doesn't read back to the same tree", text);
+        }
+        for (input, expected) in DOCUMENT_CASES {
+            let found = match read_document(input, &env.operators) {
+                Ok(tree) => tree.to_string(),
+                Err(e) => e.to_string(),
+            };
+            if found == *expected {
+                continue;
+            }
+            failures += 1;
+            println!(":read {} gives {}, expected {}", input, found, expected);
+        }
+        assert_eq!(failures, 0);
+    }
+
+    // :read stops where the parser would: the deepest tree it makes reads back, deeper is TooDeep
+    #[test]
+    fn deep_documents() {
+        with_main_stack(|| {
+            let operators = OperatorTable::standard();
+            let calls = |depth: usize| format!("{}1{}", "f(".repeat(depth), ")".repeat(depth));
+            let deepest = parse(&calls(MAX_DEPTH - 1), &operators).unwrap();
+            assert_eq!(read_json(&to_json(&deepest.expr)).unwrap().expr, deepest.expr);
+            assert_eq!(read_sexpr(&deepest.expr.sexpr(), &operators).unwrap().expr, deepest.expr);
+
+            let json_negs = |depth: usize| {
+                let neg = r#"{"node": "neg", "operand": "#;
+                let leaf = r#"{"node": "number", "int": 1}"#;
+                let tree = format!("{}{}{}", neg.repeat(depth), leaf, "}".repeat(depth));
+                format!(r#"{{"version": 1, "expr": {}}}"#, tree)
+            };
+            let too_deep = [
+                format!("{}1{}", "(neg ".repeat(10_000), ")".repeat(10_000)),
+                format!("{}1{}", "(f ".repeat(MAX_DEPTH + 1), ")".repeat(MAX_DEPTH + 1)),
+                json_negs(10_000),
+                json_negs(MAX_DEPTH),
+                format!(r#"{{"version": 1, "expr": {}]}}"#, "[".repeat(10_000)),
+            ];
+            for input in &too_deep {
+                match read_document(input, &operators) {
+                    Err(Error::TooDeep(_)) => {}
+                    other => panic!("{:.20}... gives {:?}", input, other.map(|_| ())),
+                }
+            }
+        });
+    }
+
+    // one line per node in the drawing, one edge less than that in the dot file
+    #[test]
+    fn tree_views() {
+        let env = self_test_env();
+        let expected = [
+            "if",
+            "├── cond: >",
+            "│   ├── x",
+            "│   └── 0",
+            "├── then: +",
+            "│   ├── 2",
+            "│   └── *",
+            "│       ├── 3",
+            "│       └── 4",
+            "└── else: neg",
+            "    └── x",
+        ];
+        let expected: String = expected.iter().map(|line| format!("{}\n", line)).collect();
+        let tree = parse("if x > 0 then 2 + 3 * 4 else -x", &env.operators).unwrap();
+        assert_eq!(ascii_tree(&tree.expr), expected);
+
+        for tree in &random_trees(&env.operators) {
+            let mut items = Vec::new();
+            tree.postfix(&mut items);
+            let graph = dot(&tree.expr);
+            let edges = graph.lines().filter(|line| line.contains("->")).count();
+            let nodes =
+                graph.lines().filter(|line| line.contains("[label=") && !line.contains("->"));
+            assert_eq!(ascii_tree(&tree.expr).lines().count(), items.len(), "{}", tree);
+            assert_eq!(nodes.count(), items.len(), "{}", tree);
+            assert_eq!(edges + 1, items.len(), "{}", tree);
+        }
+    }
+
+    #[test]
+    fn tracer() {
+        let mut env = self_test_env();
+        for (input, expected) in TRACE_CASES {
+            let tree = parse(input, &env.operators).unwrap();
+            let steps = tree.trace(&mut env).0;
+            let wholes: Vec<String> = steps.iter().map(|step| step.whole.to_string()).collect();
+            assert_eq!(wholes, *expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn simplifier() {
+        let mut env = self_test_env();
+        for (input, expected) in SIMPLIFY_CASES {
+            let tree = parse(input, &env.operators).unwrap();
+            assert_eq!(simplify(&tree, &mut env).to_string(), *expected, "{}", input);
+        }
+    }
+
+    // the simplifier against plain eval, in every setting; the tree is simplified once for x an
+    // int and y a float and then has to agree for all of these values
+    #[test]
+    fn simplifier_keeps_the_value() {
+        const BINDINGS: &[(i32, f64)] =
+            &[(7, -2.5), (0, -0.0), (-1, 1e300), (i32::MAX, 0.5), (i32::MIN, -1e-300)];
+        let mut scratch = self_test_env();
+        let mut rng = Rng(0xd1b5_4a32_d192_ed03);
+        let sums: Vec<String> = (0..500).map(|_| random_terms(&mut rng, 1)).collect();
+        let parsed = random_inputs()
+            .iter()
+            .chain(&sums)
+            .filter_map(|input| parse(input, &scratch.operators).ok())
+            .collect::<Vec<_>>();
+        let simplifiable: Vec<Node> =
+            parsed.into_iter().chain(random_trees(&scratch.operators)).collect();
+        let mut failures = 0;
+        for (backend, overflow) in Backend::ALL.iter().copied().flat_map(|backend| {
+            OverflowMode::ALL.iter().copied().map(move |overflow| (backend, overflow))
+        }) {
+            scratch.options = EvalOptions { backend, overflow, ..EvalOptions::default() };
+            for tree in &simplifiable {
+                let simpler = simplify(tree, &mut scratch);
+                for &(x, y) in BINDINGS {
+                    scratch.set("x", Value::Num(Number::Int(x)));
+                    scratch.set("y", Value::Num(Number::Float(y)));
+                    let (before, after) = (tree.eval(&mut scratch), simpler.eval(&mut scratch));
+                    if same_outcome(&before, &after) {
+                        continue;
+                    }
+                    failures += 1;
+                    let (backend, overflow) = (backend.name(), overflow.name());
+                    let (before, after) = (outcome_text(&before), outcome_text(&after));
+                    println!("{} simplified to {} with x = {}, y = {:?}", tree, simpler, x, y);
+                    println!("    backend={} overflow={}", backend, overflow);
+                    println!("    {} before, {} after", before, after);
+                }
+                scratch.set("x", Value::Num(Number::Int(7)));
+                scratch.set("y", Value::Num(Number::Float(-2.5)));
+            }
+        }
+        assert_eq!(failures, 0);
+    }
+
+    #[test]
+    fn derivatives() {
+        let mut env = self_test_env();
+        for (input, expected) in DERIVATIVE_CASES {
+            let tree = parse(input, &env.operators).unwrap();
+            let derivative = derive(&tree, "x", &mut env);
+            let got = derivative.map_or_else(|e| e.to_string(), |d| d.to_string());
+            assert_eq!(got, *expected, "d/dx {}", input);
+        }
+    }
+
+    // the derivative against a central difference, at a few points
+    #[test]
+    fn derivatives_match_differences() {
+        let mut scratch = self_test_env();
+        let mut rng = Rng(0x94d0_49bb_1331_11eb);
+        let (mut failures, mut compared) = (0, 0);
+        for _ in 0..300 {
+            let input = random_smooth(&mut rng, 4);
+            scratch.set("x", Value::Num(Number::Float(1.0))); // x is a float for the simplifier
+            let tree = parse(&input, &scratch.operators).expect("random_smooth makes valid input");
+            let derivative = match derive(&tree, "x", &mut scratch) {
+                Ok(derivative) => derivative,
+                Err(e) => {
+                    failures += 1;
+                    println!("d/dx {}: {}", input, e);
+                    continue;
+                }
+            };
+            for x in [0.7, 1.3, 2.9] {
+                let h = 1e-5;
+                let mut at = |node: &Node, x: f64| {
+                    scratch.set("x", Value::Num(Number::Float(x)));
+                    match node.eval(&mut scratch) {
+                        Ok(Value::Num(n)) if n.to_f64().abs() < 1e4 => Some(n.to_f64()),
+                        _ => None,
+                    }
+                };
+                let (Some(above), Some(below), Some(slope)) =
+                    (at(&tree, x + h), at(&tree, x - h), at(&derivative, x))
+                else {
+                    continue;
+                };
+                compared += 1;
+                let estimate = (above - below) / (2.0 * h);
+                if (slope - estimate).abs() <= 1e-5 * (1.0 + slope.abs()) {
+                    continue;
+                }
+                failures += 1;
+                println!("d/dx {} = {} is {} at x = {}", input, derivative, slope, x);
+                println!("    a central difference says {}", estimate);
+            }
+        }
+        assert_eq!(failures, 0);
+        assert!(compared > 0, "no derivative was compared");
+    }
+
+    fn cpu_env() -> Environment {
+        let mut cpu_env = Environment::new(EvalOptions::default());
+        cpu_env.options.division = DivisionMode::Truncate;
+        cpu_env.set("x", Value::Num(Number::Int(7)));
+        cpu_env.set("k", Value::Num(Number::Int(-3)));
+        cpu_env.set("p", Value::Bool(true));
+        cpu_env
+    }
+
+    #[test]
+    fn assembly() {
+        let mut cpu_env = cpu_env();
+        for (input, expected) in ASM_CASES {
+            let tree = parse(input, &cpu_env.operators);
+            let compiled = tree.and_then(|tree| {
+                let program = Assembly::compile(&tree, &cpu_env, CPU_REGISTERS)?;
+                Ok((program.run(&cpu_env), tree.eval(&mut cpu_env)))
+            });
+            let simulated = match compiled {
+                Ok((simulated, evaluated)) => {
+                    assert!(same_outcome(&simulated, &evaluated), ":asm {}", input);
+                    simulated
+                }
+                Err(e) => Err(e),
+            };
+            assert_eq!(outcome_text(&simulated), *expected, ":asm {}", input);
+        }
+
+        let expected = [
+            "# 7 instructions, 0 spill slots",
+            "    lw    t0, x",
+            "    li    t1, 2",
+            "    mul   t0, t0, t1",
+            "    li    t1, 1",
+            "    add   t0, t0, t1",
+            "    mv    a0, t0",
+            "    ret",
+        ];
+        let expected: String = expected.iter().map(|line| format!("{}\n", line)).collect();
+        let tree = parse("x * 2 + 1", &cpu_env.operators).unwrap();
+        let program = Assembly::compile(&tree, &cpu_env, CPU_REGISTERS).unwrap();
+        assert_eq!(program.to_string(), expected);
+    }
+
+    // the cpu against eval: compiled once per setting, with all the registers and with as few
+    // as it can do with (so there are spills), then run for every binding
+    #[test]
+    fn cpu_agrees_with_eval() {
+        const CPU_BINDINGS: &[(i32, i32)] =
+            &[(7, -3), (0, 0), (i32::MAX, -1), (i32::MIN, 2), (-5, 13)];
+        let mut cpu_env = cpu_env();
+        let mut rng = Rng(0xbf58_476d_1ce4_e5b9);
+        let integers: Vec<Node> = (0..300)
+            .map(|_| {
+                let input = random_integer(&mut rng, 5);
+                parse(&input, &cpu_env.operators).expect("random_integer makes valid input")
+            })
+            .collect();
+        let (mut failures, mut spilled) = (0, 0);
+        let overflows = [OverflowMode::Checked, OverflowMode::Wrapping, OverflowMode::Saturating];
+        for division in [DivisionMode::Truncate, DivisionMode::Floor] {
+            for overflow in overflows {
+                cpu_env.options = EvalOptions { division, overflow, ..EvalOptions::default() };
+                for registers in [MIN_CPU_REGISTERS, CPU_REGISTERS] {
+                    for tree in &integers {
+                        let program = match Assembly::compile(tree, &cpu_env, registers) {
+                            Ok(program) => program,
+                            Err(e) => {
+                                failures += 1;
+                                println!(":asm {}: {}", tree, e);
+                                continue;
+                            }
+                        };
+                        if program.frame > 0 {
+                            spilled += 1;
+                        }
+                        for &(x, k) in CPU_BINDINGS {
+                            cpu_env.set("x", Value::Num(Number::Int(x)));
+                            cpu_env.set("k", Value::Num(Number::Int(k)));
+                            cpu_env.set("p", Value::Bool(x > k));
+                            let simulated = program.run(&cpu_env);
+                            let evaluated = tree.eval(&mut cpu_env);
+                            if same_outcome(&simulated, &evaluated) {
+                                continue;
+                            }
+                            failures += 1;
+                            println!(":asm {} with x = {}, k = {}", tree, x, k);
+                            let (div, overflow) = (division.name(), overflow.name());
+                            let setting = format!("div={} overflow={}", div, overflow);
+                            println!("    {}, {} registers", setting, registers);
+                            let (cpu, eval) = (outcome_text(&simulated), outcome_text(&evaluated));
+                            println!("    {} on the cpu, {} from eval", cpu, eval);
+                        }
+                    }
+                }
+            }
+        }
+        assert_eq!(failures, 0);
+        assert!(spilled > 0, "nothing needed the stack with {} registers", MIN_CPU_REGISTERS);
+    }
+
+    fn scalar_text<T: fmt::Display>(result: Result<Value<T>, Error>) -> String {
+        match result {
+            Ok(value) => value.to_string(),
+            Err(Error::Overflow(_)) => "overflow".to_string(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn scalars() {
+        let mut env = self_test_env();
+        for (scalar, input, expected) in SCALAR_CASES {
+            let found = match *scalar {
+                "i32" => scalar_text(calculate_as::<i32>(input, &mut env)),
+                "i64" => scalar_text(calculate_as::<i64>(input, &mut env)),
+                "f64" => scalar_text(calculate_as::<f64>(input, &mut env)),
+                _ => scalar_text(calculate_as::<Complex>(input, &mut env)),
+            };
+            assert_eq!(found, *expected, ":as {} {}", scalar, input);
+        }
+    }
+
+    // a Value<i32> or Value<i64> the way eval would have it
+    fn as_number<T>(value: Value<T>, number: impl Fn(T) -> Number) -> Value {
+        match value {
+            Value::Num(n) => Value::Num(number(n)),
+            Value::Bool(b) => Value::Bool(b),
+        }
+    }
+
+    // the same text through the generic parser for each Scalar: i32 has to agree with Number
+    // under overflow=checked div=trunc, i64 with overflow=promote, and complex with f64
+    #[test]
+    fn scalars_agree_with_number() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let operators = OperatorTable::standard();
+        let mut failures = 0;
+        for _ in 0..500 {
+            let input = random_field(&mut rng, 5);
+            let mut number_env = Environment::new(EvalOptions {
+                division: DivisionMode::Truncate,
+                ..EvalOptions::default()
+            });
+            let mut env = Environment::new(EvalOptions::default());
+            let by_number = |env: &mut Environment| parse(&input, &operators)?.eval(env);
+            let small = calculate_as::<i32>(&input, &mut env)
+                .map(|value| as_number(value, Number::Int));
+            let checked = by_number(&mut number_env);
+            number_env.options.overflow = OverflowMode::Promote;
+            let wide = calculate_as::<i64>(&input, &mut env).map(|value| {
+                as_number(value, |n| i32::try_from(n).map_or(Number::Wide(n), Number::Int))
+            });
+            let promoted = by_number(&mut number_env);
+            let real = calculate_as::<f64>(&input, &mut env);
+            let complex = calculate_as::<Complex>(&input, &mut env);
+            let same_real = match (&real, &complex) {
+                (Ok(Value::Num(x)), Ok(Value::Num(z))) => z.real == *x && z.imaginary == 0.0,
+                (Err(a), Err(b)) => std::mem::discriminant(a) == std::mem::discriminant(b),
+                _ => false,
+            };
+            if same_outcome(&small, &checked) && same_outcome(&wide, &promoted) && same_real {
+                continue;
+            }
+            failures += 1;
+            println!("the scalars disagree on {}", input);
+            println!("    i32 {}, number {}", outcome_text(&small), outcome_text(&checked));
+            println!("    i64 {}, number {}", outcome_text(&wide), outcome_text(&promoted));
+            println!("    f64 {}, complex {}", scalar_text(real), scalar_text(complex));
+        }
+        assert_eq!(failures, 0);
+    }
+
+    #[test]
+    fn recovery() {
+        let env = self_test_env();
+        for (text, statements, diagnostics) in RECOVERY_CASES {
+            let recovered = parse_file(text, &env.operators);
+            let found: Vec<String> = recovered.statements.iter().map(|s| s.to_string()).collect();
+            let errors: Vec<String> = recovered
+                .diagnostics
+                .iter()
+                .map(|e| format!("{}: {}", e.span().start, e))
+                .collect();
+            assert_eq!(found.join(" | "), *statements, "parse_file {:?}", text);
+            assert_eq!(errors.join(" | "), *diagnostics, "parse_file {:?}", text);
+        }
+    }
+
+    // a line too deep is one diagnostic, the lines around it are parsed as usual
+    #[test]
+    fn recovery_of_deep_lines() {
+        let parens = format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000));
+        let sum = format!("1{}", " + 1".repeat(50_000));
+        let text = format!("{}\n1 + 2\n{}\n", parens, sum);
+        let recovered = parse_file(&text, &OperatorTable::standard());
+        let found: Vec<String> = recovered.statements.iter().map(|s| s.to_string()).collect();
+        let errors: Vec<String> = recovered
+            .diagnostics
+            .iter()
+            .map(|e| format!("{}: {}", e.span().start, e))
+            .collect();
+        assert_eq!(found.join(" | "), "<error> | 1 + 2 | <error>");
+        // where it went over: the '(' past MAX_DEPTH, the '+' past it
+        let plus = parens.len() + "\n1 + 2\n".len() + " + 1".len() * (MAX_DEPTH - 1) + 2;
+        let too_deep = format!("nesting too deep, the limit is {} levels", MAX_DEPTH);
+        let expected = [format!("{}: {}", MAX_DEPTH, too_deep), format!("{}: {}", plus, too_deep)];
+        assert_eq!(errors, expected);
+    }
+
+    // a random mistake in a good line: the error the parser stops at is one of the
+    // diagnostics (a file line has no trailing spaces, so its span may end sooner), and
+    // without the mistake the recovered statement is the one the parser makes
+    #[test]
+    fn recovery_agrees_with_the_parser() {
+        const MISTAKES: &[&str] = &["$", ")", "(", ",", "*", "1e99999", " 7 ", "let", "if"];
+        let env = self_test_env();
+        let mut rng = Rng(0x6a09_e667_f3bc_c909);
+        let mut failures = 0;
+        for input in &random_inputs() {
+            let mut broken = input.clone();
+            let at = (0..=input.len()).filter(|&i| input.is_char_boundary(i)).collect::<Vec<_>>();
+            broken.insert_str(at[rng.below(at.len())], MISTAKES[rng.below(MISTAKES.len())]);
+            for (text, clean) in [(input, true), (&broken, false)] {
+                let recovered = parse_file(text, &env.operators);
+                let parsed =
+                    Parser::new(text, &env.operators).and_then(|mut p| p.parse_statement());
+                let agrees = match parsed {
+                    Ok(statement) => {
+                        let same = match recovered.statements.as_slice() {
+                            [found] => format!("{:?}", found) == format!("{:?}", statement),
+                            _ => false,
+                        };
+                        same && recovered.diagnostics.is_empty()
+                    }
+                    Err(e) => !clean && recovered.diagnostics.iter().any(|found| {
+                        found.to_string() == e.to_string() && found.span().start == e.span().start
+                    }),
+                };
+                if agrees {
+                    continue;
+                }
+                failures += 1;
+                println!("parse_file doesn't agree with the parser on {}", text);
+            }
+        }
+        assert_eq!(failures, 0);
+    }
+
+    #[test]
+    fn batch_tables() {
+        for (format, expected) in [(Format::Csv, BATCH_CSV), (Format::Json, BATCH_JSON)] {
+            let mut batch_env = Environment::new(EvalOptions::default());
+            let mut table = format.header().to_string();
+            let mut first = true;
+            let counts = batch(BATCH_INPUT, &mut batch_env, |row| {
+                table.push_str(&format.row(&Row { time: Duration::ZERO, ..row.clone() }, first));
+                first = false;
+                Ok(())
+            });
+            table.push_str(format.footer());
+            assert_eq!(counts.ok(), Some((7, 3)));
+            assert_eq!(table, expected, "--batch --format={}", format.name());
+        }
+    }
+
+    // a line too deep to parse is one failed row, the lines after it still run
+    #[test]
+    fn batch_deep_lines() {
+        let parens = format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000));
+        let sum = format!("1{}", " + 1".repeat(50_000));
+        let input = format!("1 + 2\n{}\n{}\n_ * 4\n", parens, sum);
+        let mut batch_env = Environment::new(EvalOptions::default());
+        let mut outcomes = Vec::new();
+        let counts = batch(input.as_bytes(), &mut batch_env, |row| {
+            outcomes.push((row.line, row.outcome.clone()));
+            Ok(())
+        });
+        assert_eq!(counts.ok(), Some((4, 2)));
+        assert!(matches!(outcomes[1], (2, Err(Error::TooDeep(_)))), "{:?}", outcomes[1]);
+        assert!(matches!(outcomes[2], (3, Err(Error::TooDeep(_)))), "{:?}", outcomes[2]);
+        let last = Some(Value::Num(Number::Int(12)));
+        assert_eq!(outcomes[3].1.as_ref().ok(), Some(&last));
+    }
+}